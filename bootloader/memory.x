@@ -0,0 +1,21 @@
+MEMORY
+{
+  /* STM32F411CE, has to match the flash layout in kb_driver's update.rs */
+  FLASH            : ORIGIN = 0x08000000, LENGTH = 32K
+  BOOTLOADER_STATE : ORIGIN = 0x08010000, LENGTH = 64K
+  ACTIVE           : ORIGIN = 0x08020000, LENGTH = 128K
+  DFU              : ORIGIN = 0x08040000, LENGTH = 256K
+  RAM              : ORIGIN = 0x20000000, LENGTH = 127K
+  /* kept out of RAM so the bootloader can't clobber the firmware's crash log
+     and reset bookkeeping, has to match kb_driver's memory.x */
+  NOINIT           : ORIGIN = 0x2001FC00, LENGTH = 1K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(FLASH);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(FLASH);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
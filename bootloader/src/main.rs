@@ -0,0 +1,50 @@
+//! Two-slot bootloader for the adapter, see `kb_driver/src/update.rs` for how
+//! the firmware feeds it
+//!
+//! Every boot it checks whether the firmware asked for an update to be swapped
+//! in, or whether the last update never marked itself booted and has to be
+//! swapped back out, then jumps to whatever is in the active slot. Swapping is
+//! resumable, so a reset in the middle of one (the firmware's watchdog keeps
+//! running through it) just picks it up again.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+#[cfg(feature = "defmt")]
+use defmt_rtt as _;
+use embassy_boot_stm32::{BootLoader, BootLoaderConfig};
+use embassy_stm32::flash::{Flash, FLASH_BASE};
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Big enough for the copies to go quickly, small enough for the stack
+const BUFFER_SIZE: usize = 2048;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_stm32::init(Default::default());
+    let flash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader = BootLoader::prepare::<_, _, _, BUFFER_SIZE>(config);
+    unsafe { bootloader.load(FLASH_BASE as u32 + active_offset) }
+}
+
+#[no_mangle]
+#[cfg_attr(target_os = "none", link_section = ".HardFault.user")]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
@@ -7,32 +7,169 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also generates Vial's keyboard definition from the matrix and
+//! compresses it, since Vial downloads it from the keyboard itself, and turns
+//! `keymap.toml` (or whatever TOML or JSON file `PALM_KB_KEYMAP` points to)
+//! into the default config, read the same way `palmkb keymap load` reads them.
 
-// use std::env;
-// use std::fs::File;
-// use std::io::Write;
-// use std::path::PathBuf;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use kb_driver_core::{
+    config::Config,
+    keymap::Action,
+    via::{self, Flavor}
+};
+use palmkb_keymap::{Format, KeymapFile};
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
 
-    // let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    // File::create(out.join("memory.x"))
-    //     .unwrap()
-    //     .write_all(include_bytes!("memory.x"))
-    //     .unwrap();
-    // println!("cargo:rustc-link-search={}", out.display());
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
     // any file in the project changes. By specifying `memory.x`
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
 
-    // println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory.x");
+
+    let mut definition = String::new();
+    via::write_definition(&mut definition, Flavor::Vial).unwrap();
+    let mut compressed = Vec::new();
+    lzma_rs::xz_compress(&mut definition.as_bytes(), &mut compressed).unwrap();
+    File::create(out.join("vial.json.xz"))
+        .unwrap()
+        .write_all(&compressed)
+        .unwrap();
+
+    let keymap = env::var_os("PALM_KB_KEYMAP")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| "keymap.toml".into());
+    println!("cargo:rerun-if-changed={}", keymap.display());
+    println!("cargo:rerun-if-env-changed=PALM_KB_KEYMAP");
+    let config = match read_keymap(&keymap) {
+        Ok(config) => config,
+        Err(e) => panic!("{}: {e:#}", keymap.display())
+    };
+    File::create(out.join("keymap.rs"))
+        .unwrap()
+        .write_all(generate(&config).as_bytes())
+        .unwrap();
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     #[cfg(feature = "defmt")]
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+/// Reads a keymap file, as TOML unless it ends in `.json`
+fn read_keymap(path: &Path) -> anyhow::Result<Config> {
+    let text = fs::read_to_string(path)?;
+    let format = Format::from_path(path).unwrap_or(Format::Toml);
+    KeymapFile::parse(&text, format)?.to_config()
+}
+
+/// The action as a Rust expression
+fn action_expr(action: Action) -> String {
+    match action {
+        Action::None => "Action::None".into(),
+        Action::Transparent => "Action::Transparent".into(),
+        Action::Key(key) => format!("Action::Key(KeyCode::{})", key.name()),
+        Action::Layer(layer) => format!("Action::Layer({layer})"),
+        Action::Macro(index) => format!("Action::Macro({index})"),
+        Action::ModTap(mods, key) => format!(
+            "Action::ModTap(Modifiers::from_bits_truncate({:#04x}), KeyCode::{})",
+            mods.bits(),
+            key.name()
+        ),
+        Action::LayerTap(layer, key) => {
+            format!("Action::LayerTap({layer}, KeyCode::{})", key.name())
+        }
+        Action::Profile(index) => format!("Action::Profile({index})"),
+        Action::Unicode(code_point) => format!("Action::Unicode({code_point:#06x})")
+    }
+}
+
+/// Writes the config out as `DEFAULT_CONFIG`, only the parts that can be set
+/// from `keymap.toml`
+fn generate(config: &Config) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "#[allow(unused_imports)]\n\
+         use kb_driver_core::{\n    \
+             combo::Combo,\n    \
+             config::Config,\n    \
+             key_codes::{KeyCode, Modifiers},\n    \
+             keymap::{Action, Keymap},\n    \
+             power::{IdleAction, Policy},\n    \
+             profile::{ModifierSwaps, Profile, UnicodeMode}\n\
+         };\n\n"
+    );
+    out.push_str("pub const DEFAULT_CONFIG: Config = Config {\n");
+    writeln!(out, "    tapping_term_ms: {},", config.tapping_term_ms).unwrap();
+    writeln!(out, "    detect_host: {},", config.detect_host).unwrap();
+    writeln!(out, "    host_profiles: {:?},", config.host_profiles).unwrap();
+    writeln!(
+        out,
+        "    power: Policy {{\n        \
+         cut_vcc_on_suspend: {},\n        \
+         idle_timeout_mins: {},\n        \
+         idle_action: IdleAction::{:?}\n    \
+         }},",
+        config.power.cut_vcc_on_suspend,
+        config.power.idle_timeout_mins,
+        config.power.idle_action
+    )
+    .unwrap();
+    out.push_str("    profiles: [\n");
+    for profile in &config.profiles {
+        writeln!(
+            out,
+            "        Profile::new(\n            {:?},",
+            profile.name()
+        )
+        .unwrap();
+        out.push_str("            Keymap {\n                layers: [\n");
+        for layer in &profile.keymap.layers {
+            out.push_str("                    [\n");
+            for action in layer {
+                writeln!(out, "                        {},", action_expr(*action))
+                    .unwrap();
+            }
+            out.push_str("                    ],\n");
+        }
+        out.push_str("                ]\n            },\n");
+        writeln!(
+            out,
+            "            ModifierSwaps::from_bits_truncate({:#04x}),\n            \
+             UnicodeMode::{:?},\n            {}\n        ),",
+            profile.swaps.bits(),
+            profile.unicode,
+            profile.apple_fn
+        )
+        .unwrap();
+    }
+    out.push_str("    ],\n    combos: [\n");
+    for combo in &config.combos {
+        writeln!(
+            out,
+            "        Combo {{ keys: {:?}, action: {} }},",
+            combo.keys,
+            action_expr(combo.action)
+        )
+        .unwrap();
+    }
+    out.push_str("    ],\n    ..Config::DEFAULT\n};\n");
+    out
+}
@@ -0,0 +1,22 @@
+MEMORY
+{
+  /* STM32F411CE, the firmware only gets sector 5 of the 512K flash. The
+     bootloader, the config and everything firmware updates need take up the
+     rest, see update.rs */
+  FLASH  : ORIGIN = 0x08020000, LENGTH = 128K
+  RAM    : ORIGIN = 0x20000000, LENGTH = 127K
+  /* what has to survive resets, at a fixed spot so neither a different build
+     nor the bootloader (whose memory.x leaves it alone too) moves or
+     overwrites it. The stack starts at the end of RAM, right below */
+  NOINIT : ORIGIN = 0x2001FC00, LENGTH = 1K
+}
+
+SECTIONS
+{
+  .noinit (NOLOAD) : ALIGN(4)
+  {
+    KEEP(*(.noinit.CRASHLOG));
+    KEEP(*(.noinit.BOOTLOADER));
+    KEEP(*(.noinit.RESTARTS));
+  } > NOINIT
+} INSERT AFTER .uninit;
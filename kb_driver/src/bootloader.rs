@@ -0,0 +1,40 @@
+//! Getting into the STM32's built-in bootloader without touching BOOT0
+//!
+//! The system bootloader can't be jumped to with the clocks and peripherals
+//! already set up, so a request leaves a flag in the NOINIT RAM region and
+//! resets, and [`check`] jumps there first thing on the next boot.
+
+use core::{
+    mem::MaybeUninit,
+    ptr::{addr_of, addr_of_mut}
+};
+
+use cortex_m::peripheral::SCB;
+
+/// Where the F411's system memory (and its vector table) starts
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+const MAGIC: u32 = 0xB007_10AD;
+
+#[link_section = ".noinit.BOOTLOADER"]
+static mut FLAG: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Resets into the system bootloader
+pub fn reboot() -> ! {
+    cortex_m::interrupt::disable();
+    unsafe { addr_of_mut!(FLAG).write(MaybeUninit::new(MAGIC)) };
+    SCB::sys_reset()
+}
+
+/// Jumps to the system bootloader if [`reboot`] asked for it. MUST be called
+/// before anything else in `main`
+pub fn check() {
+    // SAFETY: nothing else is running yet, and any bit pattern is a valid u32
+    let flag = unsafe { (*addr_of!(FLAG)).assume_init() };
+    if flag != MAGIC {
+        return;
+    }
+    unsafe {
+        addr_of_mut!(FLAG).write(MaybeUninit::new(0));
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
@@ -0,0 +1,22 @@
+//! Records every byte from the keyboard's RXD line while the host asks for it,
+//! see [`kb_driver_core::capture`] for what's kept and `palmkb-replay` for
+//! what to do with it
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::Instant;
+use kb_driver_core::capture::Capture;
+
+static CAPTURE: Mutex<ThreadModeRawMutex, RefCell<Capture>> =
+    Mutex::new(RefCell::new(Capture::new()));
+
+/// Takes note of a byte from the keyboard, does nothing unless recording
+pub fn record(byte: u8) {
+    let at_ms = Instant::now().as_millis() as u32;
+    CAPTURE.lock(|c| c.borrow_mut().record(at_ms, byte));
+}
+
+pub fn with<R>(f: impl FnOnce(&mut Capture) -> R) -> R {
+    CAPTURE.lock(|c| f(&mut c.borrow_mut()))
+}
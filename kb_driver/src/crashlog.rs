@@ -0,0 +1,224 @@
+//! Crash log that survives resets
+//!
+//! The log lives in the NOINIT RAM region from `memory.x`, which isn't zeroed
+//! at boot and which the bootloader stays out of. It keeps a small ring buffer
+//! of recent driver events and, if the firmware panics or hard faults, the
+//! panic message and location. After writing those down the MCU resets, and on
+//! the next boot [`init`] picks the record up so it can be read over USB with
+//! [`REQUEST_READ`], the raw HID interface, or just logged.
+
+use core::{
+    cell::Cell, fmt::Write, mem::MaybeUninit, panic::PanicInfo, ptr::addr_of_mut
+};
+
+use cortex_m::{interrupt, peripheral::SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+pub use kb_driver_core::crash_report::{
+    CrashReport, Event, EventKind, EVENT_COUNT, FILE_LEN, MESSAGE_LEN, REPORT_LEN
+};
+
+use crate::error;
+
+/// Vendor control IN request that reads the last crash report, `wIndex` is the
+/// offset to start reading from
+pub const REQUEST_READ: u8 = 0x01;
+/// Vendor control OUT request that clears the last crash report
+pub const REQUEST_CLEAR: u8 = 0x02;
+
+const MAGIC: u32 = 0x504B_4C47;
+const CRASHED: u32 = 0xDEAD_C0DE;
+
+#[link_section = ".noinit.CRASHLOG"]
+static mut LOG: MaybeUninit<Log> = MaybeUninit::uninit();
+
+static LAST_CRASH: Mutex<CriticalSectionRawMutex, Cell<Option<CrashReport>>> =
+    Mutex::new(Cell::new(None));
+
+#[repr(C)]
+struct Log {
+    magic: u32,
+    crashed: u32,
+    next_event: u32,
+    events: [Event; EVENT_COUNT],
+    message: [u8; MESSAGE_LEN],
+    message_len: u8,
+    file: [u8; FILE_LEN],
+    file_len: u8,
+    line: u32,
+    column: u32,
+    checksum: u32
+}
+
+/// [`core::fmt::Write`] into a fixed buffer, silently dropping whatever
+/// doesn't fit
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+impl Log {
+    fn checksum(&self) -> u32 {
+        let mut sum = self.line ^ self.column.rotate_left(16);
+        for b in self.message[..self.message_len as usize]
+            .iter()
+            .chain(&self.file[..self.file_len as usize])
+        {
+            sum = sum.rotate_left(5) ^ *b as u32;
+        }
+        sum
+    }
+
+    fn has_crash(&self) -> bool {
+        self.magic == MAGIC
+            && self.crashed == CRASHED
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.file_len as usize <= FILE_LEN
+            && self.checksum == self.checksum()
+    }
+
+    fn reset(&mut self) {
+        self.magic = MAGIC;
+        self.crashed = 0;
+        self.next_event = 0;
+        self.events = [Event::default(); EVENT_COUNT];
+    }
+
+    fn record_crash(&mut self, location: Option<(&str, u32, u32)>) {
+        if self.magic != MAGIC || self.next_event as usize >= EVENT_COUNT {
+            self.reset();
+        }
+        if let Some((file, line, column)) = location {
+            // the end of the path is the interesting bit
+            let file = &file.as_bytes()[file.len().saturating_sub(FILE_LEN)..];
+            self.file[..file.len()].copy_from_slice(file);
+            self.file_len = file.len() as u8;
+            self.line = line;
+            self.column = column;
+        } else {
+            self.file_len = 0;
+            self.line = 0;
+            self.column = 0;
+        }
+        self.crashed = CRASHED;
+        self.checksum = self.checksum();
+    }
+}
+
+/// # Safety
+/// Must only be called with interrupts disabled or before they're enabled
+unsafe fn log() -> &'static mut Log {
+    &mut *(*addr_of_mut!(LOG)).as_mut_ptr()
+}
+
+/// Picks up the crash left behind by the last reset, if there is one, and
+/// starts a fresh log. MUST be called once at boot
+pub fn init(reset_reason: u8) -> Option<CrashReport> {
+    let report = interrupt::free(|_| {
+        let log = unsafe { log() };
+        let report = log.has_crash().then(|| CrashReport::from(&*log));
+        if log.magic != MAGIC || log.next_event as usize >= EVENT_COUNT {
+            log.reset();
+        }
+        log.crashed = 0;
+        report
+    });
+    LAST_CRASH.lock(|c| c.set(report));
+    record(EventKind::Boot, reset_reason);
+
+    if let Some(report) = &report {
+        error!(
+            "recovered from crash at {}:{}:{}: {}",
+            report.file(),
+            report.line,
+            report.column,
+            report.message()
+        );
+    }
+    report
+}
+
+/// Adds an event to the ring buffer
+pub fn record(kind: EventKind, data: u8) {
+    let timestamp_ms = Instant::now().as_millis() as u32;
+    interrupt::free(|_| {
+        let log = unsafe { log() };
+        let i = log.next_event as usize % EVENT_COUNT;
+        log.events[i] = Event {
+            timestamp_ms,
+            kind: kind as u8,
+            data
+        };
+        log.next_event = ((i + 1) % EVENT_COUNT) as u32;
+    })
+}
+
+/// The crash picked up at boot, if there was one
+pub fn last_crash() -> Option<CrashReport> {
+    LAST_CRASH.lock(|c| c.get())
+}
+
+pub fn clear() {
+    LAST_CRASH.lock(|c| c.set(None));
+}
+
+impl From<&Log> for CrashReport {
+    fn from(log: &Log) -> Self {
+        // next_event points at the oldest entry once the buffer has wrapped
+        let events = (0..EVENT_COUNT)
+            .map(|i| log.events[(log.next_event as usize + i) % EVENT_COUNT]);
+        CrashReport::new(
+            &log.message[..log.message_len as usize],
+            &log.file[..log.file_len as usize],
+            log.line,
+            log.column,
+            events
+        )
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    interrupt::disable();
+    let log = unsafe { log() };
+    let mut message = Truncating {
+        buf: &mut log.message,
+        len: 0
+    };
+    let _ = write!(message, "{}", info.message());
+    log.message_len = message.len as u8;
+    log.record_crash(info.location().map(|l| (l.file(), l.line(), l.column())));
+
+    #[cfg(feature = "defmt")]
+    defmt::error!("{}", defmt::Display2Format(info));
+    SCB::sys_reset()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    let log = log();
+    let mut message = Truncating {
+        buf: &mut log.message,
+        len: 0
+    };
+    let _ = write!(
+        message,
+        "HardFault at PC {:#010X}, LR {:#010X}",
+        frame.pc(),
+        frame.lr()
+    );
+    log.message_len = message.len as u8;
+    log.record_crash(None);
+    SCB::sys_reset()
+}
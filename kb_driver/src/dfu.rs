@@ -0,0 +1,142 @@
+//! USB DFU 1.1 runtime interface, so `dfu-util -e` can send the adapter to the
+//! system bootloader without any buttons or probes
+//!
+//! All it does is answer DFU_DETACH by rebooting into the STM32's ROM
+//! bootloader, which is a DFU device of its own and takes the actual download.
+
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::Timer;
+use embassy_usb::{
+    control::{InResponse, OutResponse, Recipient, Request, RequestType},
+    types::InterfaceNumber,
+    Builder, Handler
+};
+
+use crate::{
+    bootloader,
+    host_os::UsbDriver,
+    info,
+    supervisor::{self, Task}
+};
+
+const CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_RUNTIME: u8 = 0x01;
+
+const DESCRIPTOR_FUNCTIONAL: u8 = 0x21;
+/// bitWillDetach, the device goes away on its own after DFU_DETACH instead of
+/// waiting for a bus reset
+const ATTRIBUTES: u8 = 0b1000;
+/// How long the host waits for the device to detach, in ms
+const DETACH_TIMEOUT_MS: u16 = 1000;
+/// Only matters to the bootloader, but the descriptor needs it. Same as the
+/// ROM bootloader's
+const TRANSFER_SIZE: u16 = 2048;
+const DFU_VERSION: u16 = 0x0110;
+
+const REQUEST_DETACH: u8 = 0;
+const REQUEST_GET_STATUS: u8 = 3;
+const REQUEST_GET_STATE: u8 = 5;
+
+/// appIDLE, the only state a runtime that detaches itself is ever in
+const STATE_APP_IDLE: u8 = 0;
+const STATUS_OK: u8 = 0;
+
+static DETACH: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+#[derive(Default)]
+pub struct DfuRuntime {
+    /// Set once it's been added to the device
+    interface: Option<InterfaceNumber>
+}
+
+impl DfuRuntime {
+    pub const fn new() -> Self {
+        Self { interface: None }
+    }
+
+    /// Adds the interface to the device and handles its requests from then on
+    pub fn register<'d>(&'d mut self, builder: &mut Builder<'d, UsbDriver<'d>>) {
+        let mut function = builder.function(
+            CLASS_APPLICATION_SPECIFIC,
+            SUBCLASS_DFU,
+            PROTOCOL_RUNTIME
+        );
+        let mut interface = function.interface();
+        self.interface = Some(interface.interface_number());
+        let mut alt = interface.alt_setting(
+            CLASS_APPLICATION_SPECIFIC,
+            SUBCLASS_DFU,
+            PROTOCOL_RUNTIME,
+            None
+        );
+        let [timeout_lo, timeout_hi] = DETACH_TIMEOUT_MS.to_le_bytes();
+        let [size_lo, size_hi] = TRANSFER_SIZE.to_le_bytes();
+        let [version_lo, version_hi] = DFU_VERSION.to_le_bytes();
+        alt.descriptor(
+            DESCRIPTOR_FUNCTIONAL,
+            &[
+                ATTRIBUTES, timeout_lo, timeout_hi, size_lo, size_hi, version_lo,
+                version_hi
+            ]
+        );
+        drop(function);
+        builder.handler(self);
+    }
+
+    fn is_ours(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && self
+                .interface
+                .is_some_and(|i| req.index == u8::from(i) as u16)
+    }
+}
+
+impl Handler for DfuRuntime {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        let _ = data;
+        if !self.is_ours(&req) {
+            return None;
+        }
+        match req.request {
+            REQUEST_DETACH => {
+                // the reboot has to wait until the request is acknowledged
+                DETACH.signal(());
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected)
+        }
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        req: Request,
+        buf: &'a mut [u8]
+    ) -> Option<InResponse<'a>> {
+        if !self.is_ours(&req) {
+            return None;
+        }
+        match req.request {
+            REQUEST_GET_STATUS => {
+                // status, bwPollTimeout (3 bytes), state, iString
+                buf[..6].copy_from_slice(&[STATUS_OK, 0, 0, 0, STATE_APP_IDLE, 0]);
+                Some(InResponse::Accepted(&buf[..6]))
+            }
+            REQUEST_GET_STATE => {
+                buf[0] = STATE_APP_IDLE;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected)
+        }
+    }
+}
+
+/// Reboots into the bootloader once the host asks for it
+pub async fn run() -> ! {
+    supervisor::waiting(Task::Usb, DETACH.wait()).await;
+    info!("detaching into the bootloader");
+    // give the host a chance to see the request go through
+    Timer::after_millis(50).await;
+    bootloader::reboot()
+}
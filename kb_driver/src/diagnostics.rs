@@ -0,0 +1,41 @@
+//! Counters that can be read over the raw HID protocol, see
+//! [`Counter`](kb_driver_core::protocol::Counter)
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::Instant;
+use kb_driver_core::protocol::Counter;
+
+use crate::supervisor::{self, Task};
+
+pub static KEY_EVENTS: AtomicU32 = AtomicU32::new(0);
+pub static UART_ERRORS: AtomicU32 = AtomicU32::new(0);
+pub static HANDSHAKE_FAILURES: AtomicU32 = AtomicU32::new(0);
+pub static REPORTS_SENT: AtomicU32 = AtomicU32::new(0);
+pub static CONFIG_COMMITS: AtomicU32 = AtomicU32::new(0);
+
+/// How many counters [`read`] knows about
+pub const COUNT: u8 = Counter::TASK_RESTARTS + Task::COUNT as u8;
+
+#[inline]
+pub fn bump(counter: &AtomicU32) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Reads a counter by its protocol index
+pub fn read(index: u8) -> Option<u32> {
+    let load = |c: &AtomicU32| c.load(Ordering::Relaxed);
+    Some(match index {
+        0 => Instant::now().as_millis() as u32,
+        1 => supervisor::last_reset_reason() as u32,
+        2 => load(&KEY_EVENTS),
+        3 => load(&UART_ERRORS),
+        4 => load(&HANDSHAKE_FAILURES),
+        5 => load(&REPORTS_SENT),
+        6 => load(&CONFIG_COMMITS),
+        i if i < COUNT => {
+            supervisor::restarts(Task::ALL[(i - Counter::TASK_RESTARTS) as usize])
+        }
+        _ => return None
+    })
+}
@@ -1,8 +1,18 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use embassy_usb::{class::hid::RequestHandler, Handler};
+use embassy_usb::{
+    class::hid::RequestHandler,
+    control::{InResponse, OutResponse, Recipient, Request, RequestType},
+    Handler
+};
 use kb_driver_proc_macro::debug;
 
+use crate::{
+    crashlog, host_os, power,
+    status::{self, UsbStatus},
+    supervisor::{heartbeat, Task}
+};
+
 #[derive(Default)]
 pub struct MyUsbHandler {
     configured: AtomicBool
@@ -19,10 +29,16 @@ impl MyUsbHandler {
 impl Handler for MyUsbHandler {
     fn enabled(&mut self, enabled: bool) {
         self.configured.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            status::update(|s| s.usb = UsbStatus::Unconfigured);
+        }
     }
 
     fn reset(&mut self) {
         self.configured.store(false, Ordering::Relaxed);
+        host_os::reset();
+        power::set_suspended(false);
+        status::update(|s| s.usb = UsbStatus::Unconfigured);
     }
 
     fn addressed(&mut self, _addr: u8) {}
@@ -31,14 +47,28 @@ impl Handler for MyUsbHandler {
         self.configured.store(configured, Ordering::Relaxed);
         if configured {
             debug!("device configured!");
+            status::update(|s| s.usb = UsbStatus::Configured);
         } else {
             debug!("device deconfigured");
+            status::update(|s| s.usb = UsbStatus::Unconfigured);
         }
     }
 
-    fn suspended(&mut self, _suspended: bool) {}
+    fn suspended(&mut self, suspended: bool) {
+        power::set_suspended(suspended);
+        let usb = if suspended {
+            UsbStatus::Suspended
+        } else if self.configured.load(Ordering::Relaxed) {
+            UsbStatus::Configured
+        } else {
+            UsbStatus::Unconfigured
+        };
+        status::update(|s| s.usb = usb);
+    }
 
-    fn remote_wakeup_enabled(&mut self, _enabled: bool) {}
+    fn remote_wakeup_enabled(&mut self, enabled: bool) {
+        power::set_remote_wakeup_enabled(enabled);
+    }
 
     fn set_alternate_setting(
         &mut self,
@@ -49,22 +79,44 @@ impl Handler for MyUsbHandler {
         let _ = alternate_setting;
     }
 
-    fn control_out(
-        &mut self,
-        req: embassy_usb::control::Request,
-        data: &[u8]
-    ) -> Option<embassy_usb::control::OutResponse> {
-        let _ = (req, data);
-        None
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        let _ = data;
+        if !is_vendor_request(&req) {
+            return None;
+        }
+        match req.request {
+            crashlog::REQUEST_CLEAR => {
+                crashlog::clear();
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected)
+        }
     }
 
     fn control_in<'a>(
         &'a mut self,
-        req: embassy_usb::control::Request,
+        req: Request,
         buf: &'a mut [u8]
-    ) -> Option<embassy_usb::control::InResponse<'a>> {
-        let _ = (req, buf);
-        None
+    ) -> Option<InResponse<'a>> {
+        if !is_vendor_request(&req) {
+            return None;
+        }
+        match req.request {
+            crashlog::REQUEST_READ => {
+                // an empty response means there's no crash to report
+                let Some(report) = crashlog::last_crash() else {
+                    return Some(InResponse::Accepted(&[]));
+                };
+                let bytes = report.to_bytes();
+                let offset = (req.index as usize).min(bytes.len());
+                let len = (bytes.len() - offset)
+                    .min(buf.len())
+                    .min(req.length as usize);
+                buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+                Some(InResponse::Accepted(&buf[..len]))
+            }
+            _ => Some(InResponse::Rejected)
+        }
     }
 
     fn get_string(
@@ -77,6 +129,13 @@ impl Handler for MyUsbHandler {
     }
 }
 
+fn is_vendor_request(req: &Request) -> bool {
+    req.request_type == RequestType::Vendor && req.recipient == Recipient::Device
+}
+
+/// Caps Lock bit of the keyboard LED output report
+const LED_CAPS_LOCK: u8 = 1 << 1;
+
 pub struct MyRequestHandler {}
 
 impl RequestHandler for MyRequestHandler {
@@ -85,9 +144,7 @@ impl RequestHandler for MyRequestHandler {
         id: embassy_usb::class::hid::ReportId,
         buf: &mut [u8]
     ) -> Option<usize> {
-        #[cfg(feature = "defmt")]
         debug!("received report {:?}, data {:?}", id, buf);
-        let _ = (id, buf);
         None
     }
 
@@ -96,8 +153,12 @@ impl RequestHandler for MyRequestHandler {
         id: embassy_usb::class::hid::ReportId,
         data: &[u8]
     ) -> embassy_usb::control::OutResponse {
-        #[cfg(feature = "defmt")]
         debug!("received report {:?}, data: {:?}", id, data);
+        heartbeat(Task::HidReader);
+        // the only output report a boot keyboard gets is the LED state
+        if let Some(leds) = data.first() {
+            status::update(|s| s.caps_lock = leds & LED_CAPS_LOCK != 0);
+        }
         embassy_usb::control::OutResponse::Accepted
     }
 
@@ -105,9 +166,7 @@ impl RequestHandler for MyRequestHandler {
         &mut self,
         id: Option<embassy_usb::class::hid::ReportId>
     ) -> Option<u32> {
-        #[cfg(feature = "defmt")]
         debug!("received report {:?}", id);
-        let _ = id;
         None
     }
 
@@ -116,8 +175,6 @@ impl RequestHandler for MyRequestHandler {
         id: Option<embassy_usb::class::hid::ReportId>,
         duration_ms: u32
     ) {
-        #[cfg(feature = "defmt")]
         debug!("received report {:?}, duration {}ms", id, duration_ms);
-        let _ = (id, duration_ms);
     }
 }
@@ -0,0 +1,162 @@
+//! Switches to the right profile for whatever the keyboard got plugged into
+//!
+//! embassy-usb answers device descriptor requests itself, they never reach a
+//! [`Handler`](embassy_usb::Handler), so the USB driver is wrapped in
+//! [`Driver`] to see every SETUP packet before the stack does.
+
+use core::cell::RefCell;
+
+use embassy_stm32::{peripherals::USB_OTG_FS, usb};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal
+};
+use embassy_time::{with_timeout, Duration};
+use embassy_usb::driver::{self, EndpointAllocError, EndpointError, EndpointType};
+use kb_driver_core::{host_os::Fingerprint, profile::PROFILES};
+
+use crate::{
+    info, storage,
+    supervisor::{self, heartbeat, Task},
+    warn
+};
+
+/// How long the host has to stop asking for descriptors before a guess is
+/// made, they don't all come in before the device is configured
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+static FINGERPRINT: Mutex<ThreadModeRawMutex, RefCell<Fingerprint>> =
+    Mutex::new(RefCell::new(Fingerprint::new()));
+
+/// Signaled with every request that makes it into the fingerprint
+static REQUESTED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// The USB driver everything is built on
+pub type UsbDriver<'d> = Driver<usb::Driver<'d, USB_OTG_FS>>;
+
+/// Takes note of a SETUP packet, called for every one the control pipe gets
+pub fn record(setup: &[u8; 8]) {
+    if FINGERPRINT.lock(|f| f.borrow_mut().record_setup(setup)) {
+        REQUESTED.signal(());
+    }
+}
+
+/// Forgets about the last host, the next one could be a different one
+pub fn reset() {
+    FINGERPRINT.lock(|f| f.borrow_mut().clear());
+}
+
+/// Guesses the host OS every time it's done enumerating, and switches to its
+/// profile unless host detection is off
+pub async fn run() -> ! {
+    loop {
+        supervisor::waiting(Task::HostOs, REQUESTED.wait()).await;
+        while with_timeout(SETTLE_TIME, REQUESTED.wait()).await.is_ok() {
+            heartbeat(Task::HostOs);
+        }
+        heartbeat(Task::HostOs);
+
+        let Some(os) = FINGERPRINT.lock(|f| f.borrow().guess()) else {
+            continue;
+        };
+        let switched = storage::update(|c| {
+            let profile = c.profile_for(os);
+            if !c.detect_host || c.profile == profile {
+                return Ok(None);
+            }
+            if profile as usize >= PROFILES {
+                return Err(profile);
+            }
+            c.profile = profile;
+            Ok(Some(profile))
+        });
+        match switched {
+            Ok(Some(profile)) => {
+                info!("host looks like {}, switched to profile {}", os, profile)
+            }
+            Ok(None) => info!("host looks like {}", os),
+            Err(profile) => warn!("there's no profile {} for {}", profile, os)
+        }
+    }
+}
+
+/// A USB driver that passes every SETUP packet to [`record`], and is otherwise
+/// the driver it wraps
+pub struct Driver<D>(pub D);
+
+impl<'d, D: driver::Driver<'d>> driver::Driver<'d> for Driver<D> {
+    type EndpointOut = D::EndpointOut;
+    type EndpointIn = D::EndpointIn;
+    type ControlPipe = ControlPipe<D::ControlPipe>;
+    type Bus = D::Bus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.0
+            .alloc_endpoint_out(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.0
+            .alloc_endpoint_in(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let (bus, pipe) = self.0.start(control_max_packet_size);
+        (bus, ControlPipe(pipe))
+    }
+}
+
+/// The control pipe of a [`Driver`]
+pub struct ControlPipe<C>(C);
+
+impl<C: driver::ControlPipe> driver::ControlPipe for ControlPipe<C> {
+    fn max_packet_size(&self) -> usize {
+        self.0.max_packet_size()
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        let setup = self.0.setup().await;
+        record(&setup);
+        setup
+    }
+
+    async fn data_out(
+        &mut self,
+        buf: &mut [u8],
+        first: bool,
+        last: bool
+    ) -> Result<usize, EndpointError> {
+        self.0.data_out(buf, first, last).await
+    }
+
+    async fn data_in(
+        &mut self,
+        data: &[u8],
+        first: bool,
+        last: bool
+    ) -> Result<(), EndpointError> {
+        self.0.data_in(data, first, last).await
+    }
+
+    async fn accept(&mut self) {
+        self.0.accept().await
+    }
+
+    async fn reject(&mut self) {
+        self.0.reject().await
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.0.accept_set_address(addr).await
+    }
+}
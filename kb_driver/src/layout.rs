@@ -0,0 +1,6 @@
+//! The default config, generated by `build.rs` from `keymap.toml`
+//!
+//! It's what the firmware starts out with until a config is saved, and what a
+//! factory reset goes back to.
+
+include!(concat!(env!("OUT_DIR"), "/keymap.rs"));
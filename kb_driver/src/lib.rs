@@ -1,7 +1,25 @@
 #![no_std]
 
+pub mod bootloader;
+pub mod capture;
+pub mod crashlog;
+pub mod dfu;
+pub mod diagnostics;
 pub mod handlers;
-pub mod key_codes;
+pub mod host_os;
+pub mod layout;
+#[cfg(feature = "log-buffer")]
+pub mod log_buffer;
 pub mod palm_kb;
+pub mod power;
+#[cfg(not(feature = "usb-log"))]
+pub mod raw_hid;
+pub mod status;
+pub mod storage;
+pub mod supervisor;
+pub mod update;
+#[cfg(feature = "usb-log")]
+pub mod usb_log;
 
+pub use kb_driver_core::key_codes;
 pub use kb_driver_proc_macro::*;
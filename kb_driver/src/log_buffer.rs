@@ -0,0 +1,65 @@
+//! Keeps log lines in RAM, for the `log-buffer` logging backend
+//!
+//! Every message the logging macros let through gets written into [`LINES`]
+//! as a line of text, as long as there's room for the whole line. Something
+//! has to read them back out, which with `usb-log` is the USB serial port.
+//! Without it they're still there for a probe to dump.
+
+use core::fmt::{self, Write};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe};
+use embassy_time::Instant;
+use kb_driver_core::log_sink::{self, Level};
+
+/// Longer lines get cut off
+const LINE_LEN: usize = 128;
+
+/// Lines waiting to be read, new ones get dropped while it's full
+static LINES: Pipe<CriticalSectionRawMutex, 2048> = Pipe::new();
+
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // always leave room for the line ending
+        let count = s.len().min(LINE_LEN - 2 - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+fn sink(level: Level, args: fmt::Arguments) {
+    let mut line = Line {
+        buf: [0; LINE_LEN],
+        len: 0
+    };
+    let ms = Instant::now().as_millis();
+    let _ = write!(
+        line,
+        "{}.{:03} {:<5} {}",
+        ms / 1000,
+        ms % 1000,
+        level.name(),
+        args
+    );
+    line.buf[line.len..line.len + 2].copy_from_slice(b"\r\n");
+    line.len += 2;
+    // half a line is worse than none
+    if LINES.free_capacity() >= line.len {
+        let _ = LINES.try_write(&line.buf[..line.len]);
+    }
+}
+
+/// Starts keeping log lines, nothing before this gets kept
+pub fn init() {
+    log_sink::set_sink(sink);
+}
+
+/// Waits for at least one byte of log lines and takes as many as fit in `buf`
+pub async fn read(buf: &mut [u8]) -> usize {
+    LINES.read(buf).await
+}
@@ -1,34 +1,53 @@
 #![no_std]
 #![no_main]
 
+use core::mem::MaybeUninit;
+
 use embassy_executor::Spawner;
-use embassy_futures::select::{select3, Either3};
+use embassy_futures::select::{select, select4, Either};
 use embassy_stm32::{
     bind_interrupts,
-    gpio::{AnyPin, Output, Pin},
-    peripherals,
+    flash::Flash,
+    gpio::{AnyPin, Level, Output, Pin, Speed},
+    peripherals::{self, IWDG},
     time::Hertz,
     usart::{self, Config as UsartConfig, DataBits, Parity, StopBits, UartRx},
     usb::{self, Config as UsbOtgConfig},
     Config
 };
-use embassy_time::{Duration, Timer};
 use embassy_usb::{
     class::hid::{Config as HidConfig, HidReaderWriter, State},
     Config as UsbConfig
 };
-use handlers::{MyRequestHandler, MyUsbHandler};
-use kb_driver::palm_kb::KeyboardDriver;
-use kb_driver_proc_macro::{debug, error, info};
-use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
+#[cfg(feature = "log-buffer")]
+use kb_driver::log_buffer;
+#[cfg(not(feature = "usb-log"))]
+use kb_driver::raw_hid;
+#[cfg(feature = "usb-log")]
+use kb_driver::usb_log;
+use kb_driver::{
+    bootloader, crashlog,
+    dfu::{self, DfuRuntime},
+    handlers::{MyRequestHandler, MyUsbHandler},
+    host_os,
+    palm_kb::KeyboardDriver,
+    power, status,
+    storage::{self, ConfigStore},
+    supervisor::{self, Task},
+    update::Updater
+};
+#[cfg(not(feature = "usb-log"))]
+use kb_driver_core::protocol::{RAW_HID_DESCRIPTOR, REPORT_LEN};
+use kb_driver_core::{
+    model::{self, MODEL},
+    report::KEYBOARD_DESCRIPTOR,
+    via
+};
+use kb_driver_proc_macro::{debug, error, info, warn};
 
 #[cfg(feature = "defmt")]
 use defmt_rtt as _;
 
-use panic_probe as _;
-
-mod handlers;
-
 bind_interrupts!(struct UsbIrq {
     OTG_FS => usb::InterruptHandler<peripherals::USB_OTG_FS>;
 });
@@ -37,8 +56,20 @@ bind_interrupts!(struct UsartIrq {
     USART2 => usart::InterruptHandler<peripherals::USART2>;
 });
 
-#[embassy_executor::main]
-async fn main(spawner: Spawner) {
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    // `entry` turns this into a `&'static mut`
+    static mut EXECUTOR: MaybeUninit<power::Executor> = MaybeUninit::uninit();
+    EXECUTOR
+        .write(power::Executor::new())
+        .run(|spawner| spawner.spawn(run(spawner)).unwrap())
+}
+
+#[embassy_executor::task]
+async fn run(spawner: Spawner) {
+    bootloader::check();
+    #[cfg(feature = "log-buffer")]
+    log_buffer::init();
     let mut config = Config::default();
     {
         use embassy_stm32::rcc::*;
@@ -60,43 +91,62 @@ async fn main(spawner: Spawner) {
         config.rcc.apb2_pre = APBPrescaler::DIV1;
         config.rcc.sys = Sysclk::PLL1_P;
         config.rcc.mux.clk48sel = mux::Clk48sel::PLL1_Q;
+        // the RTC wakes the MCU up from stop mode
+        config.rcc.ls = LsConfig::default_lsi();
     }
     let p = embassy_stm32::init(config);
+    power::init();
     info!("clocks initialized");
+    crashlog::init(supervisor::record_reset_reason() as u8);
 
-    spawner.spawn(blink_on_time(p.PC13.degrade())).unwrap();
+    spawner.spawn(watchdog(p.IWDG)).unwrap();
+    spawner.spawn(status_led(p.PC13.degrade())).unwrap();
+    let flash = Flash::new_blocking(p.FLASH).into_blocking_regions();
+    let store = storage::init(flash.bank1_region1);
+    let updater = Updater::new(flash.bank1_region2, flash.bank1_region3);
+    spawner.spawn(config_storage(store)).unwrap();
+    spawner.spawn(host_detection()).unwrap();
 
     let mut usb_buf = [0u8; 256];
 
     let mut config = UsbOtgConfig::default();
     config.vbus_detection = false;
 
-    let driver = embassy_stm32::usb::Driver::new_fs(
+    let driver = host_os::Driver(embassy_stm32::usb::Driver::new_fs(
         p.USB_OTG_FS,
         UsbIrq,
         p.PA12,
         p.PA11,
         &mut usb_buf,
         config
-    );
+    ));
 
-    let mut config = UsbConfig::new(0x1209, 0x0011);
+    let mut config = UsbConfig::new(via::VENDOR_ID, via::PRODUCT_ID);
     config.manufacturer = Some("Juliapixel");
     config.product = Some("Palm USB Keyboard");
+    // how Vial finds keyboards it can talk to
+    config.serial_number = Some(via::VIAL_SERIAL_NUMBER);
 
     config.device_class = 0xEF;
     config.device_sub_class = 0x02;
     config.device_protocol = 0x01;
     config.composite_with_iads = true;
+    config.supports_remote_wakeup = true;
 
     let mut config_descriptor = [0; 256];
     let mut bos_descriptor = [0; 256];
     // Microsoft OS descriptor
     let mut msos_descriptor = [0; 256];
-    let mut control_buf = [0; 64];
+    // big enough to read a whole crash report in one go
+    let mut control_buf = [0; 256];
 
     let mut handler = MyUsbHandler::new();
+    let mut dfu = DfuRuntime::new();
     let mut state = State::new();
+    #[cfg(not(feature = "usb-log"))]
+    let mut raw_hid_state = State::new();
+    #[cfg(feature = "usb-log")]
+    let mut log_state = embassy_usb::class::cdc_acm::State::new();
 
     let mut builder = embassy_usb::Builder::new(
         driver,
@@ -108,11 +158,12 @@ async fn main(spawner: Spawner) {
     );
 
     builder.handler(&mut handler);
+    dfu.register(&mut builder);
 
     let mut request_handler = MyRequestHandler {};
 
     let config = HidConfig {
-        report_descriptor: KeyboardReport::desc(),
+        report_descriptor: &KEYBOARD_DESCRIPTOR,
         request_handler: None,
         poll_ms: 10,
         max_packet_size: 8
@@ -120,58 +171,136 @@ async fn main(spawner: Spawner) {
 
     let hid = HidReaderWriter::<'_, _, 1, 8>::new(&mut builder, &mut state, config);
 
+    #[cfg(not(feature = "usb-log"))]
+    let raw_hid = {
+        let config = HidConfig {
+            report_descriptor: &RAW_HID_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16
+        };
+        HidReaderWriter::<'_, _, REPORT_LEN, REPORT_LEN>::new(
+            &mut builder,
+            &mut raw_hid_state,
+            config
+        )
+    };
+    // there aren't enough endpoints for both
+    #[cfg(feature = "usb-log")]
+    let log_class = usb_log::init(&mut builder, &mut log_state);
+
     let mut usb = builder.build();
-    let usb_fut = usb.run();
+    let usb_fut = supervisor::supervised(Task::Usb, async {
+        let run_usb = async {
+            loop {
+                supervisor::waiting(Task::Usb, usb.run_until_suspend()).await;
+                let resumed =
+                    select(usb.wait_resume(), power::wait_wakeup_request());
+                match supervisor::waiting(Task::Usb, resumed).await {
+                    Either::First(_) => (),
+                    Either::Second(_) => {
+                        info!("waking up host");
+                        if let Err(e) = usb.remote_wakeup().await {
+                            warn!("failed to wake up host: {}", e);
+                        }
+                    }
+                }
+            }
+        };
+        // the USB stack has to keep going until the detach request is answered
+        select(run_usb, dfu::run()).await
+    });
 
-    let (mut reader, writer) = hid.split();
+    let (mut reader, mut writer) = hid.split();
 
-    let read_fut = async {
+    let read_fut = supervisor::supervised(Task::HidReader, async {
         debug!("waiting for reader");
-        reader.ready().await;
+        supervisor::waiting(Task::HidReader, reader.ready()).await;
         debug!("reader running");
-        reader.run(false, &mut request_handler).await;
-    };
+        // only the LED state ever comes in
+        let run = reader.run(false, &mut request_handler);
+        supervisor::waiting(Task::HidReader, run).await;
+    });
 
-    let uart_fut = async {
+    let uart_fut = supervisor::supervised(Task::Keyboard, async {
+        let line = MODEL.line;
         let mut config = UsartConfig::default();
-        config.baudrate = 9600;
-        config.data_bits = DataBits::DataBits8;
-        config.parity = Parity::ParityNone;
-        config.stop_bits = StopBits::STOP1;
+        config.baudrate = line.baud;
+        config.data_bits = match line.data_bits {
+            9 => DataBits::DataBits9,
+            _ => DataBits::DataBits8
+        };
+        config.parity = match line.parity {
+            model::Parity::None => Parity::ParityNone,
+            model::Parity::Even => Parity::ParityEven,
+            model::Parity::Odd => Parity::ParityOdd
+        };
+        config.stop_bits = match line.stop_bits {
+            2 => StopBits::STOP2,
+            _ => StopBits::STOP1
+        };
 
-        let rxd_pin = p.PA3;
-        let dma_chan = p.DMA1_CH5;
+        let mut usart = p.USART2;
+        let mut rxd_pin = p.PA3;
+        let mut dma_chan = p.DMA1_CH5;
+        let (mut vcc, mut rts, mut dcd, mut exti) = (p.PB8, p.PB4, p.PB3, p.EXTI3);
 
-        let uart =
-            UartRx::new(p.USART2, UsartIrq {}, rxd_pin, dma_chan, config).unwrap();
+        // the driver only ever returns if something went really wrong, so it
+        // gets rebuilt from scratch and started again
+        loop {
+            let uart = UartRx::new(
+                &mut usart,
+                UsartIrq {},
+                &mut rxd_pin,
+                &mut dma_chan,
+                config
+            )
+            .unwrap();
 
-        let driver = KeyboardDriver::new(uart, p.PB8, p.PB4, p.PB3, p.EXTI3, writer);
-        driver.run().await
-    };
+            let driver = KeyboardDriver::new(
+                uart,
+                &mut vcc,
+                &mut rts,
+                &mut dcd,
+                &mut exti,
+                &mut writer
+            );
+            driver.run().await;
+            supervisor::task_exited(Task::Keyboard);
+        }
+    });
 
-    let failed = select3(usb_fut, read_fut, uart_fut).await;
-    match failed {
-        Either3::First(_) => error!("usb driver task exited unexpectedly"),
-        Either3::Second(_) => error!("usb reader task exited unexpectedly"),
-        Either3::Third(_) => error!("uart task exited unexpectedly")
-    }
+    #[cfg(not(feature = "usb-log"))]
+    let raw_hid_fut =
+        supervisor::supervised(Task::RawHid, raw_hid::run(raw_hid, updater));
+    #[cfg(feature = "usb-log")]
+    let raw_hid_fut =
+        supervisor::supervised(Task::UsbLog, usb_log::run(log_class, updater));
+
+    // whichever one returned was already recorded by `supervised`, and the USB
+    // stack can't be rebuilt in place, so start over instead
+    select4(usb_fut, read_fut, uart_fut, raw_hid_fut).await;
+    error!("resetting");
+    supervisor::reset()
 }
 
 #[embassy_executor::task]
-async fn blink_on_time(pin: AnyPin) {
-    let mut blinky = Output::new(
-        pin,
-        embassy_stm32::gpio::Level::Low,
-        embassy_stm32::gpio::Speed::Low
-    );
-    loop {
-        blinky.set_low();
-        Timer::after(Duration::from_millis(20)).await;
-        blinky.set_high();
-        Timer::after(Duration::from_millis(50)).await;
-        blinky.set_low();
-        Timer::after(Duration::from_millis(20)).await;
-        blinky.set_high();
-        Timer::after(Duration::from_millis(1950)).await;
-    }
+async fn watchdog(iwdg: IWDG) {
+    supervisor::feed_watchdog(iwdg).await
+}
+
+#[embassy_executor::task]
+async fn status_led(pin: AnyPin) {
+    let led = Output::new(pin, Level::High, Speed::Low);
+    supervisor::supervised(Task::StatusLed, status::run_led(led)).await
+}
+
+#[embassy_executor::task]
+async fn config_storage(store: ConfigStore) {
+    supervisor::supervised(Task::Storage, storage::run(store)).await
+}
+
+#[embassy_executor::task]
+async fn host_detection() {
+    supervisor::supervised(Task::HostOs, host_os::run()).await
 }
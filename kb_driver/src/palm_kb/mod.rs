@@ -1,53 +1,165 @@
-use core::cell::UnsafeCell;
-
-use embassy_futures::{join::join, select::select3};
+use embassy_futures::{
+    join::join,
+    select::{select4, Either4}
+};
 use embassy_stm32::{
     exti::ExtiInput,
     gpio::{Output, Pin},
     mode::Async,
-    peripherals::USB_OTG_FS,
     usart::{BasicInstance, Error, RingBufferedUartRx, UartRx},
-    usb::Driver,
     Peripheral, PeripheralRef
 };
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, blocking_mutex::Mutex};
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use embassy_usb::class::hid::HidWriter;
 use embedded_io_async::Read;
+use kb_driver_core::{
+    macros::{Output as MacroOutput, Player},
+    model::{KeyboardModel, MODEL},
+    profile::{self, PROFILES},
+    program::Typer,
+    report::Report
+};
 use usbd_hid::descriptor::KeyboardReport;
 
-use crate::{debug, error, info, warn};
+use crate::{
+    bootloader, capture,
+    crashlog::{self, EventKind},
+    debug, diagnostics, error,
+    host_os::UsbDriver,
+    info, power,
+    status::{self, KbStatus},
+    storage,
+    supervisor::{self, heartbeat, Task},
+    warn
+};
 
-use self::state::State;
+use self::state::{is_key_down, Command, State};
 
-pub mod matrix;
-pub mod state;
+pub use kb_driver_core::{matrix, state};
 
-static REPORT: Mutex<ThreadModeRawMutex, UnsafeCell<KeyboardReport>> =
-    Mutex::new(UnsafeCell::new(KeyboardReport::default()));
+/// Reports waiting to be written out, in order so quick taps don't get lost
+static REPORTS: Channel<ThreadModeRawMutex, KeyboardReport, 16> = Channel::new();
 
 /// Compatibility layer between the Palm keyboard's UART interface and USB-HID
-pub struct KeyboardDriver<'d, 'u, T: BasicInstance, V: Pin, R: Pin> {
+pub struct KeyboardDriver<'d, 'u, 'w, T: BasicInstance, V: Pin, R: Pin> {
     uart: UartRx<'u, T, Async>,
     vcc: PeripheralRef<'d, V>,
     rts: PeripheralRef<'d, R>,
     dcd: ExtiInput<'d>,
-    writer: HidWriter<'d, Driver<'d, USB_OTG_FS>, 8>,
+    writer: &'d mut HidWriter<'w, UsbDriver<'w>, 8>,
     state: State
 }
 
-/// Constantly writes the current KeyboardReport out to the USB-HID endpoint
+/// Writes queued KeyboardReports out to the USB-HID endpoint, holding off
+/// while the bus is suspended
 async fn write_kb_report<'d>(
-    report: &'static Mutex<ThreadModeRawMutex, UnsafeCell<KeyboardReport>>,
-    mut writer: HidWriter<'d, Driver<'d, USB_OTG_FS>, 8>
+    reports: &'static Channel<ThreadModeRawMutex, KeyboardReport, 16>,
+    writer: &mut HidWriter<'d, UsbDriver<'d>, 8>
 ) {
     loop {
-        writer.ready().await;
-        let report = unsafe { report.lock(|r| *r.get()) };
+        let report = supervisor::waiting(Task::Keyboard, reports.receive()).await;
+        supervisor::waiting(Task::Keyboard, power::wait_resumed()).await;
+        // until the host configures the device
+        supervisor::waiting(Task::Keyboard, writer.ready()).await;
         match writer.write_serialize(&report).await {
-            Ok(_) => (),
+            Ok(_) => diagnostics::bump(&diagnostics::REPORTS_SENT),
             Err(e) => warn!("failed to write to USB endpoint {}", e)
         }
+        heartbeat(Task::Keyboard);
+    }
+}
+
+/// Milliseconds since boot, as used by [`State`]
+#[inline]
+fn now_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+/// Queues a report up for [`write_kb_report`], which can take a while if the
+/// queue is full and the bus is suspended
+async fn send(
+    reports: &'static Channel<ThreadModeRawMutex, KeyboardReport, 16>,
+    report: Report
+) {
+    supervisor::waiting(Task::Keyboard, reports.send(report.into())).await
+}
+
+/// Types out a macro on top of whatever is held down, then puts things back
+/// the way they were
+async fn play_macro(
+    reports: &'static Channel<ThreadModeRawMutex, KeyboardReport, 16>,
+    index: u8,
+    held: Report
+) {
+    debug!("playing macro {}", index);
+    let mut player = Player::new(index, held);
+    while let Some(output) = storage::with_config(|c| player.next(&c.macros)) {
+        match output {
+            MacroOutput::Report(report) => send(reports, report).await,
+            MacroOutput::Delay(ms) => {
+                // these can be longer than the supervisor likes
+                let delay = Timer::after_millis(ms as u64);
+                supervisor::waiting(Task::Keyboard, delay).await
+            }
+        }
+    }
+    if player.held() != held {
+        send(reports, held).await;
+    }
+}
+
+/// Sends out everything the state queued up and does what it asked for. This
+/// MUST NOT get cancelled, or reports and commands already taken out of the
+/// state are lost halfway, like a macro leaving a key held down
+async fn flush_state(
+    reports: &'static Channel<ThreadModeRawMutex, KeyboardReport, 16>,
+    state: &mut State
+) {
+    while let Some(report) = state.pop_report() {
+        send(reports, report).await;
+    }
+    while let Some(command) = state.pop_command() {
+        match command {
+            Command::PlayMacro(index) => {
+                play_macro(reports, index, state.report()).await
+            }
+            Command::FactoryReset => {
+                storage::request(storage::Request::FactoryReset)
+            }
+            Command::Bootloader => {
+                info!("rebooting into the bootloader");
+                // let go of everything first, in case the host doesn't notice
+                // the keyboard going away right away
+                send(reports, Report::new()).await;
+                Timer::after_millis(50).await;
+                bootloader::reboot();
+            }
+            Command::Type(message) => {
+                for report in Typer::new(message) {
+                    send(reports, report).await;
+                }
+            }
+            Command::Remap { layer, pos, action } => {
+                info!("remapping {} on layer {}", pos, layer);
+                storage::update(|c| {
+                    c.keymap_mut().set(layer as usize, pos as usize, action)
+                });
+                storage::request(storage::Request::Commit);
+            }
+            Command::SwitchProfile(index) if (index as usize) < PROFILES => {
+                info!("switching to profile {}", index);
+                storage::update(|c| c.profile = index);
+                storage::request(storage::Request::Commit);
+            }
+            Command::SwitchProfile(index) => warn!("there's no profile {}", index),
+            Command::Unicode(code_point) => {
+                let mode = storage::with_config(|c| c.active_profile().unicode);
+                for report in profile::unicode_reports(mode, code_point) {
+                    send(reports, report).await;
+                }
+            }
+        }
     }
 }
 
@@ -58,63 +170,127 @@ async fn read_initial_bytes<'d, T: BasicInstance>(
     let mut buf = [0u8; 2];
     let resp = uart.read_exact(&mut buf).await;
     debug!("received initial buf: {:02X}", &buf);
+    if resp.is_ok() {
+        buf.iter().for_each(|b| capture::record(*b));
+    }
     match resp {
-        Ok(_) => buf == [0xFA, 0xFD],
+        Ok(_) if buf == MODEL.handshake => true,
+        Ok(_) => {
+            match KeyboardModel::detect(buf) {
+                Some(model) => {
+                    warn!("that's a {}, not a {}", model.name, MODEL.name)
+                }
+                None => warn!("unknown handshake {:02X}", &buf)
+            }
+            false
+        }
         Err(_) => false
     }
 }
 
-/// this method *should* be cancel safe (as of embassy-stm32@51d55309)
-async fn receive_forever<'u, T: BasicInstance>(
-    report: &'static Mutex<ThreadModeRawMutex, UnsafeCell<KeyboardReport>>,
+/// Reads key events until the state has something to send out, which is left
+/// to [`flush_state`] outside of the `select4` in [`listen_kb`]
+///
+/// this method *should* be cancel safe (as of embassy-stm32@51d55309), as
+/// nothing happens between reading a byte and handing it to the state
+async fn receive_until_output<'u, T: BasicInstance>(
     state: &mut State,
-    uart: &mut RingBufferedUartRx<'u, T>
-) -> ! {
+    uart: &mut RingBufferedUartRx<'u, T>,
+    last_activity: &mut Instant
+) {
     loop {
         let mut buf = [0u8; 1];
-        let read = uart.read(&mut buf).await;
+        // wake up in time to decide on tap-hold keys nobody let go of
+        let read = match state.next_deadline() {
+            Some(deadline) => {
+                let left = deadline.wrapping_sub(now_ms()) as i32;
+                let timeout = Duration::from_millis(left.max(0) as u64);
+                let read = embassy_time::with_timeout(timeout, uart.read(&mut buf));
+                match supervisor::waiting(Task::Keyboard, read).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        state.tick(now_ms());
+                        if state.has_output() {
+                            return;
+                        }
+                        continue;
+                    }
+                }
+            }
+            None => supervisor::waiting(Task::Keyboard, uart.read(&mut buf)).await
+        };
+        heartbeat(Task::Keyboard);
         match read {
             Ok(_) => {
                 debug!("received buf: {:08b}", buf[0]);
-                state.update_from_kb_input(buf[0]);
-                unsafe { report.lock(|r| *r.get() = KeyboardReport::from(&*state)) }
-            }
-            Err(Error::Framing) => warn!("UART Framing error"),
-            Err(Error::BufferTooLong) => warn!("UART buffer too long for DMA"),
-            Err(Error::Noise) => warn!("UART Noise error"),
-            Err(Error::Overrun) => warn!("UART buffer overrun"),
-            Err(Error::Parity) => warn!("UART parity bit error"),
-            Err(_) => error!("UART unknown error")
+                capture::record(buf[0]);
+                diagnostics::bump(&diagnostics::KEY_EVENTS);
+                *last_activity = Instant::now();
+                if is_key_down(buf[0]) {
+                    power::request_wakeup();
+                }
+                storage::with_config(|config| {
+                    state.update_from_kb_input(buf[0], config, now_ms())
+                });
+                status::update(|s| s.layer = state.active_layer());
+                if state.has_output() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let code = match e {
+                    Error::Framing => {
+                        warn!("UART Framing error");
+                        1
+                    }
+                    Error::BufferTooLong => {
+                        warn!("UART buffer too long for DMA");
+                        2
+                    }
+                    Error::Noise => {
+                        warn!("UART Noise error");
+                        3
+                    }
+                    Error::Overrun => {
+                        warn!("UART buffer overrun");
+                        4
+                    }
+                    Error::Parity => {
+                        warn!("UART parity bit error");
+                        5
+                    }
+                    _ => {
+                        error!("UART unknown error");
+                        0
+                    }
+                };
+                diagnostics::bump(&diagnostics::UART_ERRORS);
+                crashlog::record(EventKind::UartError, code);
+            }
         };
     }
 }
 
-/// Main driver loop, manages the connection to the keyboard and stuff
-async fn listen_kb<'p, T: BasicInstance>(
-    report: &'static Mutex<ThreadModeRawMutex, UnsafeCell<KeyboardReport>>,
-    mut vcc: Output<'p>,
-    mut rts: Output<'p>,
-    mut dcd: ExtiInput<'p>,
-    mut state: State,
-    uart: UartRx<'p, T, Async>
+/// Powers the keyboard up and keeps retrying the handshake until it answers
+async fn connect<'p, 'u, T: BasicInstance>(
+    vcc: &mut Output<'p>,
+    rts: &mut Output<'p>,
+    uart: &mut RingBufferedUartRx<'u, T>
 ) {
-    // reset to initial state in case it wasn't already at it
-    vcc.set_low();
-    rts.set_low();
-    let mut ring_buffer = [0u8; 256];
-    let mut uart = uart.into_ring_buffered(&mut ring_buffer);
-
+    status::update(|s| s.kb = KbStatus::Handshaking);
+    let mut err_count: u32 = 0;
     loop {
+        heartbeat(Task::Keyboard);
         // toggle RTS to trigger the handshake frames
         rts.set_low();
-        Timer::after(Duration::from_millis(15)).await;
+        Timer::after(Duration::from_millis(MODEL.power.rts_low_ms as u64)).await;
         // turn on power delivery to kb
         vcc.set_high();
         rts.set_high();
 
         let handshake_successful = embassy_time::with_timeout(
-            Duration::from_millis(100),
-            read_initial_bytes(&mut uart)
+            Duration::from_millis(MODEL.power.power_up_timeout_ms as u64),
+            read_initial_bytes(uart)
         )
         .await
         .unwrap_or(false);
@@ -123,29 +299,104 @@ async fn listen_kb<'p, T: BasicInstance>(
             break;
         } else {
             error!("keyboard handshake unsuccessful");
+            diagnostics::bump(&diagnostics::HANDSHAKE_FAILURES);
+            err_count += 1;
+            if err_count >= 5 {
+                status::update(|s| s.kb = KbStatus::Faulted);
+            }
         }
     }
+    status::update(|s| s.kb = KbStatus::Connected);
+}
+
+/// Main driver loop, manages the connection to the keyboard and stuff
+async fn listen_kb<'p, T: BasicInstance>(
+    reports: &'static Channel<ThreadModeRawMutex, KeyboardReport, 16>,
+    mut vcc: Output<'p>,
+    mut rts: Output<'p>,
+    mut dcd: ExtiInput<'p>,
+    mut state: State,
+    uart: UartRx<'p, T, Async>
+) {
+    // reset to initial state in case it wasn't already at it
+    vcc.set_low();
+    rts.set_low();
+    let mut ring_buffer = [0u8; 256];
+    let mut uart = uart.into_ring_buffered(&mut ring_buffer);
+
+    connect(&mut vcc, &mut rts, &mut uart).await;
 
     // toggle RTS and perform handshake to avoid going into low-power mode
-    let mut ticker = Ticker::every(Duration::from_secs(60));
+    let mut ticker =
+        Ticker::every(Duration::from_secs(MODEL.power.keep_awake_secs as u64));
+    let mut last_activity = Instant::now();
 
     loop {
-        select3(
+        // read every time so changes apply without restarting the driver
+        let policy = storage::with_config(|c| c.power);
+        let woken_by = select4(
             dcd.wait_for_rising_edge(),
             ticker.next(),
-            receive_forever(report, &mut state, &mut uart)
+            receive_until_output(&mut state, &mut uart, &mut last_activity),
+            power::wait_suspended(policy)
         )
         .await;
 
+        match woken_by {
+            Either4::Third(()) => {
+                flush_state(reports, &mut state).await;
+                continue;
+            }
+            // the keyboard raises DCD when a key is pressed while it's asleep
+            Either4::First(_) => power::request_wakeup(),
+            Either4::Second(_) if power::should_idle(&policy, last_activity) => {
+                info!("keyboard idle, letting it sleep");
+                status::update(|s| s.kb = KbStatus::Idle);
+                if policy.idle_action == power::IdleAction::CutPower {
+                    vcc.set_low();
+                    rts.set_low();
+                }
+                power::set_keyboard_asleep(true);
+                let woken = dcd.wait_for_rising_edge();
+                supervisor::waiting(Task::Keyboard, woken).await;
+                power::set_keyboard_asleep(false);
+                info!("keyboard woke up");
+                power::request_wakeup();
+                last_activity = Instant::now();
+                if policy.idle_action == power::IdleAction::CutPower {
+                    connect(&mut vcc, &mut rts, &mut uart).await;
+                    ticker.reset();
+                    continue;
+                }
+            }
+            Either4::Fourth(_) => {
+                info!("bus suspended, cutting power to keyboard");
+                vcc.set_low();
+                rts.set_low();
+                state.reset();
+                flush_state(reports, &mut state).await;
+                power::set_keyboard_asleep(true);
+                supervisor::waiting(Task::Keyboard, power::wait_resumed()).await;
+                power::set_keyboard_asleep(false);
+                info!("bus resumed, powering keyboard back up");
+                connect(&mut vcc, &mut rts, &mut uart).await;
+                ticker.reset();
+                last_activity = Instant::now();
+                continue;
+            }
+            _ => ()
+        }
+
         let mut err_count: u32 = 0;
         loop {
+            heartbeat(Task::Keyboard);
             rts.set_low();
             // gotta have this here or kb just will not notice the toggle
-            Timer::after(Duration::from_millis(15)).await;
+            Timer::after(Duration::from_millis(MODEL.power.rts_low_ms as u64)).await;
             rts.set_high();
             info!("keyboard reconnecting");
             let handshake_successful = embassy_time::with_timeout(
-                Duration::from_millis(30),
+                Duration::from_millis(MODEL.power.handshake_timeout_ms as u64),
                 read_initial_bytes(&mut uart)
             )
             .await
@@ -155,24 +406,32 @@ async fn listen_kb<'p, T: BasicInstance>(
                 break;
             } else {
                 error!("keyboard handshake unsuccessful");
+                diagnostics::bump(&diagnostics::HANDSHAKE_FAILURES);
                 err_count += 1;
                 if err_count >= 5 {
                     state.reset();
+                    flush_state(reports, &mut state).await;
+                    status::update(|s| s.kb = KbStatus::Faulted);
+                } else {
+                    status::update(|s| s.kb = KbStatus::Handshaking);
                 }
             }
         }
+        status::update(|s| s.kb = KbStatus::Connected);
         ticker.reset();
     }
 }
 
-impl<'d, 'u, T: BasicInstance, V: Pin, R: Pin> KeyboardDriver<'d, 'u, T, V, R> {
+impl<'d, 'u, 'w, T: BasicInstance, V: Pin, R: Pin>
+    KeyboardDriver<'d, 'u, 'w, T, V, R>
+{
     pub fn new<D: Pin>(
         uart: UartRx<'u, T, Async>,
         vcc: impl Peripheral<P = V> + 'd,
         rts: impl Peripheral<P = R> + 'd,
         dcd: impl Peripheral<P = D> + 'd,
         exti: impl Peripheral<P = D::ExtiChannel> + 'd,
-        writer: HidWriter<'d, Driver<'d, USB_OTG_FS>, 8>
+        writer: &'d mut HidWriter<'w, UsbDriver<'w>, 8>
     ) -> Self {
         let input = ExtiInput::new(dcd, exti, embassy_stm32::gpio::Pull::Down);
         Self {
@@ -199,8 +458,8 @@ impl<'d, 'u, T: BasicInstance, V: Pin, R: Pin> KeyboardDriver<'d, 'u, T, V, R> {
             embassy_stm32::gpio::Speed::VeryHigh
         );
         join(
-            write_kb_report(&REPORT, self.writer),
-            listen_kb(&REPORT, vcc, rts, self.dcd, self.state, self.uart)
+            write_kb_report(&REPORTS, self.writer),
+            listen_kb(&REPORTS, vcc, rts, self.dcd, self.state, self.uart)
         )
         .await;
     }
@@ -0,0 +1,229 @@
+//! USB suspend/resume handling, remote wakeup and idle power management
+//!
+//! While the bus is suspended the keyboard driver stops writing reports and,
+//! if the host allowed it, a key press (or the keyboard raising DCD) wakes the
+//! host back up. What happens to the keyboard itself is up to the [`Policy`]
+//! in the config.
+//!
+//! The MCU sleeps in [`Executor`]'s `WFE` whenever every task is waiting. Once
+//! the bus is suspended and the keyboard can't send anything without raising
+//! DCD first, it goes into stop mode instead, where the clocks are off and only
+//! DCD, the host resuming the bus or the RTC can wake it up. The RTC does that
+//! every few seconds to pet the IWDG, which keeps counting in stop mode. The
+//! embassy clock doesn't, so timers are held off for as long as the MCU stops.
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering}
+};
+
+use embassy_executor::{raw, Spawner};
+use embassy_stm32::pac::{self, iwdg::vals::Key, rcc::vals::Sw, rtc::vals::Wucksel};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant};
+
+use crate::debug;
+
+/// How often the RTC wakes the MCU up from stop mode to pet the IWDG, well
+/// within its timeout
+const STOP_WAKEUP_MS: u32 = 4000;
+/// The RTC's wakeup timer runs off the ~32kHz LSI divided by 16
+const WAKEUP_TIMER_HZ: u32 = 32_000 / 16;
+/// EXTI lines of the USB OTG FS wakeup and the RTC wakeup timer
+const EXTI_OTG_FS_WKUP: usize = 18;
+const EXTI_RTC_WKUP: usize = 22;
+
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+static REMOTE_WAKEUP_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set while the keyboard is asleep or has no power, so it can't send anything
+/// without raising DCD first
+static KEYBOARD_ASLEEP: AtomicBool = AtomicBool::new(false);
+
+/// Signaled with the new state every time the bus is suspended or resumed
+static SUSPEND_CHANGED: Signal<ThreadModeRawMutex, bool> = Signal::new();
+/// Signaled when the bus is resumed, used to hold off writing reports
+static RESUMED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// Signaled when something wants the host to wake up
+static WAKEUP_REQUEST: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+pub use kb_driver_core::power::{IdleAction, Policy};
+
+/// Checks if the keyboard has gone without key events for long enough to be
+/// put to sleep
+pub fn should_idle(policy: &Policy, last_activity: Instant) -> bool {
+    policy.idle_timeout_ms().is_some_and(|timeout| {
+        last_activity.elapsed() >= Duration::from_millis(timeout as u64)
+    })
+}
+
+/// Called by the keyboard driver when it puts the keyboard to sleep or cuts
+/// its power, and when it's back
+pub fn set_keyboard_asleep(asleep: bool) {
+    KEYBOARD_ASLEEP.store(asleep, Ordering::Relaxed);
+}
+
+/// Called by the USB handler when the host suspends or resumes the bus
+pub fn set_suspended(suspended: bool) {
+    if SUSPENDED.swap(suspended, Ordering::Relaxed) == suspended {
+        return;
+    }
+    debug!("bus suspended: {}", suspended);
+    if suspended {
+        RESUMED.reset();
+        WAKEUP_REQUEST.reset();
+    } else {
+        RESUMED.signal(());
+    }
+    SUSPEND_CHANGED.signal(suspended);
+}
+
+#[inline]
+pub fn is_suspended() -> bool {
+    SUSPENDED.load(Ordering::Relaxed)
+}
+
+/// Called by the USB handler when the host enables or disables remote wakeup
+pub fn set_remote_wakeup_enabled(enabled: bool) {
+    debug!("remote wakeup enabled: {}", enabled);
+    REMOTE_WAKEUP_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Asks the USB task to wake the host up, does nothing if the bus isn't
+/// suspended or the host didn't enable remote wakeup
+pub fn request_wakeup() {
+    if is_suspended() && REMOTE_WAKEUP_ENABLED.load(Ordering::Relaxed) {
+        WAKEUP_REQUEST.signal(());
+    }
+}
+
+/// Waits until something calls [`request_wakeup`]
+pub async fn wait_wakeup_request() {
+    WAKEUP_REQUEST.wait().await
+}
+
+/// Returns right away if the bus isn't suspended, otherwise waits for it to
+/// be resumed
+pub async fn wait_resumed() {
+    while is_suspended() {
+        RESUMED.wait().await;
+    }
+}
+
+/// Waits for the bus to be suspended or resumed and returns the new state
+pub async fn wait_suspend_change() -> bool {
+    SUSPEND_CHANGED.wait().await
+}
+
+/// Waits until the bus gets suspended, never returns if the policy doesn't
+/// care about suspend
+pub async fn wait_suspended(policy: Policy) {
+    if !policy.cut_vcc_on_suspend {
+        core::future::pending::<()>().await;
+    }
+    while !is_suspended() {
+        wait_suspend_change().await;
+    }
+}
+
+/// Sets up what wakes the MCU up from stop mode, MUST be called after
+/// `embassy_stm32::init`, which has the RTC running off the LSI
+pub fn init() {
+    pac::RCC.apb1enr().modify(|w| w.set_pwren(true));
+    pac::PWR.cr1().modify(|w| w.set_dbp(true));
+
+    pac::RTC.wpr().write(|w| w.set_key(0xCA));
+    pac::RTC.wpr().write(|w| w.set_key(0x53));
+    pac::RTC.cr().modify(|w| w.set_wute(false));
+    while !pac::RTC.isr().read().wutwf() {}
+    pac::RTC
+        .wutr()
+        .write(|w| w.set_wut((STOP_WAKEUP_MS * WAKEUP_TIMER_HZ / 1000 - 1) as u16));
+    pac::RTC.cr().modify(|w| {
+        w.set_wucksel(Wucksel::DIV16);
+        w.set_wutie(true);
+        w.set_wute(true);
+    });
+    pac::RTC.wpr().write(|w| w.set_key(0xFF));
+
+    // as events, so they wake up `WFE` without needing an interrupt handler
+    for line in [EXTI_OTG_FS_WKUP, EXTI_RTC_WKUP] {
+        pac::EXTI.rtsr(0).modify(|w| w.set_line(line, true));
+        pac::EXTI.emr(0).modify(|w| w.set_line(line, true));
+    }
+    // keeps the probe attached while stopped
+    #[cfg(feature = "defmt")]
+    pac::DBGMCU.cr().modify(|w| w.set_dbg_stop(true));
+}
+
+/// Stop mode runs off the HSI, this brings back the clocks `main` set up. The
+/// PLL keeps its settings, it only has to be turned back on
+fn restore_clocks() {
+    if pac::RCC.cfgr().read().sws() == Sw::PLL1_P {
+        return;
+    }
+    pac::RCC.cr().modify(|w| w.set_hseon(true));
+    while !pac::RCC.cr().read().hserdy() {}
+    pac::RCC.cr().modify(|w| w.set_pllon(true));
+    while !pac::RCC.cr().read().pllrdy() {}
+    pac::RCC.cfgr().modify(|w| w.set_sw(Sw::PLL1_P));
+    while pac::RCC.cfgr().read().sws() != Sw::PLL1_P {}
+}
+
+/// Lets the RTC wake the MCU up again, it only does so on a rising edge
+fn clear_rtc_wakeup() {
+    pac::RTC.isr().modify(|w| w.set_wutf(false));
+    pac::EXTI.pr(0).write(|w| w.set_line(EXTI_RTC_WKUP, true));
+}
+
+/// Waits for something to happen, in stop mode if nothing needs the clocks
+fn sleep() {
+    if !(is_suspended() && KEYBOARD_ASLEEP.load(Ordering::Relaxed)) {
+        cortex_m::asm::wfe();
+        return;
+    }
+    // SAFETY: nothing else touches SLEEPDEEP
+    let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+    // PDDS stays clear, which picks stop mode over standby
+    pac::PWR.cr1().modify(|w| {
+        w.set_lpds(true);
+        w.set_fpds(true);
+    });
+    clear_rtc_wakeup();
+    scb.set_sleepdeep();
+    cortex_m::asm::wfe();
+    scb.clear_sleepdeep();
+    restore_clocks();
+    clear_rtc_wakeup();
+    // nothing ran while stopped, so there's nothing the supervisor could've
+    // caught either
+    pac::IWDG.kr().write(|w| w.set_key(Key::RESET));
+}
+
+/// Embassy's thread mode executor, except it goes into stop mode when it can
+/// instead of just sleeping
+pub struct Executor {
+    inner: raw::Executor,
+    not_send: PhantomData<*mut ()>
+}
+
+impl Executor {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            // what embassy's own executor uses, `SEV` wakes it up
+            inner: raw::Executor::new(usize::MAX as *mut ()),
+            not_send: PhantomData
+        }
+    }
+
+    /// Spawns the first tasks with `init` and runs them forever
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        let this: &'static Self = self;
+        init(this.inner.spawner());
+        loop {
+            // SAFETY: only ever polled from here, in thread mode
+            unsafe { this.inner.poll() };
+            sleep();
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! The vendor raw HID interface, see [`kb_driver_core::protocol`] and
+//! [`kb_driver_core::via`] for what goes over it
+
+use embassy_time::Timer;
+use embassy_usb::class::hid::{HidReaderWriter, ReadError};
+use kb_driver_core::{
+    capture::Capture,
+    config::Config,
+    crash_report::CrashReport,
+    protocol::{self, Backend, UpdateError, REPORT_LEN}
+};
+
+use crate::{
+    bootloader, capture, crashlog, diagnostics,
+    host_os::UsbDriver,
+    info, layout,
+    storage::{self, Request},
+    supervisor::{self, heartbeat, Task},
+    update::Updater,
+    warn
+};
+
+/// Vial's keyboard definition, xz compressed by the build script
+static VIAL_DEFINITION: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/vial.json.xz"));
+
+struct Firmware {
+    /// Set once the host asked for the bootloader, which has to wait until
+    /// the response is out
+    reboot: bool,
+    updater: Updater
+}
+
+impl Backend for Firmware {
+    fn with_config<R>(&mut self, f: impl FnOnce(&mut Config) -> R) -> R {
+        storage::update(f)
+    }
+
+    fn commit(&mut self) -> bool {
+        storage::request(Request::Commit);
+        true
+    }
+
+    fn revert(&mut self) -> bool {
+        storage::request(Request::Revert);
+        true
+    }
+
+    fn counter(&mut self, index: u8) -> Option<u32> {
+        diagnostics::read(index)
+    }
+
+    fn counter_count(&self) -> u8 {
+        diagnostics::COUNT
+    }
+
+    fn firmware_version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn factory_reset(&mut self) -> bool {
+        storage::request(Request::FactoryReset);
+        true
+    }
+
+    fn default_config(&self) -> &Config {
+        &layout::DEFAULT_CONFIG
+    }
+
+    fn vial_definition(&self) -> &[u8] {
+        VIAL_DEFINITION
+    }
+
+    fn crash_report(&mut self) -> Option<CrashReport> {
+        crashlog::last_crash()
+    }
+
+    fn clear_crash_report(&mut self) {
+        crashlog::clear()
+    }
+
+    fn reboot_to_bootloader(&mut self) -> bool {
+        self.reboot = true;
+        true
+    }
+
+    fn begin_update(&mut self, len: u32) -> Result<(), UpdateError> {
+        self.updater.begin(len)
+    }
+
+    fn write_update(&mut self, offset: u32, data: &[u8]) -> Result<(), UpdateError> {
+        self.updater.write(offset, data)
+    }
+
+    fn finish_update(&mut self, crc: u32) -> Result<(), UpdateError> {
+        self.updater.finish(crc)
+    }
+
+    fn with_capture<R>(&mut self, f: impl FnOnce(&mut Capture) -> R) -> R {
+        capture::with(f)
+    }
+}
+
+/// Answers requests forever
+pub async fn run<'d>(
+    hid: HidReaderWriter<'d, UsbDriver<'d>, REPORT_LEN, REPORT_LEN>,
+    mut updater: Updater
+) -> ! {
+    let (mut reader, mut writer) = hid.split();
+    // the host setting the device up is as good a sign as any that a new
+    // firmware works
+    let configured = supervisor::waiting(Task::RawHid, reader.ready());
+    updater.confirm(configured).await;
+    supervisor::waiting(Task::RawHid, reader.ready()).await;
+    let mut firmware = Firmware {
+        reboot: false,
+        updater
+    };
+    loop {
+        let mut request = [0u8; REPORT_LEN];
+        let read = reader.read(&mut request);
+        match supervisor::waiting(Task::RawHid, read).await {
+            Ok(_) => {}
+            // unplugged or suspended, reading again would just fail again
+            // right away until the host sets the device back up
+            Err(ReadError::Disabled) => {
+                supervisor::waiting(Task::RawHid, reader.ready()).await;
+                continue;
+            }
+            Err(e) => {
+                warn!("failed to read raw HID request: {}", e);
+                continue;
+            }
+        }
+        let mut response = [0u8; REPORT_LEN];
+        protocol::dispatch(&request, &mut response, &mut firmware);
+        if let Err(e) = writer.write(&response).await {
+            warn!("failed to write raw HID response: {}", e);
+        }
+        heartbeat(Task::RawHid);
+        if firmware.reboot {
+            info!("rebooting into the bootloader");
+            // give the host a chance to pick the response up
+            Timer::after_millis(50).await;
+            bootloader::reboot();
+        }
+        if firmware.updater.is_ready() {
+            info!("resetting into the new firmware");
+            Timer::after_millis(50).await;
+            supervisor::reset();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Status LED driven by what the USB bus and the keyboard are actually doing
+
+use core::cell::Cell;
+
+use embassy_futures::select::select;
+use embassy_stm32::gpio::Output;
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal
+};
+use embassy_time::{Duration, Timer};
+
+use crate::{
+    crashlog::{self, EventKind},
+    supervisor::{self, heartbeat, Task}
+};
+
+static STATUS: Mutex<ThreadModeRawMutex, Cell<Status>> =
+    Mutex::new(Cell::new(Status::new()));
+
+/// Signaled whenever the indication shown by the LED has to change
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbStatus {
+    Unconfigured,
+    Configured,
+    Suspended
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KbStatus {
+    Handshaking,
+    Connected,
+    /// the keyboard failed to handshake too many times in a row
+    Faulted,
+    /// the keyboard was put to sleep after going unused for a while
+    Idle
+}
+
+/// Everything the LED can tell you about, updated by the keyboard driver and
+/// the USB handlers through [`update`]
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    pub usb: UsbStatus,
+    pub kb: KbStatus,
+    pub caps_lock: bool,
+    pub layer: u8,
+    /// The host profile in use
+    pub profile: u8
+}
+
+/// What the LED is currently showing, only the most important one is shown at
+/// any time
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Indication {
+    UsbUnconfigured,
+    Suspended,
+    KbFaulted,
+    KbHandshaking,
+    KbIdle,
+    CapsLock,
+    Layer,
+    /// Connected and idle, with the host profile in use
+    Connected(u8)
+}
+
+/// A single step of a blink pattern, the LED is held in this state for `ms`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub lit: bool,
+    pub ms: u16
+}
+
+const fn on(ms: u16) -> Step {
+    Step { lit: true, ms }
+}
+
+const fn off(ms: u16) -> Step {
+    Step { lit: false, ms }
+}
+
+/// Blink pattern for every [`Indication`], looped for as long as it is active.
+///
+/// Change these to customize what the LED looks like, an empty pattern just
+/// keeps the LED off
+pub static PATTERNS: [(Indication, &[Step]); 10] = [
+    (Indication::UsbUnconfigured, &[on(100), off(900)]),
+    (Indication::Suspended, &[]),
+    (Indication::KbFaulted, &[on(100), off(100)]),
+    (Indication::KbHandshaking, &[on(500), off(500)]),
+    (Indication::KbIdle, &[]),
+    (Indication::CapsLock, &[on(1000)]),
+    (Indication::Layer, &[on(900), off(100)]),
+    // one blink for every profile number
+    (Indication::Connected(0), &[on(20), off(1980)]),
+    (
+        Indication::Connected(1),
+        &[on(20), off(50), on(20), off(1910)]
+    ),
+    (
+        Indication::Connected(2),
+        &[on(20), off(50), on(20), off(50), on(20), off(1840)]
+    )
+];
+
+impl Status {
+    pub const fn new() -> Self {
+        Self {
+            usb: UsbStatus::Unconfigured,
+            kb: KbStatus::Handshaking,
+            caps_lock: false,
+            layer: 0,
+            profile: 0
+        }
+    }
+
+    /// Picks the most important thing to show on the LED
+    pub fn indication(&self) -> Indication {
+        match (self.usb, self.kb) {
+            (UsbStatus::Suspended, _) => Indication::Suspended,
+            (UsbStatus::Unconfigured, _) => Indication::UsbUnconfigured,
+            (_, KbStatus::Faulted) => Indication::KbFaulted,
+            (_, KbStatus::Handshaking) => Indication::KbHandshaking,
+            (_, KbStatus::Idle) => Indication::KbIdle,
+            _ if self.caps_lock => Indication::CapsLock,
+            _ if self.layer != 0 => Indication::Layer,
+            _ => Indication::Connected(self.profile)
+        }
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Indication {
+    pub fn pattern(self) -> &'static [Step] {
+        PATTERNS
+            .iter()
+            .find(|(i, _)| *i == self)
+            .map(|(_, steps)| *steps)
+            .unwrap_or(&[])
+    }
+}
+
+/// Returns the current status
+pub fn current() -> Status {
+    STATUS.lock(|s| s.get())
+}
+
+/// Updates the current status, waking up the LED if what it shows has changed
+pub fn update(f: impl FnOnce(&mut Status)) {
+    STATUS.lock(|s| {
+        let old = s.get();
+        let mut new = old;
+        f(&mut new);
+        s.set(new);
+        if old.indication() != new.indication() {
+            CHANGED.signal(());
+        }
+        record_transitions(&old, &new);
+    })
+}
+
+/// Keeps the crash log's event buffer up to date with what's going on
+fn record_transitions(old: &Status, new: &Status) {
+    if old.usb != new.usb {
+        let kind = match (old.usb, new.usb) {
+            (_, UsbStatus::Suspended) => EventKind::UsbSuspended,
+            (UsbStatus::Suspended, _) => EventKind::UsbResumed,
+            (_, UsbStatus::Configured) => EventKind::UsbConfigured,
+            (_, UsbStatus::Unconfigured) => EventKind::UsbDeconfigured
+        };
+        crashlog::record(kind, 0);
+    }
+    if old.kb != new.kb {
+        match new.kb {
+            KbStatus::Connected => crashlog::record(EventKind::KbConnected, 0),
+            KbStatus::Faulted => crashlog::record(EventKind::KbHandshakeFailed, 0),
+            KbStatus::Idle => crashlog::record(EventKind::KbIdle, 0),
+            KbStatus::Handshaking => ()
+        }
+    }
+}
+
+/// Loops a blink pattern forever
+async fn play(led: &mut Output<'_>, steps: &[Step]) {
+    // the LED on the Black Pill is active low
+    if steps.is_empty() {
+        led.set_high();
+        supervisor::waiting(Task::StatusLed, core::future::pending::<()>()).await;
+    }
+    loop {
+        for step in steps {
+            if step.lit {
+                led.set_low();
+            } else {
+                led.set_high();
+            }
+            Timer::after(Duration::from_millis(step.ms as u64)).await;
+            heartbeat(Task::StatusLed);
+        }
+    }
+}
+
+/// Drives the status LED forever
+pub async fn run_led(mut led: Output<'_>) -> ! {
+    loop {
+        let pattern = current().indication().pattern();
+        select(play(&mut led, pattern), CHANGED.wait()).await;
+    }
+}
@@ -0,0 +1,142 @@
+//! The runtime config and the task that keeps it in flash
+//!
+//! Changes made with [`update`] only live in RAM until a [`Request::Commit`],
+//! so a bad keymap can be undone with [`Request::Revert`] or a power cycle.
+//! The config lives in sectors 2 and 3, two 16K sectors between the bootloader
+//! and its state, see [`update`](crate::update) for the whole flash layout.
+//!
+//! Erasing a sector stalls the whole executor for a bit, but that only happens
+//! once every ~5 commits. Commits that come in quick succession, like VIA
+//! saving every key as it's remapped, only get written once things settle
+//! down.
+
+use core::cell::RefCell;
+
+use embassy_stm32::flash::{Bank1Region1, Blocking};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal
+};
+use embassy_time::{with_timeout, Duration};
+use kb_driver_core::config::{Config, Store};
+
+use crate::{
+    diagnostics, error, info,
+    layout::DEFAULT_CONFIG,
+    status,
+    supervisor::{self, heartbeat, Task},
+    warn
+};
+
+/// Offset of sector 2 from the start of the flash
+const BASE: u32 = 0x8000;
+/// One 16K sector per bank
+const BANK_SIZE: u32 = 0x4000;
+/// How long a commit waits for another one before being written
+const COMMIT_DELAY: Duration = Duration::from_millis(500);
+
+static CONFIG: Mutex<ThreadModeRawMutex, RefCell<Config>> =
+    Mutex::new(RefCell::new(DEFAULT_CONFIG));
+
+static REQUEST: Signal<ThreadModeRawMutex, Request> = Signal::new();
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    /// Saves the config as it is right now
+    Commit,
+    /// Throws away changes made since the last commit
+    Revert,
+    /// Erases the saved config and goes back to the defaults
+    FactoryReset
+}
+
+/// Sectors 0 to 3, the 16K ones
+pub type ConfigStore = Store<Bank1Region1<'static, Blocking>>;
+
+/// Reads the config from flash, MUST be called before anything looks at the
+/// config
+pub fn init(flash: Bank1Region1<'static, Blocking>) -> ConfigStore {
+    let mut store = Store::new(flash, BASE, BANK_SIZE);
+    load(&mut store);
+    store
+}
+
+fn load(store: &mut ConfigStore) {
+    let config = match store.load() {
+        Ok(Some(config)) => {
+            info!("loaded config from flash");
+            config
+        }
+        Ok(None) => DEFAULT_CONFIG,
+        Err(e) => {
+            error!("failed to load config, using defaults: {}", e);
+            DEFAULT_CONFIG
+        }
+    };
+    set(config);
+}
+
+/// Replaces the whole config
+fn set(config: Config) {
+    status::update(|s| s.profile = config.profile);
+    CONFIG.lock(|c| *c.borrow_mut() = config);
+}
+
+/// Looks at the current config
+pub fn with_config<R>(f: impl FnOnce(&Config) -> R) -> R {
+    CONFIG.lock(|c| f(&c.borrow()))
+}
+
+/// Changes the current config, without saving it
+pub fn update<R>(f: impl FnOnce(&mut Config) -> R) -> R {
+    let (out, profile) = CONFIG.lock(|c| {
+        let mut config = c.borrow_mut();
+        (f(&mut config), config.profile)
+    });
+    // the LED shows which profile is in use, whatever changed it
+    status::update(|s| s.profile = profile);
+    out
+}
+
+/// Asks the storage task to do something, a request that hasn't been handled
+/// yet gets replaced
+pub fn request(request: Request) {
+    REQUEST.signal(request)
+}
+
+/// Handles [`Request`]s forever
+pub async fn run(mut store: ConfigStore) -> ! {
+    loop {
+        let mut request = supervisor::waiting(Task::Storage, REQUEST.wait()).await;
+        while request == Request::Commit {
+            heartbeat(Task::Storage);
+            match with_timeout(COMMIT_DELAY, REQUEST.wait()).await {
+                Ok(next) => request = next,
+                Err(_) => break
+            }
+        }
+        match request {
+            Request::Commit => {
+                if let Err(e) = with_config(|c| store.save(c)) {
+                    error!("failed to save config: {}", e);
+                } else {
+                    info!("config saved");
+                    diagnostics::bump(&diagnostics::CONFIG_COMMITS);
+                }
+            }
+            Request::Revert => {
+                info!("reverting config");
+                load(&mut store);
+            }
+            Request::FactoryReset => {
+                warn!("resetting config to factory defaults");
+                if let Err(e) = store.factory_reset() {
+                    error!("failed to erase config: {}", e);
+                }
+                set(DEFAULT_CONFIG);
+            }
+        }
+        heartbeat(Task::Storage);
+    }
+}
@@ -0,0 +1,247 @@
+//! Task supervision, watchdog feeding and reset reason bookkeeping
+//!
+//! Every long-running task is wrapped in [`supervised`] and calls [`heartbeat`]
+//! whenever it gets something done, like handling a byte from the keyboard or a
+//! request from the host. Waits that can legitimately go on forever, like for
+//! the next key press, go through [`waiting`] instead. [`feed_watchdog`] only
+//! pets the IWDG while every task is either waiting or sending heartbeats, so
+//! if a task gets stuck somewhere it shouldn't, exits for good, or the
+//! executor gets stuck, the MCU gets reset.
+//!
+//! How often each task had to be restarted is kept in the NOINIT RAM region
+//! like the crash log, so it survives those resets and only starts over at
+//! power on.
+
+use core::{
+    future::Future,
+    mem::MaybeUninit,
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicU32, AtomicU8, Ordering}
+};
+
+use cortex_m::interrupt;
+use embassy_stm32::{pac, peripherals::IWDG, wdg::IndependentWatchdog};
+use embassy_time::{Duration, Timer};
+
+use crate::{
+    crashlog::{self, EventKind},
+    error, info
+};
+
+/// How long a task can go without sending heartbeats while it isn't waiting
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Comfortably longer than [`CHECK_INTERVAL`] so a slightly late check doesn't
+/// reset the MCU, even when a flash erase stalls everything for a couple seconds
+const WATCHDOG_TIMEOUT_US: u32 = 8_000_000;
+
+const RESTARTS_MAGIC: u32 = 0x5253_5452;
+
+static HEARTBEATS: [AtomicU32; Task::COUNT] =
+    [const { AtomicU32::new(0) }; Task::COUNT];
+/// How many [`waiting`] futures each task has going right now
+static WAITING: [AtomicU8; Task::COUNT] = [const { AtomicU8::new(0) }; Task::COUNT];
+static RESET_REASON: AtomicU8 = AtomicU8::new(ResetReason::Unknown as u8);
+
+/// Tasks that have to keep running for the firmware to work
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Task {
+    Usb,
+    HidReader,
+    Keyboard,
+    StatusLed,
+    Storage,
+    #[cfg(not(feature = "usb-log"))]
+    RawHid,
+    /// Only in `usb-log` builds, where it takes the raw HID interface's place
+    #[cfg(feature = "usb-log")]
+    UsbLog,
+    HostOs
+}
+
+impl Task {
+    pub const COUNT: usize = 7;
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::Usb,
+        Self::HidReader,
+        Self::Keyboard,
+        Self::StatusLed,
+        Self::Storage,
+        #[cfg(not(feature = "usb-log"))]
+        Self::RawHid,
+        #[cfg(feature = "usb-log")]
+        Self::UsbLog,
+        Self::HostOs
+    ];
+}
+
+#[repr(C)]
+struct Restarts {
+    magic: u32,
+    counts: [u32; Task::COUNT]
+}
+
+#[link_section = ".noinit.RESTARTS"]
+static mut RESTARTS: MaybeUninit<Restarts> = MaybeUninit::uninit();
+
+/// # Safety
+/// Must only be called with interrupts disabled or before they're enabled
+unsafe fn restart_counts() -> &'static mut Restarts {
+    &mut *(*addr_of_mut!(RESTARTS)).as_mut_ptr()
+}
+
+/// Why the MCU was last reset, read from `RCC_CSR` at boot
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ResetReason {
+    PowerOn,
+    Pin,
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    LowPower,
+    Brownout,
+    Unknown
+}
+
+impl From<u8> for ResetReason {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::PowerOn,
+            1 => Self::Pin,
+            2 => Self::Software,
+            3 => Self::IndependentWatchdog,
+            4 => Self::WindowWatchdog,
+            5 => Self::LowPower,
+            6 => Self::Brownout,
+            _ => Self::Unknown
+        }
+    }
+}
+
+/// Reads and clears the reset flags, MUST be called once at boot
+pub fn record_reset_reason() -> ResetReason {
+    let csr = pac::RCC.csr().read();
+    // BORRSTF is also set on power-on and PINRSTF on pretty much every kind of
+    // reset, so those go last
+    let reason = if csr.iwdgrstf() {
+        ResetReason::IndependentWatchdog
+    } else if csr.wwdgrstf() {
+        ResetReason::WindowWatchdog
+    } else if csr.lpwrrstf() {
+        ResetReason::LowPower
+    } else if csr.sftrstf() {
+        ResetReason::Software
+    } else if csr.porrstf() {
+        ResetReason::PowerOn
+    } else if csr.borrstf() {
+        ResetReason::Brownout
+    } else if csr.pinrstf() {
+        ResetReason::Pin
+    } else {
+        ResetReason::Unknown
+    };
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+    RESET_REASON.store(reason as u8, Ordering::Relaxed);
+
+    // RAM doesn't keep anything without power
+    interrupt::free(|_| {
+        let restarts = unsafe { restart_counts() };
+        let powered_up =
+            matches!(reason, ResetReason::PowerOn | ResetReason::Brownout);
+        if powered_up || restarts.magic != RESTARTS_MAGIC {
+            restarts.magic = RESTARTS_MAGIC;
+            restarts.counts = [0; Task::COUNT];
+        }
+    });
+    info!("last reset reason: {}", reason);
+    reason
+}
+
+#[inline]
+pub fn last_reset_reason() -> ResetReason {
+    RESET_REASON.load(Ordering::Relaxed).into()
+}
+
+/// How many times a task had to be restarted since the adapter was powered up
+pub fn restarts(task: Task) -> u32 {
+    interrupt::free(|_| unsafe { restart_counts() }.counts[task as usize])
+}
+
+/// Lets the supervisor know `task` is still getting things done
+#[inline]
+pub fn heartbeat(task: Task) {
+    HEARTBEATS[task as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Marks a task as waiting until it's dropped
+struct Waiting(Task);
+
+impl Waiting {
+    fn new(task: Task) -> Self {
+        WAITING[task as usize].fetch_add(1, Ordering::Relaxed);
+        Self(task)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        WAITING[self.0 as usize].fetch_sub(1, Ordering::Relaxed);
+        heartbeat(self.0);
+    }
+}
+
+/// Awaits `fut` without expecting heartbeats from `task` in the meantime, for
+/// waits that can legitimately take forever, like for a key press or the host
+pub async fn waiting<F: Future>(task: Task, fut: F) -> F::Output {
+    let _waiting = Waiting::new(task);
+    fut.await
+}
+
+/// Called when a task returns when it shouldn't have, right before it gets
+/// restarted
+pub fn task_exited(task: Task) {
+    error!("task {} exited unexpectedly", task);
+    interrupt::free(|_| unsafe { restart_counts() }.counts[task as usize] += 1);
+    crashlog::record(EventKind::TaskExited, task as u8);
+}
+
+/// Resets the MCU, for tasks that can't be restarted in place
+pub fn reset() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Runs `fut` as `task`, and records it exiting if it ever does
+pub async fn supervised<F: Future>(task: Task, fut: F) -> F::Output {
+    heartbeat(task);
+    let out = fut.await;
+    task_exited(task);
+    out
+}
+
+/// Starts the IWDG and pets it for as long as every task keeps sending
+/// heartbeats or is waiting
+pub async fn feed_watchdog(iwdg: IWDG) -> ! {
+    let mut wdg = IndependentWatchdog::new(iwdg, WATCHDOG_TIMEOUT_US);
+    wdg.unleash();
+
+    let mut last_beats = [0u32; Task::COUNT];
+    loop {
+        Timer::after(CHECK_INTERVAL).await;
+        let mut all_alive = true;
+        for task in Task::ALL {
+            let beats = HEARTBEATS[task as usize].load(Ordering::Relaxed);
+            let waiting = WAITING[task as usize].load(Ordering::Relaxed) > 0;
+            if beats == last_beats[task as usize] && !waiting {
+                error!("task {} is stuck", task);
+                all_alive = false;
+            }
+            last_beats[task as usize] = beats;
+        }
+        if all_alive {
+            wdg.pet();
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Firmware updates through `embassy-boot`
+//!
+//! The flash is split up like this, the bootloader has the same layout in its
+//! own `memory.x`:
+//!
+//! | sectors | address      | size | content                             |
+//! |---------|--------------|------|-------------------------------------|
+//! | 0-1     | `0x08000000` | 32K  | the bootloader                      |
+//! | 2-3     | `0x08008000` | 32K  | the config, see [`storage`](crate::storage) |
+//! | 4       | `0x08010000` | 64K  | the bootloader's state              |
+//! | 5       | `0x08020000` | 128K | the firmware that's running         |
+//! | 6-7     | `0x08040000` | 256K | where updates get written           |
+//!
+//! An image sent over raw HID goes into the update slot, and once its CRC
+//! checks out the bootloader gets told to swap it in on the next reset. The
+//! new firmware starts out on trial: it only marks itself booted once a host
+//! has set it up, and if it resets before that, whether it crashed, got
+//! stuck or never enumerated in [`TRIAL_TIMEOUT`], the bootloader swaps the
+//! old one back.
+
+use core::future::Future;
+
+use embassy_boot_stm32::{AlignedBuffer, BlockingFirmwareState, State};
+use embassy_stm32::flash::{Bank1Region2, Bank1Region3, Blocking, WRITE_SIZE};
+use embassy_time::{with_timeout, Duration};
+use kb_driver_core::{firmware_update::UpdateSlot, protocol::UpdateError};
+
+use crate::{error, info, supervisor, warn};
+
+/// Offset of sector 6 from the start of sector 5
+const DFU_OFFSET: u32 = 0x2_0000;
+/// Images can't be bigger than the active slot, sector 5
+const ACTIVE_SIZE: u32 = 0x2_0000;
+/// How long a new firmware gets to be set up by the host before it's given up
+/// on
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+type StateFlash = Bank1Region2<'static, Blocking>;
+
+pub struct Updater {
+    slot: UpdateSlot<Bank1Region3<'static, Blocking>>,
+    /// The bootloader's state, sector 4
+    state: StateFlash,
+    /// Set once an image checked out, the reset has to wait until the
+    /// response is out
+    ready: bool
+}
+
+impl Updater {
+    pub fn new(state: StateFlash, slots: Bank1Region3<'static, Blocking>) -> Self {
+        Self {
+            slot: UpdateSlot::new(slots, DFU_OFFSET, ACTIVE_SIZE),
+            state,
+            ready: false
+        }
+    }
+
+    fn with_state<R>(
+        &mut self,
+        f: impl FnOnce(&mut BlockingFirmwareState<&mut StateFlash>) -> R
+    ) -> R {
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+        f(&mut BlockingFirmwareState::new(
+            &mut self.state,
+            &mut aligned.0
+        ))
+    }
+
+    /// Whether this firmware was just swapped in and hasn't been marked
+    /// booted yet
+    fn is_trial(&mut self) -> bool {
+        matches!(self.with_state(|s| s.get_state()), Ok(State::Swap))
+    }
+
+    /// Keeps a firmware on trial around for good once `configured` finishes,
+    /// which should be when the host has set the device up. If that takes
+    /// longer than [`TRIAL_TIMEOUT`] it resets instead
+    pub async fn confirm(&mut self, configured: impl Future<Output = ()>) {
+        if !self.is_trial() {
+            return;
+        }
+        if with_timeout(TRIAL_TIMEOUT, configured).await.is_err() {
+            error!("new firmware wasn't set up by the host, rolling back");
+            supervisor::reset();
+        }
+        match self.with_state(|s| s.mark_booted()) {
+            Ok(()) => info!("new firmware marked as booted"),
+            Err(e) => error!("failed to mark the firmware as booted: {}", e)
+        }
+    }
+
+    /// Whether an image is waiting for the reset that swaps it in
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    pub fn begin(&mut self, len: u32) -> Result<(), UpdateError> {
+        // a firmware on trial can't be replaced before it's known to work,
+        // the bootloader would lose the old one
+        if self.ready || self.is_trial() {
+            return Err(UpdateError::Busy);
+        }
+        info!("receiving a {} byte firmware image", len);
+        self.slot.begin(len).map_err(|e| {
+            warn!("failed to start the update: {}", e);
+            e.map_flash(drop)
+        })
+    }
+
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), UpdateError> {
+        self.slot.write(offset, data).map_err(|e| e.map_flash(drop))
+    }
+
+    pub fn finish(&mut self, crc: u32) -> Result<(), UpdateError> {
+        if let Err(e) = self.slot.finish(crc) {
+            warn!("firmware image rejected: {}", e);
+            return Err(e.map_flash(drop));
+        }
+        if let Err(e) = self.with_state(|s| s.mark_updated()) {
+            error!("failed to hand the image to the bootloader: {}", e);
+            return Err(UpdateError::Flash(()));
+        }
+        info!("firmware image ready, swapping on the next reset");
+        self.ready = true;
+        Ok(())
+    }
+}
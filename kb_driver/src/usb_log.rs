@@ -0,0 +1,87 @@
+//! Log lines over a USB serial port, for when there's no probe attached
+//!
+//! Only there with the `usb-log` feature, which sends the lines kept in
+//! [`crate::log_buffer`] whenever a terminal has the port open. Typing `t`,
+//! `d`, `i`, `w` or `e` into the terminal switches the level to trace, debug,
+//! info, warn or error.
+//!
+//! The F411 only has three IN endpoints besides the control one, and the
+//! serial port needs two of them, so it takes the raw HID interface's place
+//! and runs as [`Task::UsbLog`] instead of `Task::RawHid`. It's the IN
+//! endpoints that run out, so moving the keyboard's LED reports to the control
+//! pipe to free its OUT endpoint wouldn't make room for both.
+
+use embassy_futures::select::select;
+use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, Receiver, Sender, State},
+    driver::EndpointError,
+    Builder
+};
+use kb_driver_core::log_sink::{self, Level};
+
+use crate::{
+    host_os::UsbDriver,
+    info, log_buffer,
+    supervisor::{self, heartbeat, Task},
+    update::Updater
+};
+
+const PACKET_SIZE: u16 = 64;
+
+/// Adds the serial port to the device
+pub fn init<'d>(
+    builder: &mut Builder<'d, UsbDriver<'d>>,
+    state: &'d mut State<'d>
+) -> CdcAcmClass<'d, UsbDriver<'d>> {
+    CdcAcmClass::new(builder, state, PACKET_SIZE)
+}
+
+/// Sends log lines and takes level changes forever
+pub async fn run(class: CdcAcmClass<'_, UsbDriver<'_>>, mut updater: Updater) -> ! {
+    let (mut sender, mut receiver) = class.split();
+    // there's no raw HID interface to do this in
+    let configured = supervisor::waiting(Task::UsbLog, sender.wait_connection());
+    updater.confirm(configured).await;
+    loop {
+        supervisor::waiting(Task::UsbLog, sender.wait_connection()).await;
+        info!("log terminal connected");
+        let _ = select(send(&mut sender), receive(&mut receiver)).await;
+    }
+}
+
+async fn send(sender: &mut Sender<'_, UsbDriver<'_>>) -> Result<(), EndpointError> {
+    // a full packet would have to be followed by an empty one
+    let mut buf = [0; PACKET_SIZE as usize - 1];
+    loop {
+        let count =
+            supervisor::waiting(Task::UsbLog, log_buffer::read(&mut buf)).await;
+        // the terminal can take its time reading it
+        let write = sender.write_packet(&buf[..count]);
+        supervisor::waiting(Task::UsbLog, write).await?;
+        heartbeat(Task::UsbLog);
+    }
+}
+
+async fn receive(
+    receiver: &mut Receiver<'_, UsbDriver<'_>>
+) -> Result<(), EndpointError> {
+    let mut buf = [0; PACKET_SIZE as usize];
+    loop {
+        let read = receiver.read_packet(&mut buf);
+        let count = supervisor::waiting(Task::UsbLog, read).await?;
+        heartbeat(Task::UsbLog);
+        for byte in &buf[..count] {
+            let level = match byte.to_ascii_lowercase() {
+                b't' => Level::Trace,
+                b'd' => Level::Debug,
+                b'i' => Level::Info,
+                b'w' => Level::Warn,
+                b'e' => Level::Error,
+                _ => continue
+            };
+            log_sink::set_level(level);
+            // at the new level, so it always shows up
+            log_sink::write(level, format_args!("log level is {}", level.name()));
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Generates [`KeyCode`] and the other HID usage enums from the usage tables in
+//! the `hid_*_data.json` files
+//!
+//! Variant names come from the names in the usage tables, the ones that were
+//! written by hand before this existed are kept in [`RENAMED`] so keymaps using
+//! them keep working.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Usage {
+    id: u16,
+    name: String,
+    /// Whether Windows (PC-AT in the usage tables), macOS and Unix support it,
+    /// only the keyboard page has these
+    pc_at: Option<bool>,
+    mac: Option<bool>,
+    unix: Option<bool>,
+    /// `dv` for the modifiers on the keyboard page
+    #[serde(rename = "type")]
+    kind: String
+}
+
+/// Keyboard page variants whose names don't follow the usage tables
+const RENAMED: &[(u16, &str)] = &[
+    (2, "KeyboardPostFail"),
+    // these two have their parentheses swapped, but they've been around for
+    // too long to fix
+    (38, "Keyboard9AndRightParentheses"),
+    (39, "Keyboard0AndLeftParentheses"),
+    (40, "KeyboardEnter"),
+    (42, "KeyboardBackspace"),
+    (47, "KeyboardLeftSquareBracketAndCurlyBracket"),
+    (48, "KeyboardRightSquareBracketAndCurlyBracket"),
+    (52, "KeyboardSingleAndDoubleQuotes"),
+    (76, "KeyboardDelete"),
+    (182, "KeypadRightParentheses"),
+    (183, "KeypadLeftParentheses"),
+    (194, "KeypadXOR"),
+    (215, "KeypadPlusMinus")
+];
+
+/// Keyboard page usages that aren't in the usage tables, along with their
+/// variant names
+const EXTRA_KEYS: &[(u16, &str, &str)] = &[
+    (0, "Keyboard no key", "KeyBoardNoKey"),
+    (232, "Keyboard Fn key", "KeyboardFn")
+];
+
+/// Extra lines for the docs of some variants
+const NOTES: &[(u16, &str)] = &[
+    (227, "A.K.A. Meta or Super or Windows or CMD"),
+    (231, "A.K.A. Meta or Super or Windows or CMD"),
+    (232, "this is a reserved value technically but idc")
+];
+
+/// How symbols are spelled out in variant names
+const SYMBOLS: &[(&str, &str)] = &[
+    ("!", "Exclamation"),
+    ("@", "At"),
+    ("#", "Sharp"),
+    ("$", "DollarSign"),
+    ("%", "Percent"),
+    ("∧", "Caret"),
+    ("&", "Ampersand"),
+    ("&&", "DoubleAmpersand"),
+    ("*", "Asterisk"),
+    ("(", "LeftParentheses"),
+    (")", "RightParentheses"),
+    ("[", "LeftSquareBracket"),
+    ("]", "RightSquareBracket"),
+    ("{", "LeftCurlyBracket"),
+    ("}", "RightCurlyBracket"),
+    ("<", "LessThan"),
+    (">", "GreaterThan"),
+    (";", "Semicolon"),
+    (":", "Colon"),
+    (".", "Period"),
+    (",", "Comma"),
+    ("?", "QuestionMark"),
+    ("+/-", "PlusOrMinus"),
+    ("+", "Plus"),
+    ("-", "Minus"),
+    ("|", "Pipe"),
+    ("||", "DoublePipe"),
+    ("=", "Equals"),
+    ("\\", "Backslash"),
+    ("/", "Slash"),
+    ("‘", "SingleQuote"),
+    ("“", "DoubleQuote"),
+    // a combining tilde, which the usage tables put after a space
+    ("\u{303}", "Tilde")
+];
+
+/// Turns a name from the usage tables into a variant name, `Keyboard a and A`
+/// becomes `KeyboardA` and `Keyboard Caps Lock` becomes `KeyboardCapsLock`
+fn variant_name(name: &str) -> String {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    if let [page, lower, "and", upper] = words[..] {
+        if lower.len() == 1 && upper == lower.to_uppercase() {
+            return format!("{page}{upper}");
+        }
+    }
+    let mut out = String::new();
+    for word in words {
+        if let Some((_, spelled)) = SYMBOLS.iter().find(|(s, _)| *s == word) {
+            out.push_str(spelled);
+            continue;
+        }
+        let word = word.trim_start_matches('(').trim_end_matches(')');
+        for part in word.split(['-', '/']).filter(|p| !p.is_empty()) {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap();
+            out.push(first.to_ascii_uppercase());
+            // all caps words like ESCAPE or GUI don't stay that way
+            if part.len() > 1 && part.chars().all(|c| !c.is_ascii_lowercase()) {
+                out.extend(chars.map(|c| c.to_ascii_lowercase()));
+            } else {
+                out.extend(chars);
+            }
+        }
+    }
+    out
+}
+
+fn support_table(usage: &Usage) -> Option<String> {
+    let mark = |supported: bool| if supported { "✅" } else { "❌" };
+    Some(format!(
+        "///\n/// | Windows | Mac | Unix |\n/// |---------|-----|------|\n/// | {} | {} \
+         | {} |\n",
+        mark(usage.pc_at?),
+        mark(usage.mac?),
+        mark(usage.unix?)
+    ))
+}
+
+struct Page<'a> {
+    ty: &'a str,
+    repr: &'a str,
+    doc: &'a str,
+    /// The variant name, the usage, and the docs
+    variants: Vec<(String, Usage, String)>
+}
+
+fn read(file: &str) -> Vec<Usage> {
+    println!("cargo:rerun-if-changed={file}");
+    let text = fs::read_to_string(file).unwrap();
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("{file}: {e}"))
+}
+
+fn page<'a>(
+    ty: &'a str,
+    repr: &'a str,
+    doc: &'a str,
+    mut usages: Vec<Usage>,
+    extra: &[(u16, &str, &str)]
+) -> Page<'a> {
+    for &(id, name, _) in extra {
+        usages.push(Usage {
+            id,
+            name: name.into(),
+            pc_at: None,
+            mac: None,
+            unix: None,
+            kind: String::new()
+        });
+    }
+    usages.sort_by_key(|usage| usage.id);
+    let variants: Vec<_> = usages
+        .into_iter()
+        .map(|usage| {
+            let name = extra
+                .iter()
+                .map(|&(id, _, name)| (id, name))
+                .chain(RENAMED.iter().copied().filter(|_| ty == "KeyCode"))
+                .find(|(id, _)| *id == usage.id)
+                .map_or_else(|| variant_name(&usage.name), |(_, name)| name.into());
+            let mut doc = format!("/// {}\n", usage.name.replace("  ", " "));
+            if let Some((_, note)) = NOTES.iter().find(|(id, _)| *id == usage.id) {
+                writeln!(doc, "/// {note}").unwrap();
+            }
+            if let Some(table) = support_table(&usage) {
+                doc.push_str(&table);
+            }
+            (name, usage, doc)
+        })
+        .collect();
+    let mut ids = HashSet::new();
+    let mut names = HashSet::new();
+    for (name, usage, _) in &variants {
+        assert!(ids.insert(usage.id), "{ty} has {} twice", usage.id);
+        assert!(names.insert(name), "{ty} has two {name}s");
+    }
+    Page {
+        ty,
+        repr,
+        doc,
+        variants
+    }
+}
+
+fn generate(page: &Page, out: &mut String) {
+    let Page {
+        ty,
+        repr,
+        doc,
+        ref variants
+    } = *page;
+
+    // usage 0 is always "nothing", so that's the default if the page has it
+    let has_default = variants.iter().any(|(_, usage, _)| usage.id == 0);
+    writeln!(out, "{doc}").unwrap();
+    if has_default {
+        out.push_str("#[derive(Default)]\n");
+    }
+    out.push_str("#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]\n");
+    out.push_str("#[cfg_attr(feature = \"defmt\", derive(defmt::Format))]\n");
+    writeln!(out, "#[repr({repr})]\npub enum {ty} {{").unwrap();
+    for (name, usage, doc) in variants {
+        for line in doc.lines() {
+            writeln!(out, "    {line}").unwrap();
+        }
+        if usage.id == 0 {
+            out.push_str("    #[default]\n");
+        }
+        writeln!(out, "    {name} = {},", usage.id).unwrap();
+    }
+    out.push_str("}\n\n");
+
+    writeln!(out, "impl {ty} {{").unwrap();
+    out.push_str("    /// Every variant, in order\n");
+    writeln!(out, "    pub const ALL: [Self; {}] = [", variants.len()).unwrap();
+    for (name, ..) in variants {
+        writeln!(out, "        Self::{name},").unwrap();
+    }
+    out.push_str("    ];\n\n");
+
+    out.push_str("    /// The variant's name, as used in keymap files\n");
+    out.push_str(
+        "    pub const fn name(self) -> &'static str {\n        match self {\n"
+    );
+    for (name, ..) in variants {
+        writeln!(out, "            Self::{name} => \"{name}\",").unwrap();
+    }
+    out.push_str("        }\n    }\n\n");
+
+    out.push_str("    /// What the HID usage tables call it\n");
+    out.push_str("    pub const fn usage_name(self) -> &'static str {\n        match self {\n");
+    for (name, usage, _) in variants {
+        writeln!(
+            out,
+            "            Self::{name} => {:?},",
+            usage.name.replace("  ", " ")
+        )
+        .unwrap();
+    }
+    out.push_str("        }\n    }\n\n");
+
+    out.push_str("    /// Finds a variant by its [`name`](Self::name)\n");
+    out.push_str("    pub fn from_name(name: &str) -> Option<Self> {\n");
+    out.push_str("        Some(match name {\n");
+    for (name, ..) in variants {
+        writeln!(out, "            \"{name}\" => Self::{name},").unwrap();
+    }
+    out.push_str("            _ => return None\n        })\n    }\n");
+
+    let supported: Vec<_> = variants
+        .iter()
+        .filter_map(|(name, u, _)| Some((name, u.pc_at?, u.mac?, u.unix?)))
+        .collect();
+    if !supported.is_empty() {
+        out.push_str(
+            "\n    /// Whether the HID usage tables say an OS supports it, `None` if \
+             they don't say\n"
+        );
+        out.push_str(
+            "    pub const fn supported_on(self, os: Os) -> Option<bool> {\n"
+        );
+        out.push_str("        let [windows, mac, unix] = match self {\n");
+        for (name, windows, mac, unix) in supported {
+            writeln!(
+                out,
+                "            Self::{name} => [{windows}, {mac}, {unix}],"
+            )
+            .unwrap();
+        }
+        out.push_str("            _ => return None\n        };\n");
+        out.push_str(
+            "        Some(match os {\n            Os::Windows => windows,\n            \
+             Os::MacOs => mac,\n            Os::Unix => unix\n        })\n    }\n"
+        );
+    }
+    if ty == "KeyCode" {
+        out.push_str("\n    /// Checks if this is one of the 8 modifier keys\n");
+        out.push_str("    pub const fn is_modifier(self) -> bool {\n");
+        let modifiers: Vec<_> = variants
+            .iter()
+            .filter(|(_, usage, _)| usage.kind == "dv")
+            .map(|(name, ..)| format!("Self::{name}"))
+            .collect();
+        writeln!(
+            out,
+            "        matches!(self, {})\n    }}",
+            modifiers.join(" | ")
+        )
+        .unwrap();
+    }
+    out.push_str("}\n\n");
+
+    writeln!(out, "impl TryFrom<{repr}> for {ty} {{").unwrap();
+    out.push_str("    type Error = ();\n\n");
+    writeln!(
+        out,
+        "    fn try_from(value: {repr}) -> Result<Self, Self::Error> {{"
+    )
+    .unwrap();
+    out.push_str("        Ok(match value {\n");
+    for (name, usage, _) in variants {
+        writeln!(out, "            {} => Self::{name},", usage.id).unwrap();
+    }
+    out.push_str("            _ => return Err(())\n        })\n    }\n}\n\n");
+
+    writeln!(out, "impl core::str::FromStr for {ty} {{").unwrap();
+    out.push_str("    type Err = UnknownName;\n\n");
+    out.push_str("    fn from_str(s: &str) -> Result<Self, Self::Err> {\n");
+    out.push_str("        Self::from_name(s).ok_or(UnknownName)\n    }\n}\n");
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let pages = [
+        (
+            "key_codes.rs",
+            page(
+                "KeyCode",
+                "u8",
+                "/// USB-HID key codes, from the keyboard/keypad page (`0x07`)",
+                read("hid_keycodes_data.json"),
+                EXTRA_KEYS
+            )
+        ),
+        (
+            "consumer.rs",
+            page(
+                "ConsumerUsage",
+                "u16",
+                "/// Media and application keys, from the consumer page (`0x0C`)",
+                read("hid_consumer_data.json"),
+                &[]
+            )
+        ),
+        (
+            "desktop.rs",
+            page(
+                "DesktopUsage",
+                "u16",
+                "/// System controls, from the generic desktop page (`0x01`)",
+                read("hid_generic_desktop_data.json"),
+                &[]
+            )
+        )
+    ];
+    for (file, page) in pages {
+        let mut out = String::new();
+        generate(&page, &mut out);
+        fs::write(out_dir.join(file), out).unwrap();
+    }
+}
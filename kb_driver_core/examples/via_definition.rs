@@ -0,0 +1,14 @@
+//! Prints the keyboard definition VIA wants, for loading it with the "Design"
+//! tab. The one in `via/palm_kb.json` comes from here:
+//!
+//! ```sh
+//! cargo run -p kb_driver_core --example via_definition > via/palm_kb.json
+//! ```
+
+use kb_driver_core::via::{self, Flavor};
+
+fn main() {
+    let mut definition = String::new();
+    via::write_definition(&mut definition, Flavor::Via).unwrap();
+    print!("{definition}");
+}
@@ -0,0 +1,195 @@
+//! Arbitrary bytes from the keyboard into [`State`], checking the same
+//! invariants as `tests/state_props.rs`
+//!
+//! The input starts with a few keymap and combo changes so tap-hold keys and
+//! combos get a go too, the rest is `(byte, 2ms ticks since the last one)`
+//! pairs. Run it with `cargo fuzz run state` from `kb_driver_core`.
+
+#![no_main]
+
+use kb_driver_core::{
+    combo::{Combo, MAX_KEYS},
+    config::Config,
+    key_codes::KeyCode,
+    keymap::{Action, LAYERS, POSITIONS},
+    report::Report,
+    state::{is_key_down, State}
+};
+use libfuzzer_sys::fuzz_target;
+
+const KEY_UP: u8 = 0x80;
+
+fn is_modifier_action(action: Action) -> bool {
+    match action {
+        Action::Key(key) | Action::LayerTap(_, key) => key.is_modifier(),
+        Action::ModTap(..) => true,
+        _ => false
+    }
+}
+
+fn reached(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+/// Takes the config changes off the front of the input
+fn config(data: &mut &[u8]) -> Config {
+    let mut config = Config::DEFAULT;
+    let Some((&counts, rest)) = data.split_first() else {
+        return config;
+    };
+    *data = rest;
+    for _ in 0..counts & 0x0F {
+        let Some(([layer, pos, lo, hi], rest)) = data.split_first_chunk() else {
+            return config;
+        };
+        *data = rest;
+        if let Some(action) = Action::from_u16(u16::from_le_bytes([*lo, *hi])) {
+            config.keymap_mut().set(
+                *layer as usize % LAYERS,
+                *pos as usize % POSITIONS,
+                action
+            );
+        }
+    }
+    for slot in config.combos.iter_mut().take((counts >> 4) as usize) {
+        let Some((chunk, rest)) = data.split_first_chunk::<{ MAX_KEYS + 2 }>()
+        else {
+            return config;
+        };
+        *data = rest;
+        let (keys, action) = chunk.split_at(MAX_KEYS);
+        let len = 2 + keys[0] as usize % (MAX_KEYS - 1);
+        if let Some(action) =
+            Action::from_u16(u16::from_le_bytes([action[0], action[1]]))
+        {
+            *slot = Combo::new(&keys[..len], action).unwrap_or(Combo::NONE);
+        }
+    }
+    config
+}
+
+/// Keeps track of what's physically held down, going by the bytes alone
+struct Harness<'c> {
+    state: State,
+    config: &'c Config,
+    now_ms: u32,
+    held: Vec<u8>,
+    /// Positions that can put modifiers in a report on some layer
+    modifier_keys: Vec<u8>,
+    last_report: Option<Report>
+}
+
+impl<'c> Harness<'c> {
+    fn new(config: &'c Config) -> Self {
+        let mut modifier_keys: Vec<u8> = (0..POSITIONS)
+            .filter(|pos| {
+                (0..LAYERS).any(|layer| {
+                    config
+                        .keymap()
+                        .get(layer, *pos)
+                        .is_some_and(is_modifier_action)
+                })
+            })
+            .map(|pos| pos as u8)
+            .collect();
+        for combo in config
+            .combos
+            .iter()
+            .filter(|c| is_modifier_action(c.action))
+        {
+            modifier_keys.extend(combo.keys());
+        }
+        Self {
+            state: State::new(),
+            config,
+            now_ms: 0,
+            held: Vec::new(),
+            modifier_keys,
+            last_report: None
+        }
+    }
+
+    /// Waits `ms`, ticking like the firmware does
+    fn wait(&mut self, ms: u32) {
+        let target = self.now_ms.wrapping_add(ms);
+        while let Some(deadline) = self.state.next_deadline() {
+            if !reached(target, deadline) {
+                break;
+            }
+            self.now_ms = deadline;
+            self.state.tick(deadline);
+            self.check(false);
+        }
+        self.now_ms = target;
+    }
+
+    /// Waits `ms`, then sends a byte
+    fn feed(&mut self, byte: u8, ms: u32) {
+        self.wait(ms);
+        let was_modifier_held = self.modifier_held();
+        self.state
+            .update_from_kb_input(byte, self.config, self.now_ms);
+        if KeyCode::try_from_matrix_key(byte).is_some() {
+            let pos = byte & !KEY_UP;
+            match is_key_down(byte) {
+                true if !self.held.contains(&pos) => self.held.push(pos),
+                true => (),
+                false => self.held.retain(|p| *p != pos)
+            }
+        }
+        self.check(was_modifier_held);
+    }
+
+    fn modifier_held(&self) -> bool {
+        self.held.iter().any(|p| self.modifier_keys.contains(p))
+    }
+
+    /// Goes through the reports queued up since last time. A key that was let
+    /// go of can still be behind the reports it caused
+    fn check(&mut self, was_modifier_held: bool) {
+        let modifier_held = was_modifier_held || self.modifier_held();
+        while let Some(report) = self.state.pop_report() {
+            let keys: Vec<u8> =
+                report.keycodes.into_iter().filter(|k| *k != 0).collect();
+            for (i, key) in keys.iter().enumerate() {
+                assert!(!keys[..i].contains(key), "{key:#04x} is in twice");
+            }
+            assert!(
+                report.modifiers.is_empty() || modifier_held,
+                "modifiers {:08b} without a modifier key held",
+                report.modifiers.bits()
+            );
+            self.last_report = Some(report);
+        }
+        while self.state.pop_command().is_some() {}
+    }
+
+    /// Lets go of everything the way the keyboard does, the last key twice
+    fn release_all(&mut self) {
+        let mut last = None;
+        while let Some(&pos) = self.held.first() {
+            self.feed(pos | KEY_UP, 10);
+            last = Some(pos);
+        }
+        if let Some(pos) = last {
+            self.feed(pos | KEY_UP, 0);
+        }
+        // let pending tap-hold keys and combos time out
+        self.wait(1000);
+        assert!(self.state.report() == Report::new(), "keys left held");
+        assert!(
+            self.last_report.is_none_or(|r| r == Report::new()),
+            "the last report wasn't empty"
+        );
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+    let config = config(&mut data);
+    let mut harness = Harness::new(&config);
+    for pair in data.chunks_exact(2) {
+        harness.feed(pair[0], pair[1] as u32 * 2);
+    }
+    harness.release_all();
+});
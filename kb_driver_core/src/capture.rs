@@ -0,0 +1,143 @@
+//! Recordings of the raw bytes the Palm keyboard sends, so whatever someone ran
+//! into can be replayed on a host
+//!
+//! A [`Capture`] keeps the newest [`CAPACITY`] bytes along with when they came
+//! in, older ones get dropped to make room. Saved traces are text, one
+//! [`Sample`] per line as milliseconds and the byte in hex (`1042 8C`), with
+//! `#` starting a comment.
+
+use core::{fmt, str::FromStr};
+
+use heapless::Deque;
+
+/// Most samples a capture keeps, about a thousand key presses
+pub const CAPACITY: usize = 2048;
+/// Size of [`Sample::to_bytes`]
+pub const SAMPLE_LEN: usize = 5;
+
+/// A byte from the keyboard's RXD line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Milliseconds since boot, same as what [`State`](crate::state::State)
+    /// gets
+    pub at_ms: u32,
+    pub byte: u8
+}
+
+impl Sample {
+    /// `[at_ms (u32), byte]`
+    pub fn to_bytes(&self) -> [u8; SAMPLE_LEN] {
+        let [a, b, c, d] = self.at_ms.to_le_bytes();
+        [a, b, c, d, self.byte]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let [a, b, c, d, byte, ..] = *bytes else {
+            return None;
+        };
+        Some(Self {
+            at_ms: u32::from_le_bytes([a, b, c, d]),
+            byte
+        })
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:02X}", self.at_ms, self.byte)
+    }
+}
+
+impl FromStr for Sample {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (at_ms, byte) = s.trim().split_once(char::is_whitespace).ok_or(())?;
+        Ok(Self {
+            at_ms: at_ms.parse().map_err(drop)?,
+            byte: u8::from_str_radix(byte.trim(), 16).map_err(drop)?
+        })
+    }
+}
+
+/// The newest bytes from the keyboard, while it's recording
+pub struct Capture {
+    samples: Deque<Sample, CAPACITY>,
+    dropped: u32,
+    recording: bool
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Capture {
+    pub const fn new() -> Self {
+        Self {
+            samples: Deque::new(),
+            dropped: 0,
+            recording: false
+        }
+    }
+
+    /// Throws the last capture away and starts a new one
+    pub fn start(&mut self) {
+        self.samples.clear();
+        self.dropped = 0;
+        self.recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Keeps a byte if it's recording, dropping the oldest one once full
+    pub fn record(&mut self, at_ms: u32, byte: u8) {
+        if !self.recording {
+            return;
+        }
+        if self.samples.is_full() {
+            self.samples.pop_front();
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        let _ = self.samples.push_back(Sample { at_ms, byte });
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// How many samples were dropped from the start to make room, a replay
+    /// starts partway through if there were any
+    #[inline]
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// The sample at `index`, oldest first
+    pub fn get(&self, index: usize) -> Option<Sample> {
+        let (front, back) = self.samples.as_slices();
+        match index.checked_sub(front.len()) {
+            None => front.get(index).copied(),
+            Some(index) => back.get(index).copied()
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Sample> + '_ {
+        self.samples.iter().copied()
+    }
+}
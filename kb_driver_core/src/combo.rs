@@ -0,0 +1,93 @@
+//! Combos, keys that do something else when pressed together
+//!
+//! Keys that are part of a combo are held back for up to [`COMBO_TERM_MS`]
+//! after going down. If the rest of the combo goes down in that time the
+//! combo's action happens instead, otherwise they're pressed like normal.
+
+use crate::keymap::{Action, POSITIONS};
+
+pub const MAX_COMBOS: usize = 8;
+/// Most keys a single combo can have
+pub const MAX_KEYS: usize = 4;
+/// How long after the first key of a combo the rest have to go down
+pub const COMBO_TERM_MS: u32 = 50;
+/// Length of a combo in its serialized form
+pub const ENCODED_LEN: usize = MAX_KEYS + 2;
+
+/// Marks an unused key slot
+pub const UNUSED: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Combo {
+    /// Matrix positions, unused slots are [`UNUSED`]
+    pub keys: [u8; MAX_KEYS],
+    pub action: Action
+}
+
+impl Combo {
+    /// A combo that never happens
+    pub const NONE: Self = Self {
+        keys: [UNUSED; MAX_KEYS],
+        action: Action::None
+    };
+
+    /// Makes a combo out of 2 to [`MAX_KEYS`] different positions
+    pub fn new(keys: &[u8], action: Action) -> Option<Self> {
+        if !(2..=MAX_KEYS).contains(&keys.len()) {
+            return None;
+        }
+        let mut out = [UNUSED; MAX_KEYS];
+        for (i, &key) in keys.iter().enumerate() {
+            if key as usize >= POSITIONS || keys[..i].contains(&key) {
+                return None;
+            }
+            out[i] = key;
+        }
+        Some(Self { keys: out, action })
+    }
+
+    /// The positions in the combo, in the order they were given
+    pub fn keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys.iter().copied().filter(|k| *k != UNUSED)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys().count()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn contains(&self, pos: u8) -> bool {
+        pos != UNUSED && self.keys.contains(&pos)
+    }
+
+    /// The keys followed by the action as a little endian QMK keycode
+    pub fn to_bytes(&self) -> [u8; ENCODED_LEN] {
+        let mut out = [0u8; ENCODED_LEN];
+        out[..MAX_KEYS].copy_from_slice(&self.keys);
+        out[MAX_KEYS..].copy_from_slice(&self.action.to_u16().to_le_bytes());
+        out
+    }
+
+    /// Reads what [`to_bytes`](Self::to_bytes) wrote, actions we can't do
+    /// turn the combo off
+    pub fn from_bytes(bytes: &[u8; ENCODED_LEN]) -> Self {
+        let mut keys = [UNUSED; MAX_KEYS];
+        keys.copy_from_slice(&bytes[..MAX_KEYS]);
+        match Action::from_u16(u16::from_le_bytes([bytes[4], bytes[5]])) {
+            Some(action) => Self { keys, action },
+            None => Self::NONE
+        }
+    }
+}
+
+impl Default for Combo {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
@@ -0,0 +1,369 @@
+//! Everything about the keyboard that can be changed at runtime, and how it's
+//! serialized for [`Store`]
+//!
+//! The serialized form is a list of tag, length, value entries so fields can
+//! be added without breaking old configs, unknown tags are skipped and missing
+//! ones keep their defaults. Anything that changes the meaning of an existing
+//! tag has to bump [`SCHEMA_VERSION`] and add a step to [`migrate`], so
+//! configs saved by older firmware still load after an update.
+//!
+//! | version | change                                                      |
+//! |---------|-------------------------------------------------------------|
+//! | 1       | first one                                                   |
+//! | 2       | keymaps are per profile, `TAG_KEYMAP_LAYER` is only read    |
+
+use crate::{
+    combo::{self, Combo, MAX_COMBOS},
+    host_os::{HostOs, HOST_OSES},
+    keymap::{Action, Keymap, LAYERS, POSITIONS},
+    macros::{Macros, BUFFER_LEN},
+    power::Policy,
+    profile::{ModifierSwaps, Profile, UnicodeMode, NAME_LEN, PROFILES},
+    warn
+};
+
+pub use self::store::Store;
+
+pub mod store;
+
+/// Version of the serialized config, stored next to it in flash
+pub const SCHEMA_VERSION: u16 = 2;
+/// Biggest a serialized config can get
+pub const MAX_ENCODED_LEN: usize = (3 + 1)
+    + (3 + 2)
+    + PROFILES * (LAYERS * (3 + 2 + POSITIONS * 2) + (3 + 3 + NAME_LEN) + (3 + 2))
+    + (3 + BUFFER_LEN)
+    + (3 + 4)
+    + (3 + MAX_COMBOS * combo::ENCODED_LEN)
+    + (3 + 1 + HOST_OSES)
+    + (3 + 4);
+
+const TAG_PROFILE: u8 = 1;
+const TAG_TAPPING_TERM: u8 = 2;
+/// A layer of the keymap from before there were profiles, which goes to
+/// every profile. Only in version 1, and only ever read
+const TAG_KEYMAP_LAYER: u8 = 3;
+const TAG_MACROS: u8 = 4;
+const TAG_LAYOUT_OPTIONS: u8 = 5;
+const TAG_COMBOS: u8 = 6;
+const TAG_PROFILE_LAYER: u8 = 7;
+const TAG_PROFILE_SETTINGS: u8 = 8;
+const TAG_HOST_DETECTION: u8 = 9;
+const TAG_PROFILE_APPLE_FN: u8 = 10;
+const TAG_POWER: u8 = 11;
+
+/// Positions that reset the config to defaults when held down together:
+/// Fn, CMD and Backspace
+pub const FACTORY_RESET_COMBO: [u8; 3] = [34, 8, 50];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Doesn't fit in the buffer it's being written to
+    TooLarge,
+    /// An entry is cut off or has the wrong length
+    Malformed,
+    /// Written by newer firmware, which we can't know how to read
+    UnsupportedVersion(u16)
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Which of [`profiles`](Self::profiles) is in use
+    pub profile: u8,
+    /// How long a tap-hold key has to be held for it to count as a hold
+    pub tapping_term_ms: u16,
+    pub profiles: [Profile; PROFILES],
+    pub macros: Macros,
+    /// VIA's layout options, only stored so VIA gets back what it set
+    pub layout_options: u32,
+    /// Keys that do something else when pressed together
+    pub combos: [Combo; MAX_COMBOS],
+    /// Switch to the profile for the host OS when plugged in, otherwise
+    /// [`profile`](Self::profile) always sticks
+    pub detect_host: bool,
+    /// The profile to use for each [`HostOs`]
+    pub host_profiles: [u8; HOST_OSES],
+    /// What happens to the keyboard while suspended or idle
+    pub power: Policy
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        profile: 0,
+        tapping_term_ms: 200,
+        profiles: Profile::DEFAULTS,
+        macros: Macros::EMPTY,
+        layout_options: 0,
+        combos: [Combo::NONE; MAX_COMBOS],
+        detect_host: true,
+        // macOS, Windows, Linux and iOS, in the order of `Profile::DEFAULTS`
+        host_profiles: [0, 1, 2, 0],
+        power: Policy::ALWAYS_ON
+    };
+
+    /// The profile in use, the first one if [`profile`](Self::profile) is
+    /// out of range
+    #[inline]
+    pub fn active_profile(&self) -> &Profile {
+        self.profiles
+            .get(self.profile as usize)
+            .unwrap_or(&self.profiles[0])
+    }
+
+    #[inline]
+    pub fn active_profile_mut(&mut self) -> &mut Profile {
+        let index = (self.profile as usize).min(PROFILES - 1);
+        &mut self.profiles[index]
+    }
+
+    /// The keymap of the profile in use
+    #[inline]
+    pub fn keymap(&self) -> &Keymap {
+        &self.active_profile().keymap
+    }
+
+    #[inline]
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.active_profile_mut().keymap
+    }
+
+    /// The profile to switch to when plugged into `os`
+    #[inline]
+    pub fn profile_for(&self, os: HostOs) -> u8 {
+        self.host_profiles[os as usize]
+    }
+
+    /// Serializes the config into `buf`, returning how many bytes were written
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut writer = Writer { buf, len: 0 };
+        writer.entry(TAG_PROFILE, &[self.profile])?;
+        writer.entry(TAG_TAPPING_TERM, &self.tapping_term_ms.to_le_bytes())?;
+        for (p, profile) in self.profiles.iter().enumerate() {
+            let mut settings = [0u8; 3 + NAME_LEN];
+            settings[0] = p as u8;
+            settings[1] = profile.swaps.bits();
+            settings[2] = profile.unicode as u8;
+            settings[3..].copy_from_slice(&profile.name);
+            writer.entry(TAG_PROFILE_SETTINGS, &settings)?;
+            writer
+                .entry(TAG_PROFILE_APPLE_FN, &[p as u8, profile.apple_fn as u8])?;
+            for (l, layer) in profile.keymap.layers.iter().enumerate() {
+                let mut value = [0u8; 2 + POSITIONS * 2];
+                value[0] = p as u8;
+                value[1] = l as u8;
+                encode_layer(layer, &mut value[2..]);
+                writer.entry(TAG_PROFILE_LAYER, &value)?;
+            }
+        }
+        writer.entry(TAG_MACROS, &self.macros.buffer)?;
+        writer.entry(TAG_LAYOUT_OPTIONS, &self.layout_options.to_le_bytes())?;
+        let mut combos = [0u8; MAX_COMBOS * combo::ENCODED_LEN];
+        let (chunks, _) = combos.as_chunks_mut::<{ combo::ENCODED_LEN }>();
+        for (chunk, combo) in chunks.iter_mut().zip(&self.combos) {
+            *chunk = combo.to_bytes();
+        }
+        writer.entry(TAG_COMBOS, &combos)?;
+        let mut detection = [0u8; 1 + HOST_OSES];
+        detection[0] = self.detect_host as u8;
+        detection[1..].copy_from_slice(&self.host_profiles);
+        writer.entry(TAG_HOST_DETECTION, &detection)?;
+        writer.entry(TAG_POWER, &self.power.to_bytes())?;
+        Ok(writer.len)
+    }
+
+    /// Reads a config serialized by any schema version up to
+    /// [`SCHEMA_VERSION`]
+    pub fn decode(version: u16, data: &[u8]) -> Result<Self, Error> {
+        if version == 0 || version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let mut config = Self::DEFAULT;
+        let mut shared_layers = 0u8;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let [tag, len_lo, len_hi, ref tail @ ..] = *rest else {
+                return Err(Error::Malformed);
+            };
+            let len = u16::from_le_bytes([len_lo, len_hi]) as usize;
+            if tail.len() < len {
+                return Err(Error::Malformed);
+            }
+            let (value, tail) = tail.split_at(len);
+            if version < 2 && tag == TAG_KEYMAP_LAYER {
+                // goes into the first profile for now, see `migrate`
+                let [layer, ref actions @ ..] = *value else {
+                    return Err(Error::Malformed);
+                };
+                decode_layer(&mut config.profiles[0].keymap, layer, actions)?;
+                if (layer as usize) < LAYERS {
+                    shared_layers |= 1 << layer;
+                }
+            } else {
+                config.apply(tag, value)?;
+            }
+            rest = tail;
+        }
+        migrate(&mut config, version, shared_layers);
+        Ok(config)
+    }
+
+    fn apply(&mut self, tag: u8, value: &[u8]) -> Result<(), Error> {
+        match tag {
+            TAG_PROFILE => {
+                let [profile] = *value else {
+                    return Err(Error::Malformed);
+                };
+                self.profile = profile;
+            }
+            TAG_TAPPING_TERM => {
+                let [lo, hi] = *value else {
+                    return Err(Error::Malformed);
+                };
+                self.tapping_term_ms = u16::from_le_bytes([lo, hi]);
+            }
+            TAG_PROFILE_LAYER => {
+                let [profile, layer, ref actions @ ..] = *value else {
+                    return Err(Error::Malformed);
+                };
+                let Some(profile) = self.profiles.get_mut(profile as usize) else {
+                    warn!("skipping profile {} that doesn't exist", profile);
+                    return Ok(());
+                };
+                decode_layer(&mut profile.keymap, layer, actions)?;
+            }
+            TAG_PROFILE_SETTINGS => {
+                let [profile, swaps, unicode, ref name @ ..] = *value else {
+                    return Err(Error::Malformed);
+                };
+                let Some(profile) = self.profiles.get_mut(profile as usize) else {
+                    warn!("skipping profile {} that doesn't exist", profile);
+                    return Ok(());
+                };
+                profile.swaps = ModifierSwaps::from_bits_truncate(swaps);
+                if let Ok(unicode) = UnicodeMode::try_from(unicode) {
+                    profile.unicode = unicode;
+                }
+                let len = name.len().min(NAME_LEN);
+                profile.name = [0; NAME_LEN];
+                profile.name[..len].copy_from_slice(&name[..len]);
+            }
+            TAG_PROFILE_APPLE_FN => {
+                let [profile, apple_fn] = *value else {
+                    return Err(Error::Malformed);
+                };
+                let Some(profile) = self.profiles.get_mut(profile as usize) else {
+                    warn!("skipping profile {} that doesn't exist", profile);
+                    return Ok(());
+                };
+                profile.apple_fn = apple_fn != 0;
+            }
+            TAG_MACROS => {
+                let len = value.len().min(BUFFER_LEN);
+                self.macros.buffer = [0; BUFFER_LEN];
+                self.macros.buffer[..len].copy_from_slice(&value[..len]);
+            }
+            TAG_LAYOUT_OPTIONS => {
+                let Ok(bytes) = value.try_into() else {
+                    return Err(Error::Malformed);
+                };
+                self.layout_options = u32::from_le_bytes(bytes);
+            }
+            TAG_COMBOS => {
+                let (chunks, []) = value.as_chunks::<{ combo::ENCODED_LEN }>()
+                else {
+                    return Err(Error::Malformed);
+                };
+                self.combos = [Combo::NONE; MAX_COMBOS];
+                for (slot, chunk) in self.combos.iter_mut().zip(chunks) {
+                    *slot = Combo::from_bytes(chunk);
+                }
+            }
+            TAG_HOST_DETECTION => {
+                let [enabled, ref profiles @ ..] = *value else {
+                    return Err(Error::Malformed);
+                };
+                self.detect_host = enabled != 0;
+                // OSes that got added since keep their defaults
+                for (slot, profile) in self.host_profiles.iter_mut().zip(profiles) {
+                    *slot = *profile;
+                }
+            }
+            TAG_POWER => {
+                let Ok(bytes) = value.try_into() else {
+                    return Err(Error::Malformed);
+                };
+                self.power = Policy::from_bytes(bytes);
+            }
+            _ => warn!("skipping unknown config tag {}", tag)
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Brings a config decoded from schema version `from` up to date, one version
+/// at a time. `shared_layers` has a bit set for every layer a version 1 config
+/// had from before there were profiles
+fn migrate(config: &mut Config, from: u16, shared_layers: u8) {
+    if from < 2 {
+        // the shared layers were decoded into the first profile, and back then
+        // every profile used them
+        let (first, others) = config.profiles.split_at_mut(1);
+        for (layer, actions) in first[0].keymap.layers.iter().enumerate() {
+            if shared_layers & (1 << layer) == 0 {
+                continue;
+            }
+            for profile in others.iter_mut() {
+                profile.keymap.layers[layer] = *actions;
+            }
+        }
+    }
+}
+
+fn encode_layer(layer: &[Action; POSITIONS], out: &mut [u8]) {
+    let (chunks, _) = out.as_chunks_mut::<2>();
+    for (chunk, action) in chunks.iter_mut().zip(layer) {
+        *chunk = action.to_u16().to_le_bytes();
+    }
+}
+
+fn decode_layer(
+    keymap: &mut Keymap,
+    layer: u8,
+    actions: &[u8]
+) -> Result<(), Error> {
+    let (chunks, []) = actions.as_chunks::<2>() else {
+        return Err(Error::Malformed);
+    };
+    let Some(layer) = keymap.layers.get_mut(layer as usize) else {
+        warn!("skipping keymap layer {} that doesn't exist", layer);
+        return Ok(());
+    };
+    for (slot, &chunk) in layer.iter_mut().zip(chunks) {
+        *slot = Action::from_u16(u16::from_le_bytes(chunk)).unwrap_or_default();
+    }
+    Ok(())
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize
+}
+
+impl Writer<'_> {
+    fn entry(&mut self, tag: u8, value: &[u8]) -> Result<(), Error> {
+        let end = self.len + 3 + value.len();
+        let out = self.buf.get_mut(self.len..end).ok_or(Error::TooLarge)?;
+        out[0] = tag;
+        out[1..3].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        out[3..].copy_from_slice(value);
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Keeps the config in flash
+//!
+//! The reserved flash is split into two banks. Every save appends a new record
+//! to the active bank, and once it's full the other bank gets erased and
+//! becomes the active one, so a save that gets interrupted never takes the
+//! previous config with it.
+//!
+//! With every profile's keymap in it a record is about 3K (see
+//! [`MAX_ENCODED_LEN`]), so the firmware's 16K banks take 5 of them and every
+//! 5th save erases a bank, each bank every 10th. The F411's flash is good for
+//! 10,000 erases, so that's around 100,000 saves. VIA commits on every
+//! `SET_KEYCODE`, which the firmware only holds back until they stop coming
+//! for a moment, so remapping a layer key by key in VIA can easily take a few
+//! dozen of those. A record looks like this, padded with `0xFF` to a multiple
+//! of [`ALIGN`]:
+//!
+//! | offset | size | content                                            |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | magic, `PKCF`                                      |
+//! | 4      | 4    | sequence number, bigger is newer                   |
+//! | 8      | 2    | [`SCHEMA_VERSION`] it was written with             |
+//! | 10     | 2    | length of the config                               |
+//! | 12     | 4    | CRC-32 of bytes 4..12 followed by the config       |
+//! | 16     | len  | the config, see [`Config::encode`]                 |
+
+use embedded_storage::nor_flash::NorFlash;
+
+use super::{Config, Error as ConfigError, MAX_ENCODED_LEN, SCHEMA_VERSION};
+use crate::{crc::Crc32, info, warn};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"PKCF");
+const HEADER_LEN: usize = 16;
+/// Records start and end on multiples of this, it has to be a multiple of the
+/// flash's write size
+pub const ALIGN: usize = 16;
+const RECORD_CAPACITY: usize =
+    (HEADER_LEN + MAX_ENCODED_LEN).next_multiple_of(ALIGN);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Flash(E),
+    Config(ConfigError)
+}
+
+pub struct Store<F: NorFlash> {
+    flash: F,
+    base: u32,
+    bank_size: u32,
+    active: u32,
+    /// Where the next record goes in the active bank
+    write_offset: u32,
+    /// The active bank has a record that didn't finish writing, so nothing
+    /// can be appended after it
+    torn: bool,
+    seq: u32,
+    /// CRC of the last config saved or loaded, to skip saving it again
+    last_crc: Option<u32>
+}
+
+struct Header {
+    seq: u32,
+    version: u16,
+    len: u16,
+    crc: u32
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        out[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        out[8..10].copy_from_slice(&self.version.to_le_bytes());
+        out[10..12].copy_from_slice(&self.len.to_le_bytes());
+        out[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        out
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Option<Self> {
+        let word = |i: usize| {
+            u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+        };
+        let header = Self {
+            seq: word(4),
+            version: u16::from_le_bytes([bytes[8], bytes[9]]),
+            len: u16::from_le_bytes([bytes[10], bytes[11]]),
+            crc: word(12)
+        };
+        (word(0) == MAGIC && header.len as usize <= MAX_ENCODED_LEN)
+            .then_some(header)
+    }
+
+    fn checksum(bytes: &[u8; HEADER_LEN], data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&bytes[4..12]);
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+/// What scanning a bank found
+struct Scan {
+    /// Offset and header of the newest valid record
+    latest: Option<(u32, Header)>,
+    end: u32,
+    torn: bool
+}
+
+#[inline]
+fn is_newer(seq: u32, than: u32) -> bool {
+    (seq.wrapping_sub(than) as i32) > 0
+}
+
+#[inline]
+fn record_len(data_len: usize) -> u32 {
+    (HEADER_LEN + data_len).next_multiple_of(ALIGN) as u32
+}
+
+impl<F: NorFlash> Store<F> {
+    /// Uses the `2 * bank_size` bytes of `flash` starting at `base`, both have
+    /// to line up with the flash's erase size
+    pub fn new(flash: F, base: u32, bank_size: u32) -> Self {
+        assert!(ALIGN.is_multiple_of(F::WRITE_SIZE));
+        assert!(ALIGN.is_multiple_of(F::READ_SIZE));
+        assert!((base as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!((bank_size as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(bank_size as usize >= RECORD_CAPACITY);
+        Self {
+            flash,
+            base,
+            bank_size,
+            active: 0,
+            write_offset: 0,
+            torn: false,
+            seq: 0,
+            last_crc: None
+        }
+    }
+
+    /// Gives the flash back
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    #[inline]
+    fn bank_start(&self, bank: u32) -> u32 {
+        self.base + bank * self.bank_size
+    }
+
+    fn scan(&mut self, bank: u32, buf: &mut [u8]) -> Result<Scan, Error<F::Error>> {
+        let start = self.bank_start(bank);
+        let mut scan = Scan {
+            latest: None,
+            end: 0,
+            torn: false
+        };
+        while scan.end as usize + HEADER_LEN <= self.bank_size as usize {
+            let mut bytes = [0u8; HEADER_LEN];
+            self.flash
+                .read(start + scan.end, &mut bytes)
+                .map_err(Error::Flash)?;
+            if bytes.iter().all(|b| *b == 0xFF) {
+                break;
+            }
+            let Some(header) = Header::from_bytes(&bytes) else {
+                scan.torn = true;
+                break;
+            };
+            let len = record_len(header.len as usize);
+            if scan.end + len > self.bank_size {
+                scan.torn = true;
+                break;
+            }
+            let data = &mut buf[..len as usize - HEADER_LEN];
+            self.flash
+                .read(start + scan.end + HEADER_LEN as u32, data)
+                .map_err(Error::Flash)?;
+            if Header::checksum(&bytes, &data[..header.len as usize]) != header.crc {
+                scan.torn = true;
+                break;
+            }
+            scan.latest = Some((scan.end, header));
+            scan.end += len;
+        }
+        Ok(scan)
+    }
+
+    /// Finds the newest config in flash, `None` if there isn't one that can be
+    /// read
+    pub fn load(&mut self) -> Result<Option<Config>, Error<F::Error>> {
+        let mut buf = [0u8; RECORD_CAPACITY];
+        let scans = [self.scan(0, &mut buf)?, self.scan(1, &mut buf)?];
+        let active = match (&scans[0].latest, &scans[1].latest) {
+            (Some((_, a)), Some((_, b))) if is_newer(b.seq, a.seq) => 1,
+            (None, Some(_)) => 1,
+            _ => 0
+        };
+        let [a, b] = scans;
+        let scan = if active == 0 { a } else { b };
+        self.active = active;
+        self.write_offset = scan.end;
+        self.torn = scan.torn;
+
+        let Some((offset, header)) = scan.latest else {
+            info!("no config in flash");
+            self.seq = 0;
+            self.last_crc = None;
+            return Ok(None);
+        };
+        self.seq = header.seq;
+        let data = &mut buf[..header.len as usize];
+        self.flash
+            .read(self.bank_start(active) + offset + HEADER_LEN as u32, data)
+            .map_err(Error::Flash)?;
+        match Config::decode(header.version, data) {
+            Ok(config) => {
+                self.last_crc = Some(crate::crc::crc32(data));
+                Ok(Some(config))
+            }
+            Err(e) => {
+                warn!("config in flash can't be read: {}", e);
+                self.last_crc = None;
+                Err(Error::Config(e))
+            }
+        }
+    }
+
+    /// Writes a config to flash, unless it's the same as the last one
+    pub fn save(&mut self, config: &Config) -> Result<(), Error<F::Error>> {
+        let mut buf = [0xFFu8; RECORD_CAPACITY];
+        let len = config
+            .encode(&mut buf[HEADER_LEN..HEADER_LEN + MAX_ENCODED_LEN])
+            .map_err(Error::Config)?;
+        let crc = crate::crc::crc32(&buf[HEADER_LEN..HEADER_LEN + len]);
+        if self.last_crc == Some(crc) {
+            return Ok(());
+        }
+        // the encoder doesn't touch the bytes after what it wrote, so the
+        // padding is still 0xFF
+        let record_len = record_len(len);
+
+        if self.torn || self.write_offset + record_len > self.bank_size {
+            let next = 1 - self.active;
+            info!("switching config to bank {}", next);
+            let start = self.bank_start(next);
+            self.flash
+                .erase(start, start + self.bank_size)
+                .map_err(Error::Flash)?;
+            self.active = next;
+            self.write_offset = 0;
+            self.torn = false;
+        }
+
+        let seq = self.seq.wrapping_add(1);
+        let mut header = Header {
+            seq,
+            version: SCHEMA_VERSION,
+            len: len as u16,
+            crc: 0
+        };
+        let (head, data) = buf.split_at_mut(HEADER_LEN);
+        header.crc = Header::checksum(&header.to_bytes(), &data[..len]);
+        head.copy_from_slice(&header.to_bytes());
+
+        let offset = self.bank_start(self.active) + self.write_offset;
+        if let Err(e) = self.flash.write(offset, &buf[..record_len as usize]) {
+            // whatever made it to flash is garbage now
+            self.torn = true;
+            return Err(Error::Flash(e));
+        }
+        self.write_offset += record_len;
+        self.seq = seq;
+        self.last_crc = Some(crc);
+        Ok(())
+    }
+
+    /// Erases every saved config, the next [`load`](Self::load) won't find
+    /// anything
+    pub fn factory_reset(&mut self) -> Result<(), Error<F::Error>> {
+        info!("erasing config");
+        self.flash
+            .erase(self.base, self.bank_start(2))
+            .map_err(Error::Flash)?;
+        self.active = 0;
+        self.write_offset = 0;
+        self.torn = false;
+        self.seq = 0;
+        self.last_crc = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! What the firmware remembers about its last crash, and how that's sent to
+//! the host
+
+pub const MESSAGE_LEN: usize = 96;
+pub const FILE_LEN: usize = 48;
+pub const EVENT_COUNT: usize = 16;
+/// Size of a serialized [`CrashReport`]
+pub const REPORT_LEN: usize = 12 + MESSAGE_LEN + FILE_LEN + EVENT_COUNT * 6;
+
+/// Things that happen in the driver that are worth knowing about after a crash
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum EventKind {
+    /// data is the reset reason as numbered by the firmware's supervisor
+    Boot = 1,
+    UsbConfigured = 2,
+    UsbDeconfigured = 3,
+    UsbSuspended = 4,
+    UsbResumed = 5,
+    KbConnected = 6,
+    KbHandshakeFailed = 7,
+    KbIdle = 8,
+    /// data is what kind of UART error it was
+    UartError = 9,
+    /// data is the task that exited, in the firmware's task order
+    TaskExited = 10
+}
+
+impl TryFrom<u8> for EventKind {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => Self::Boot,
+            2 => Self::UsbConfigured,
+            3 => Self::UsbDeconfigured,
+            4 => Self::UsbSuspended,
+            5 => Self::UsbResumed,
+            6 => Self::KbConnected,
+            7 => Self::KbHandshakeFailed,
+            8 => Self::KbIdle,
+            9 => Self::UartError,
+            10 => Self::TaskExited,
+            _ => return Err(())
+        })
+    }
+}
+
+/// A single entry of the event ring buffer, kept as raw values since the RAM
+/// it lives in can hold anything after a power cycle. A `kind` of 0 is an empty
+/// slot
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct Event {
+    pub timestamp_ms: u32,
+    pub kind: u8,
+    pub data: u8
+}
+
+/// What the last crash looked like, events are sorted oldest first
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CrashReport {
+    message: [u8; MESSAGE_LEN],
+    message_len: u8,
+    file: [u8; FILE_LEN],
+    file_len: u8,
+    pub line: u32,
+    pub column: u32,
+    events: [Event; EVENT_COUNT],
+    event_count: u8
+}
+
+impl CrashReport {
+    /// Builds a report, cutting off whatever doesn't fit and skipping events
+    /// that aren't a known [`EventKind`]
+    pub fn new(
+        message: &[u8],
+        file: &[u8],
+        line: u32,
+        column: u32,
+        events: impl IntoIterator<Item = Event>
+    ) -> Self {
+        let mut report = Self {
+            message: [0; MESSAGE_LEN],
+            message_len: message.len().min(MESSAGE_LEN) as u8,
+            file: [0; FILE_LEN],
+            file_len: file.len().min(FILE_LEN) as u8,
+            line,
+            column,
+            events: [Event::default(); EVENT_COUNT],
+            event_count: 0
+        };
+        report.message[..report.message_len as usize]
+            .copy_from_slice(&message[..report.message_len as usize]);
+        report.file[..report.file_len as usize]
+            .copy_from_slice(&file[..report.file_len as usize]);
+        for event in events
+            .into_iter()
+            .filter(|e| EventKind::try_from(e.kind).is_ok())
+            .take(EVENT_COUNT)
+        {
+            report.events[report.event_count as usize] = event;
+            report.event_count += 1;
+        }
+        report
+    }
+
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len as usize])
+            .unwrap_or("<invalid utf-8>")
+    }
+
+    pub fn file(&self) -> &str {
+        core::str::from_utf8(&self.file[..self.file_len as usize])
+            .unwrap_or("<invalid utf-8>")
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events[..self.event_count as usize]
+    }
+
+    /// Serializes the report for reading over USB, everything is little endian:
+    ///
+    /// | offset | size | content                                  |
+    /// |--------|------|------------------------------------------|
+    /// | 0      | 1    | format version, always 1                 |
+    /// | 1      | 1    | message length                           |
+    /// | 2      | 1    | file length                              |
+    /// | 3      | 1    | event count                              |
+    /// | 4      | 4    | line                                     |
+    /// | 8      | 4    | column                                   |
+    /// | 12     | 96   | message                                  |
+    /// | 108    | 48   | file                                     |
+    /// | 156    | 96   | events, 4 byte timestamp + kind + data   |
+    pub fn to_bytes(&self) -> [u8; REPORT_LEN] {
+        let mut out = [0u8; REPORT_LEN];
+        out[0] = 1;
+        out[1] = self.message_len;
+        out[2] = self.file_len;
+        out[3] = self.event_count;
+        out[4..8].copy_from_slice(&self.line.to_le_bytes());
+        out[8..12].copy_from_slice(&self.column.to_le_bytes());
+        let (message, rest) = out[12..].split_at_mut(MESSAGE_LEN);
+        message.copy_from_slice(&self.message);
+        let (file, events) = rest.split_at_mut(FILE_LEN);
+        file.copy_from_slice(&self.file);
+        let (chunks, _) = events.as_chunks_mut::<6>();
+        for (chunk, event) in chunks.iter_mut().zip(self.events()) {
+            let [a, b, c, d] = event.timestamp_ms.to_le_bytes();
+            *chunk = [a, b, c, d, event.kind, event.data];
+        }
+        out
+    }
+
+    /// Reads what [`to_bytes`](Self::to_bytes) wrote, `None` if it's cut off
+    /// or from a format version we don't know
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; REPORT_LEN] = bytes.get(..REPORT_LEN)?.try_into().ok()?;
+        let [1, message_len, file_len, event_count, ..] = *bytes else {
+            return None;
+        };
+        let word = |i: usize| {
+            u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+        };
+        let (message, rest) = bytes[12..].split_at(MESSAGE_LEN);
+        let (file, events) = rest.split_at(FILE_LEN);
+        let (events, _) = events.as_chunks::<6>();
+        let events = events.iter().take(event_count as usize).map(
+            |&[a, b, c, d, kind, data]| Event {
+                timestamp_ms: u32::from_le_bytes([a, b, c, d]),
+                kind,
+                data
+            }
+        );
+        Some(Self::new(
+            message.get(..message_len as usize)?,
+            file.get(..file_len as usize)?,
+            word(4),
+            word(8),
+            events
+        ))
+    }
+}
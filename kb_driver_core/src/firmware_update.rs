@@ -0,0 +1,139 @@
+//! Writing a new firmware image into the DFU slot, for the bootloader to swap
+//! in on the next reset
+//!
+//! The image comes in over USB in small chunks, so nothing is checked until
+//! all of it is in flash: [`UpdateSlot::finish`] reads it back and compares
+//! its CRC-32 with the one the host sent. Only an image that made it through
+//! that should be handed to the bootloader.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::Crc32;
+
+/// Most image bytes in one write, a multiple of every write size the flash
+/// could have so chunks never straddle a write
+pub const MAX_CHUNK: usize = 24;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Flash(E),
+    /// The firmware can't take an update right now
+    Busy,
+    /// The image doesn't fit in the slot
+    TooLarge,
+    /// Nothing was begun, or the chunk is out of place
+    OutOfRange,
+    /// What got written doesn't match the CRC the host sent
+    Mismatch
+}
+
+impl<E> Error<E> {
+    /// Turns the flash's error into something else, keeping the rest
+    pub fn map_flash<T>(self, f: impl FnOnce(E) -> T) -> Error<T> {
+        match self {
+            Self::Flash(e) => Error::Flash(f(e)),
+            Self::Busy => Error::Busy,
+            Self::TooLarge => Error::TooLarge,
+            Self::OutOfRange => Error::OutOfRange,
+            Self::Mismatch => Error::Mismatch
+        }
+    }
+}
+
+pub struct UpdateSlot<F: NorFlash> {
+    flash: F,
+    base: u32,
+    capacity: u32,
+    /// Length of the image being written, if there is one
+    len: Option<u32>
+}
+
+impl<F: NorFlash> UpdateSlot<F> {
+    /// `base` and `capacity` have to be multiples of the flash's erase size
+    pub fn new(flash: F, base: u32, capacity: u32) -> Self {
+        assert!(base.is_multiple_of(F::ERASE_SIZE as u32));
+        assert!(capacity.is_multiple_of(F::ERASE_SIZE as u32));
+        assert!(MAX_CHUNK.is_multiple_of(F::WRITE_SIZE));
+        Self {
+            flash,
+            base,
+            capacity,
+            len: None
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// Erases as much of the slot as an image of `len` bytes needs, throwing
+    /// away whatever was being written before
+    pub fn begin(&mut self, len: u32) -> Result<(), Error<F::Error>> {
+        self.len = None;
+        if len == 0 || len > self.capacity {
+            return Err(Error::TooLarge);
+        }
+        let end = len.next_multiple_of(F::ERASE_SIZE as u32);
+        self.flash
+            .erase(self.base, self.base + end)
+            .map_err(Error::Flash)?;
+        self.len = Some(len);
+        Ok(())
+    }
+
+    /// Writes part of the image, `offset` has to be a multiple of
+    /// [`MAX_CHUNK`] and only the last chunk can be shorter than that
+    pub fn write(
+        &mut self,
+        offset: u32,
+        data: &[u8]
+    ) -> Result<(), Error<F::Error>> {
+        let Some(len) = self.len else {
+            return Err(Error::OutOfRange);
+        };
+        let end = offset as usize + data.len();
+        if !offset.is_multiple_of(MAX_CHUNK as u32)
+            || data.len() > MAX_CHUNK
+            || end > len as usize
+            || (data.len() < MAX_CHUNK && end != len as usize)
+        {
+            return Err(Error::OutOfRange);
+        }
+        // the last chunk gets padded to the write size, with bytes that leave
+        // erased flash as it was
+        let mut buf = [0xFF; MAX_CHUNK];
+        buf[..data.len()].copy_from_slice(data);
+        let padded = data.len().next_multiple_of(F::WRITE_SIZE);
+        self.flash
+            .write(self.base + offset, &buf[..padded])
+            .map_err(Error::Flash)
+    }
+
+    /// Checks the image against `crc`, the slot can't be written to anymore
+    /// afterwards either way
+    pub fn finish(&mut self, crc: u32) -> Result<u32, Error<F::Error>> {
+        let Some(len) = self.len.take() else {
+            return Err(Error::OutOfRange);
+        };
+        let mut actual = Crc32::new();
+        let mut buf = [0; 64];
+        let mut offset = 0;
+        while offset < len {
+            let count = (len - offset).min(buf.len() as u32) as usize;
+            self.flash
+                .read(self.base + offset, &mut buf[..count])
+                .map_err(Error::Flash)?;
+            actual.update(&buf[..count]);
+            offset += count as u32;
+        }
+        match actual.finish() == crc {
+            true => Ok(len),
+            false => Err(Error::Mismatch)
+        }
+    }
+}
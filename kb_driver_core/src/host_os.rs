@@ -0,0 +1,164 @@
+//! Guessing the host OS from the way it enumerates the keyboard
+//!
+//! Nothing in USB says what the host is, but every OS asks for descriptors in
+//! its own way, mostly in how many bytes it asks for at a time. The firmware
+//! feeds every SETUP packet it gets into a [`Fingerprint`] and switches to the
+//! profile [`Config::host_profiles`](crate::config::Config::host_profiles)
+//! has for whatever it guesses, unless that's been switched off.
+
+use heapless::Vec;
+
+/// Descriptor type of configuration descriptors, in `wValue`'s high byte
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+/// Descriptor type of string descriptors, in `wValue`'s high byte
+pub const DESCRIPTOR_STRING: u8 = 3;
+
+pub const HOST_OSES: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HostOs {
+    MacOs = 0,
+    Windows = 1,
+    /// Android too, which enumerates just like any other Linux
+    Linux = 2,
+    /// iPadOS too
+    Ios = 3
+}
+
+impl HostOs {
+    pub const ALL: [Self; HOST_OSES] =
+        [Self::MacOs, Self::Windows, Self::Linux, Self::Ios];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MacOs => "macos",
+            Self::Windows => "windows",
+            Self::Linux => "linux",
+            Self::Ios => "ios"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|os| os.name() == name)
+    }
+}
+
+impl TryFrom<u8> for HostOs {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(value as usize).copied().ok_or(())
+    }
+}
+
+/// Most requests worth remembering, hosts are done asking way before this
+const MAX_REQUESTS: usize = 64;
+
+/// `bmRequestType` of standard requests for the device, from device to host
+const STANDARD_DEVICE_IN: u8 = 0x80;
+/// `bRequest` of GET_DESCRIPTOR
+const GET_DESCRIPTOR: u8 = 6;
+
+/// A GET_DESCRIPTOR request for one of the device's descriptors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DescriptorRequest {
+    /// `wValue`'s high byte
+    pub descriptor_type: u8,
+    /// `wLength`
+    pub length: u16
+}
+
+/// The descriptor requests a host made since the last bus reset, in the order
+/// it made them
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    requests: Vec<DescriptorRequest, MAX_REQUESTS>
+}
+
+impl Fingerprint {
+    pub const fn new() -> Self {
+        Self {
+            requests: Vec::new()
+        }
+    }
+
+    /// Forgets everything, for when the bus gets reset
+    pub fn clear(&mut self) {
+        self.requests.clear();
+    }
+
+    /// Takes note of a GET_DESCRIPTOR request
+    pub fn record(&mut self, descriptor_type: u8, length: u16) {
+        // hosts asking for more than this are weird anyway
+        let _ = self.requests.push(DescriptorRequest {
+            descriptor_type,
+            length
+        });
+    }
+
+    /// Takes note of a SETUP packet if it's a GET_DESCRIPTOR for one of the
+    /// device's descriptors, returns whether it was. Packets are passed in as
+    /// they come off the control pipe, whoever ends up answering them
+    pub fn record_setup(&mut self, setup: &[u8; 8]) -> bool {
+        if setup[0] != STANDARD_DEVICE_IN || setup[1] != GET_DESCRIPTOR {
+            return false;
+        }
+        self.record(setup[3], u16::from_le_bytes([setup[6], setup[7]]));
+        true
+    }
+
+    /// Every request so far, oldest first
+    pub fn requests(&self) -> &[DescriptorRequest] {
+        &self.requests
+    }
+
+    /// `wLength` of every request for a type of descriptor, in order
+    fn lengths(&self, descriptor_type: u8) -> impl Iterator<Item = u16> + '_ {
+        self.requests
+            .iter()
+            .filter(move |r| r.descriptor_type == descriptor_type)
+            .map(|r| r.length)
+    }
+
+    /// How many string descriptor requests asked for exactly `length` bytes
+    fn strings_of(&self, length: u16) -> usize {
+        self.lengths(DESCRIPTOR_STRING)
+            .filter(|l| *l == length)
+            .count()
+    }
+
+    /// Makes a guess, `None` until the host has asked for enough strings to
+    /// tell. The patterns are the same ones QMK's OS detection looks for
+    pub fn guess(&self) -> Option<HostOs> {
+        let count = self.lengths(DESCRIPTOR_STRING).count();
+        if count < 3 {
+            return None;
+        }
+        let (twos, fours, full) = (
+            self.strings_of(0x2),
+            self.strings_of(0x4),
+            self.strings_of(0xFF)
+        );
+        // Windows reads the whole configuration descriptor in one 255 byte go,
+        // everyone else asks for its actual length
+        let windows_config =
+            self.lengths(DESCRIPTOR_CONFIGURATION).any(|l| l == 0xFF);
+        let last_string = self.lengths(DESCRIPTOR_STRING).last();
+        Some(if (full >= 2 && fours >= 1) || windows_config {
+            HostOs::Windows
+        } else if full == count {
+            HostOs::Linux
+        } else if count == 5 && last_string == Some(0xFF) && full == 1 && twos == 2 {
+            HostOs::MacOs
+        } else if count == 4 && full == 0 && twos == 2 {
+            // the same as macOS, without the last request
+            HostOs::Ios
+        } else if full >= 2 && twos == 0 && fours == 0 {
+            HostOs::Windows
+        } else {
+            HostOs::Linux
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use crate::model::MODEL;
+
+// Generated by build.rs from the HID usage tables: `KeyCode` and the other
+// usage enums, each with an `impl` holding `ALL`, the names, `from_name` and
+// `supported_on`, plus `TryFrom` and `FromStr`. Hand written additions go in
+// the one `impl KeyCode` below
+include!(concat!(env!("OUT_DIR"), "/key_codes.rs"));
+
+/// Host operating systems the HID usage tables say something about
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Os {
+    /// What the usage tables call PC-AT
+    Windows,
+    MacOs,
+    Unix
+}
+
+/// A name that isn't any of a usage enum's variants
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UnknownName;
+
+impl core::fmt::Display for UnknownName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("unknown name")
+    }
+}
+
+bitflags::bitflags! {
+    /// Keyboard modifiers
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Modifiers: u8 {
+        const LEFT_CTRL   = 0b0000_0001;
+        const LEFT_SHIFT  = 0b0000_0010;
+        const LEFT_ALT    = 0b0000_0100;
+        const LEFT_META   = 0b0000_1000;
+        const RIGHT_CTRL  = 0b0001_0000;
+        const RIGHT_SHIFT = 0b0010_0000;
+        const RIGHT_ALT   = 0b0100_0000;
+        const RIGHT_META  = 0b1000_0000;
+    }
+}
+
+impl KeyCode {
+    /// What a byte from the keyboard is on the [`MODEL`]'s matrix
+    #[inline]
+    pub fn try_from_matrix_key(key: u8) -> Option<(Self, Option<Self>)> {
+        MODEL.key(key)
+    }
+
+    /// Finds the key that types an ASCII character on a US layout, along with
+    /// whether shift has to be held for it
+    pub fn from_ascii(c: u8) -> Option<(Self, bool)> {
+        use KeyCode as Kc;
+
+        let offset = |base: Kc, from: u8| Kc::try_from(base as u8 + (c - from)).ok();
+        Some(match c {
+            b'a'..=b'z' => (offset(Kc::KeyboardA, b'a')?, false),
+            b'A'..=b'Z' => (offset(Kc::KeyboardA, b'A')?, true),
+            b'1'..=b'9' => (offset(Kc::Keyboard1AndExclamation, b'1')?, false),
+            b'0' => (Kc::Keyboard0AndLeftParentheses, false),
+            b'\n' => (Kc::KeyboardEnter, false),
+            b'\t' => (Kc::KeyboardTab, false),
+            b' ' => (Kc::KeyboardSpacebar, false),
+            b'!' => (Kc::Keyboard1AndExclamation, true),
+            b'@' => (Kc::Keyboard2AndAt, true),
+            b'#' => (Kc::Keyboard3AndSharp, true),
+            b'$' => (Kc::Keyboard4AndDollarSign, true),
+            b'%' => (Kc::Keyboard5AndPercent, true),
+            b'^' => (Kc::Keyboard6AndCaret, true),
+            b'&' => (Kc::Keyboard7AndAmpersand, true),
+            b'*' => (Kc::Keyboard8AndAsterisk, true),
+            b'(' => (Kc::Keyboard9AndRightParentheses, true),
+            b')' => (Kc::Keyboard0AndLeftParentheses, true),
+            b'-' => (Kc::KeyboardMinusAndUnderscore, false),
+            b'_' => (Kc::KeyboardMinusAndUnderscore, true),
+            b'=' => (Kc::KeyboardEqualsAndPlus, false),
+            b'+' => (Kc::KeyboardEqualsAndPlus, true),
+            b'[' => (Kc::KeyboardLeftSquareBracketAndCurlyBracket, false),
+            b'{' => (Kc::KeyboardLeftSquareBracketAndCurlyBracket, true),
+            b']' => (Kc::KeyboardRightSquareBracketAndCurlyBracket, false),
+            b'}' => (Kc::KeyboardRightSquareBracketAndCurlyBracket, true),
+            b'\\' => (Kc::KeyboardBackslashAndPipe, false),
+            b'|' => (Kc::KeyboardBackslashAndPipe, true),
+            b';' => (Kc::KeyboardSemicolonAndColon, false),
+            b':' => (Kc::KeyboardSemicolonAndColon, true),
+            b'\'' => (Kc::KeyboardSingleAndDoubleQuotes, false),
+            b'"' => (Kc::KeyboardSingleAndDoubleQuotes, true),
+            b'`' => (Kc::KeyboardGraveAccentAndTilde, false),
+            b'~' => (Kc::KeyboardGraveAccentAndTilde, true),
+            b',' => (Kc::KeyboardCommaAndLessThan, false),
+            b'<' => (Kc::KeyboardCommaAndLessThan, true),
+            b'.' => (Kc::KeyboardPeriodAndGreaterThan, false),
+            b'>' => (Kc::KeyboardPeriodAndGreaterThan, true),
+            b'/' => (Kc::KeyboardSlashAndQuestionMark, false),
+            b'?' => (Kc::KeyboardSlashAndQuestionMark, true),
+            _ => return None
+        })
+    }
+}
+
+impl From<KeyCode> for Modifiers {
+    #[inline]
+    fn from(value: KeyCode) -> Self {
+        match value {
+            KeyCode::KeyboardLeftShift => Self::LEFT_SHIFT,
+            KeyCode::KeyboardRightShift => Self::RIGHT_SHIFT,
+            KeyCode::KeyboardLeftAlt => Self::LEFT_ALT,
+            KeyCode::KeyboardRightAlt => Self::RIGHT_ALT,
+            KeyCode::KeyboardLeftControl => Self::LEFT_CTRL,
+            KeyCode::KeyboardRightControl => Self::RIGHT_CTRL,
+            KeyCode::KeyboardLeftGui => Self::LEFT_META,
+            KeyCode::KeyboardRightGui => Self::RIGHT_META,
+            _ => Self::empty()
+        }
+    }
+}
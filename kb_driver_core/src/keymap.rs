@@ -0,0 +1,359 @@
+//! Layered keymaps, stored per matrix position
+
+use core::{fmt, str::FromStr};
+
+use crate::{
+    key_codes::{KeyCode, Modifiers},
+    matrix::MATRIX
+};
+
+pub const ROWS: usize = 12;
+pub const COLS: usize = 8;
+/// Every position the keyboard can report, `Y * COLS + X`
+pub const POSITIONS: usize = ROWS * COLS;
+pub const LAYERS: usize = 4;
+/// The layer the Fn key switches to in the default keymap
+pub const FN_LAYER: u8 = 1;
+
+/// What pressing a key does
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Does nothing
+    #[default]
+    None,
+    /// Does whatever the next active layer below has at this position
+    Transparent,
+    Key(KeyCode),
+    /// Switches to a layer for as long as the key is held
+    Layer(u8),
+    /// Types out a macro from the macro buffer
+    Macro(u8),
+    /// Acts as modifiers when held and as a key when tapped
+    ModTap(Modifiers, KeyCode),
+    /// Switches to a layer when held and acts as a key when tapped
+    LayerTap(u8, KeyCode),
+    /// Switches to another [host profile](crate::profile) and saves that
+    Profile(u8),
+    /// Types a Unicode character, however the profile says to
+    Unicode(u16)
+}
+
+// QMK's keycode ranges, so keymaps can be shared with VIA and friends
+const QK_MOD_TAP: u16 = 0x2000;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_MOMENTARY: u16 = 0x5220;
+const QK_MACRO: u16 = 0x7700;
+/// Keycodes QMK leaves to keyboards, VIA shows them as custom keycodes
+const QK_KB: u16 = 0x7E00;
+const QK_UNICODE: u16 = 0x8000;
+
+/// Turns HID modifiers into QMK's 5 bit ones, which can't mix left and right
+/// modifiers, the right ones win
+fn mods_to_qmk(mods: Modifiers) -> u16 {
+    let bits = mods.bits();
+    if bits & 0xF0 != 0 {
+        (bits >> 4) as u16 | 0x10
+    } else {
+        bits as u16
+    }
+}
+
+fn mods_from_qmk(mods: u16) -> Modifiers {
+    let side = (mods & 0x0F) as u8;
+    if mods & 0x10 != 0 {
+        Modifiers::from_bits_truncate(side << 4)
+    } else {
+        Modifiers::from_bits_truncate(side)
+    }
+}
+
+impl Action {
+    /// Encodes the action as a QMK keycode. `Key(KeyboardErrorRollOver)`
+    /// collides with `Transparent`, which shouldn't matter since no one maps it
+    pub fn to_u16(self) -> u16 {
+        match self {
+            Action::None => 0x0000,
+            Action::Transparent => 0x0001,
+            Action::Key(key) => key as u16,
+            Action::Layer(layer) => QK_MOMENTARY | (layer as u16 & 0x1F),
+            Action::Macro(index) => QK_MACRO | (index as u16 & 0x7F),
+            Action::ModTap(mods, key) => {
+                QK_MOD_TAP | (mods_to_qmk(mods) << 8) | key as u16
+            }
+            Action::LayerTap(layer, key) => {
+                QK_LAYER_TAP | ((layer as u16 & 0x0F) << 8) | key as u16
+            }
+            Action::Profile(profile) => QK_KB | (profile as u16 & 0x3F),
+            Action::Unicode(code_point) => QK_UNICODE | (code_point & 0x7FFF)
+        }
+    }
+
+    /// Decodes a QMK keycode, returns `None` for anything we can't do
+    pub fn from_u16(value: u16) -> Option<Self> {
+        let key = || KeyCode::try_from((value & 0xFF) as u8).ok();
+        Some(match value {
+            0x0000 => Action::None,
+            0x0001 => Action::Transparent,
+            0x0002..=0x00FF => Action::Key(key()?),
+            0x2000..=0x3FFF => Action::ModTap(mods_from_qmk(value >> 8), key()?),
+            0x4000..=0x4FFF => Action::LayerTap(((value >> 8) & 0x0F) as u8, key()?),
+            0x5220..=0x523F => Action::Layer((value & 0x1F) as u8),
+            0x7700..=0x777F => Action::Macro((value & 0x7F) as u8),
+            0x7E00..=0x7E3F => Action::Profile((value & 0x3F) as u8),
+            0x8000..=0xFFFF => Action::Unicode(value & 0x7FFF),
+            _ => return None
+        })
+    }
+
+    /// Checks if the action does something different when tapped and held
+    #[inline]
+    pub fn is_tap_hold(self) -> bool {
+        matches!(self, Action::ModTap(..) | Action::LayerTap(..))
+    }
+}
+
+/// Writes an action the way [`FromStr`] reads it: `none`, `trans`, a key,
+/// `MO(layer)`, `MACRO(index)`, `MT(LEFT_CTRL|LEFT_SHIFT, key)`,
+/// `LT(layer, key)`, `PROFILE(index)` or `UC(code point)`. Keys are written by [name](KeyCode::name), but a HID
+/// usage like `0x04` is read too
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Action::None => write!(f, "none"),
+            Action::Transparent => write!(f, "trans"),
+            Action::Key(key) => f.write_str(key.name()),
+            Action::Layer(layer) => write!(f, "MO({layer})"),
+            Action::Macro(index) => write!(f, "MACRO({index})"),
+            Action::ModTap(mods, key) => {
+                write!(f, "MT(")?;
+                for (i, (name, _)) in mods.iter_names().enumerate() {
+                    if i > 0 {
+                        write!(f, "|")?;
+                    }
+                    write!(f, "{name}")?;
+                }
+                write!(f, ", {})", Action::Key(key))
+            }
+            Action::LayerTap(layer, key) => {
+                write!(f, "LT({layer}, {})", Action::Key(key))
+            }
+            Action::Profile(profile) => write!(f, "PROFILE({profile})"),
+            Action::Unicode(code_point) => write!(f, "UC({code_point:#06x})")
+        }
+    }
+}
+
+/// Same as [`Display`](fmt::Display), `KeyCode` doesn't have a `Debug`
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Action {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=u16:#06x}", self.to_u16())
+    }
+}
+
+/// Why an action couldn't be parsed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseActionError {
+    /// Not any of the forms [`Action`] can be written in
+    Syntax,
+    UnknownKey,
+    UnknownModifier,
+    /// Past [`LAYERS`]
+    InvalidLayer,
+    /// Past [`MACRO_COUNT`](crate::macros::MACRO_COUNT)
+    InvalidMacro,
+    /// Past [`PROFILES`](crate::profile::PROFILES)
+    InvalidProfile,
+    /// Past `0x7FFF`, which is as far as QMK's keycodes go
+    InvalidCodePoint
+}
+
+impl fmt::Display for ParseActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Syntax => "not a valid action",
+            Self::UnknownKey => "unknown key",
+            Self::UnknownModifier => "unknown modifier",
+            Self::InvalidLayer => "layer out of range",
+            Self::InvalidMacro => "macro out of range",
+            Self::InvalidProfile => "profile out of range",
+            Self::InvalidCodePoint => "code point out of range"
+        })
+    }
+}
+
+fn parse_number(s: &str) -> Option<u8> {
+    parse_wide_number(s)?.try_into().ok()
+}
+
+fn parse_wide_number(s: &str) -> Option<u16> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+fn parse_key(s: &str) -> Result<KeyCode, ParseActionError> {
+    parse_number(s)
+        .and_then(|usage| KeyCode::try_from(usage).ok())
+        .or_else(|| KeyCode::from_name(s.trim()))
+        .ok_or(ParseActionError::UnknownKey)
+}
+
+fn parse_layer(s: &str) -> Result<u8, ParseActionError> {
+    parse_number(s)
+        .filter(|layer| (*layer as usize) < LAYERS)
+        .ok_or(ParseActionError::InvalidLayer)
+}
+
+impl FromStr for Action {
+    type Err = ParseActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "none" => return Ok(Action::None),
+            "trans" => return Ok(Action::Transparent),
+            _ => {}
+        }
+        let Some((name, args)) = s.strip_suffix(')').and_then(|s| s.split_once('('))
+        else {
+            return parse_key(s).map(Action::Key);
+        };
+        match (name.trim(), args.split_once(',')) {
+            ("MO", None) => parse_layer(args).map(Action::Layer),
+            ("MACRO", None) => parse_number(args)
+                .filter(|i| (*i as usize) < crate::macros::MACRO_COUNT)
+                .map(Action::Macro)
+                .ok_or(ParseActionError::InvalidMacro),
+            ("MT", Some((mods, key))) => {
+                let mut modifiers = Modifiers::empty();
+                for name in mods.split('|') {
+                    modifiers |= Modifiers::from_name(name.trim())
+                        .ok_or(ParseActionError::UnknownModifier)?;
+                }
+                Ok(Action::ModTap(modifiers, parse_key(key)?))
+            }
+            ("LT", Some((layer, key))) => {
+                Ok(Action::LayerTap(parse_layer(layer)?, parse_key(key)?))
+            }
+            ("PROFILE", None) => parse_number(args)
+                .filter(|i| (*i as usize) < crate::profile::PROFILES)
+                .map(Action::Profile)
+                .ok_or(ParseActionError::InvalidProfile),
+            ("UC", None) => parse_wide_number(args)
+                .filter(|c| *c <= 0x7FFF)
+                .map(Action::Unicode)
+                .ok_or(ParseActionError::InvalidCodePoint),
+            _ => Err(ParseActionError::Syntax)
+        }
+    }
+}
+
+/// What every position does on every layer, layer 0 is always active
+#[derive(Clone, PartialEq, Eq)]
+pub struct Keymap {
+    pub layers: [[Action; POSITIONS]; LAYERS]
+}
+
+impl Keymap {
+    /// The layout printed on the keyboard, with Fn switching to
+    /// [`FN_LAYER`] where the alternate keys are, modifiers stay usable while
+    /// Fn is held. Fn + 1 to 3 switch between host profiles
+    pub const DEFAULT: Self = kb_driver_proc_macro::keymap! {
+        layer 0 {
+            Y0 [Keyboard1AndExclamation, Keyboard2AndAt, Keyboard3AndSharp,
+                KeyboardZ, Keyboard4AndDollarSign, Keyboard5AndPercent,
+                Keyboard6AndCaret, Keyboard7AndAmpersand],
+            Y1 [KeyboardLeftGui, KeyboardQ, KeyboardW, KeyboardE, KeyboardR,
+                KeyboardT, KeyboardY, KeyboardGraveAccentAndTilde],
+            Y2 [KeyboardX, KeyboardA, KeyboardS, KeyboardD, KeyboardF, KeyboardG,
+                KeyboardH, KeyboardSpacebar],
+            Y3 [KeyboardCapsLock, KeyboardTab, KeyboardLeftControl, _, _, _, _, _],
+            Y4 [_, _, MO(1), KeyboardLeftAlt, _, _, _, _],
+            Y5 [_, _, _, _, KeyboardC, KeyboardV, KeyboardB, KeyboardN],
+            Y6 [KeyboardMinusAndUnderscore, KeyboardEqualsAndPlus, KeyboardBackspace,
+                none, Keyboard8AndAsterisk, Keyboard9AndRightParentheses,
+                Keyboard0AndLeftParentheses, KeyboardSpacebar],
+            Y7 [KeyboardLeftSquareBracketAndCurlyBracket,
+                KeyboardRightSquareBracketAndCurlyBracket, KeyboardBackslashAndPipe,
+                none, KeyboardU, KeyboardI, KeyboardO, KeyboardP],
+            Y8 [KeyboardSingleAndDoubleQuotes, KeyboardEnter, none, _, KeyboardJ,
+                KeyboardK, KeyboardL, KeyboardSemicolonAndColon],
+            Y9 [KeyboardSlashAndQuestionMark, KeyboardUpArrow, none, _, KeyboardM,
+                KeyboardCommaAndLessThan, KeyboardPeriodAndGreaterThan,
+                KeyboardEnter],
+            Y10 [KeyboardDelete, KeyboardLeftArrow, KeyboardDownArrow,
+                KeyboardRightArrow, _, _, _, _],
+            Y11 [KeyboardLeftShift, KeyboardRightShift, _, _, _, _, _, _]
+        }
+        layer 1 {
+            Y0 [PROFILE(0), PROFILE(1), PROFILE(2), none, none, none, none, none],
+            Y1 [trans, none, none, none, none, none, none, none],
+            Y2 [none, none, none, none, none, none, none, none],
+            Y3 [none, KeyboardEscape, trans, _, _, _, _, _],
+            Y4 [_, _, trans, trans, _, _, _, _],
+            Y5 [_, _, _, _, none, none, none, none],
+            Y6 [none, none, none, none, none, none, none, none],
+            Y7 [none, none, none, none, none, none, none, none],
+            Y8 [none, none, none, _, none, none, none, none],
+            Y9 [none, KeyboardPageUp, none, _, none, none, none, none],
+            Y10 [none, none, KeyboardPageDown, none, _, _, _, _],
+            Y11 [trans, trans, _, _, _, _, _, _]
+        }
+    };
+
+    #[inline]
+    pub fn get(&self, layer: usize, pos: usize) -> Option<Action> {
+        self.layers.get(layer)?.get(pos).copied()
+    }
+
+    /// Changes what a position does on a layer, returns `false` if either is
+    /// out of range
+    pub fn set(&mut self, layer: usize, pos: usize, action: Action) -> bool {
+        match self.layers.get_mut(layer).and_then(|l| l.get_mut(pos)) {
+            Some(slot) => {
+                *slot = action;
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Looks up what pressing a position does, `active_layers` is a bitmask
+    /// of the layers that are switched on. The highest active layer that isn't
+    /// transparent at that position wins
+    pub fn resolve(&self, pos: usize, active_layers: u8) -> Action {
+        if pos >= POSITIONS {
+            return Action::None;
+        }
+        for layer in (0..LAYERS).rev() {
+            if layer != 0 && active_layers & (1 << layer) == 0 {
+                continue;
+            }
+            match self.layers[layer][pos] {
+                Action::Transparent => continue,
+                action => return action
+            }
+        }
+        Action::None
+    }
+}
+
+/// Checks if there's a key at a position, `Y * COLS + X`
+pub const fn is_key(pos: usize) -> bool {
+    pos < MATRIX.len() && MATRIX[pos].is_some()
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,32 @@
+//! Everything about the keyboard that doesn't touch the hardware, so it can be
+//! used by host-side tools as well as the firmware
+
+#![no_std]
+
+// so `keymap!` works in here too
+extern crate self as kb_driver_core;
+
+pub mod capture;
+pub mod combo;
+pub mod config;
+pub mod crash_report;
+pub mod crc;
+pub mod firmware_update;
+pub mod host_os;
+pub mod key_codes;
+pub mod keymap;
+pub mod log_sink;
+pub mod macros;
+pub mod matrix;
+pub mod mem_flash;
+pub mod model;
+pub mod power;
+pub mod profile;
+pub mod program;
+pub mod protocol;
+pub mod report;
+pub mod state;
+pub mod usages;
+pub mod via;
+
+pub use kb_driver_proc_macro::*;
@@ -0,0 +1,83 @@
+//! Where the logging macros send their messages when the `log-buffer` feature
+//! is on, so everything can log the same way whether or not it knows where
+//! the lines end up
+//!
+//! The firmware hands [`set_sink`] something that keeps lines in a buffer,
+//! and messages below [`level`] never get formatted at all. The level
+//! can be changed while the firmware runs, it starts out at [`Level::Info`].
+
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicPtr, AtomicU8, Ordering}
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4
+}
+
+impl Level {
+    pub const ALL: [Self; 5] = [
+        Self::Trace,
+        Self::Debug,
+        Self::Info,
+        Self::Warn,
+        Self::Error
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR"
+        }
+    }
+}
+
+impl TryFrom<u8> for Level {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, ()> {
+        Self::ALL.get(value as usize).copied().ok_or(())
+    }
+}
+
+/// Gets every message at or above the level
+pub type Sink = fn(Level, fmt::Arguments);
+
+static SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_sink(sink: Sink) {
+    SINK.store(sink as *mut (), Ordering::Release);
+}
+
+pub fn level() -> Level {
+    Level::try_from(LEVEL.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// What the logging macros call, does nothing until there's a sink
+pub fn write(level: Level, args: fmt::Arguments) {
+    if level < self::level() {
+        return;
+    }
+    let sink = SINK.load(Ordering::Acquire);
+    if sink.is_null() {
+        return;
+    }
+    // SAFETY: the only thing that ever gets stored is a `Sink`
+    let sink = unsafe { core::mem::transmute::<*mut (), Sink>(sink) };
+    sink(level, args)
+}
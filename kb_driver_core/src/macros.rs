@@ -0,0 +1,205 @@
+//! Keyboard macros, stored the same way QMK's dynamic macros are
+//!
+//! Every macro is a NUL terminated string in one shared buffer. Plain ASCII
+//! characters get typed out on a US layout, and [`PREFIX`] starts a special
+//! step:
+//!
+//! | bytes                         | step                              |
+//! |-------------------------------|-----------------------------------|
+//! | `0x01 0x01 <keycode>`         | tap a key                         |
+//! | `0x01 0x02 <keycode>`         | press a key down                  |
+//! | `0x01 0x03 <keycode>`         | let go of a key                   |
+//! | `0x01 0x04 <ascii digits> \|` | wait for that many milliseconds   |
+
+use crate::{key_codes::KeyCode, report::Report};
+
+pub const MACRO_COUNT: usize = 16;
+/// Size of the buffer shared by all macros, NUL terminators included
+pub const BUFFER_LEN: usize = 512;
+
+pub const PREFIX: u8 = 0x01;
+const TAP: u8 = 0x01;
+const DOWN: u8 = 0x02;
+const UP: u8 = 0x03;
+const DELAY: u8 = 0x04;
+const DELAY_END: u8 = b'|';
+
+/// Every macro in one buffer, see the [module docs](self) for the format
+#[derive(Clone, PartialEq, Eq)]
+pub struct Macros {
+    pub buffer: [u8; BUFFER_LEN]
+}
+
+/// A single thing a macro does
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Step {
+    Tap(KeyCode),
+    Down(KeyCode),
+    Up(KeyCode),
+    Delay(u16),
+    /// An ASCII character, typed with shift if it needs it
+    Char(u8)
+}
+
+impl Macros {
+    /// No macros at all
+    pub const EMPTY: Self = Self {
+        buffer: [0; BUFFER_LEN]
+    };
+
+    /// The raw bytes of a macro, without the NUL terminator. Macros past the
+    /// end of the buffer are empty
+    pub fn get(&self, index: u8) -> &[u8] {
+        self.buffer
+            .split(|b| *b == 0)
+            .nth(index as usize)
+            .unwrap_or(&[])
+    }
+
+    /// Replaces a macro, returns `false` without changing anything if the
+    /// index is out of range or everything wouldn't fit in the buffer anymore
+    pub fn set(&mut self, index: u8, data: &[u8]) -> bool {
+        if index as usize >= MACRO_COUNT || data.contains(&0) {
+            return false;
+        }
+        let mut out = [0u8; BUFFER_LEN];
+        let mut len = 0;
+        for i in 0..MACRO_COUNT as u8 {
+            let data = if i == index { data } else { self.get(i) };
+            // room for the terminator too
+            if len + data.len() + 1 > BUFFER_LEN {
+                return false;
+            }
+            out[len..len + data.len()].copy_from_slice(data);
+            len += data.len() + 1;
+        }
+        self.buffer = out;
+        true
+    }
+
+    /// Iterates over the steps of a macro, skipping anything that doesn't
+    /// parse
+    pub fn steps(&self, index: u8) -> impl Iterator<Item = Step> + '_ {
+        let data = self.get(index);
+        let mut offset = 0;
+        core::iter::from_fn(move || next_step(data, &mut offset))
+    }
+}
+
+impl Default for Macros {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// Parses the step at `offset`, moving it past the step. Steps that don't
+/// parse are skipped
+fn next_step(data: &[u8], offset: &mut usize) -> Option<Step> {
+    loop {
+        let rest = data.get(*offset..)?;
+        let (step, len) = match *rest {
+            [] => return None,
+            [PREFIX, DELAY, ref digits @ ..] => {
+                let end = digits.iter().position(|b| *b == DELAY_END);
+                let digits = &digits[..end.unwrap_or(digits.len())];
+                let ms = digits
+                    .iter()
+                    .filter(|b| b.is_ascii_digit())
+                    .fold(0u16, |ms, b| {
+                        ms.saturating_mul(10).saturating_add((b - b'0') as u16)
+                    });
+                (
+                    Some(Step::Delay(ms)),
+                    2 + digits.len() + end.is_some() as usize
+                )
+            }
+            [PREFIX, kind, key, ..] => {
+                let step = KeyCode::try_from(key).ok().and_then(|key| match kind {
+                    TAP => Some(Step::Tap(key)),
+                    DOWN => Some(Step::Down(key)),
+                    UP => Some(Step::Up(key)),
+                    _ => None
+                });
+                (step, 3)
+            }
+            [PREFIX, ..] => return None,
+            [c, ..] => (KeyCode::from_ascii(c).map(|_| Step::Char(c)), 1)
+        };
+        *offset += len;
+        if step.is_some() {
+            return step;
+        }
+    }
+}
+
+/// What a [`Player`] wants done next
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Report(Report),
+    Delay(u16)
+}
+
+/// Plays a macro back one report at a time, without holding on to the macro
+/// buffer so it can be changed in between
+pub struct Player {
+    index: u8,
+    offset: usize,
+    report: Report,
+    /// The release half of a tap
+    pending: Option<Report>
+}
+
+impl Player {
+    /// Starts playing a macro on top of whatever is already held down
+    pub fn new(index: u8, held: Report) -> Self {
+        Self {
+            index,
+            offset: 0,
+            report: held,
+            pending: None
+        }
+    }
+
+    /// Gets the next thing to do, `None` once the macro is done
+    pub fn next(&mut self, macros: &Macros) -> Option<Output> {
+        if let Some(report) = self.pending.take() {
+            return Some(Output::Report(report));
+        }
+        let step = next_step(macros.get(self.index), &mut self.offset)?;
+        let mut tap = |key: KeyCode, shift: bool| {
+            let before = self.report;
+            if shift {
+                self.report.press(KeyCode::KeyboardLeftShift);
+            }
+            self.report.press(key);
+            let pressed = self.report;
+            self.report = before;
+            self.pending = Some(before);
+            Output::Report(pressed)
+        };
+        Some(match step {
+            Step::Tap(key) => tap(key, false),
+            Step::Char(c) => match KeyCode::from_ascii(c) {
+                Some((key, shift)) => tap(key, shift),
+                None => Output::Delay(0)
+            },
+            Step::Down(key) => {
+                self.report.press(key);
+                Output::Report(self.report)
+            }
+            Step::Up(key) => {
+                self.report.release(key);
+                Output::Report(self.report)
+            }
+            Step::Delay(ms) => Output::Delay(ms)
+        })
+    }
+
+    /// What's still held down by the macro, so it can be let go of if the
+    /// macro gets interrupted
+    #[inline]
+    pub fn held(&self) -> Report {
+        self.report
+    }
+}
@@ -1,7 +1,17 @@
-use crate::key_codes::KeyCode as Kc;
+//! Key matrices, what each position a keyboard reports is
 
-/// The coordinates of keys on the physical key matrix of the keyboard, as shown
-/// [here](https://www.splorp.com/pdf/stowawayhwref.pdf) on page 18
+use crate::{key_codes::KeyCode as Kc, model::MODEL};
+
+/// What each position is, `Y * COLS + X`, along with what it does with Fn held.
+/// Positions past the end aren't keys
+pub type Matrix = [Option<(Kc, Option<Kc>)>];
+
+/// The matrix of the keyboard the firmware's built for
+pub const MATRIX: &Matrix = MODEL.matrix;
+
+/// The coordinates of keys on the physical key matrix of the Palm Portable
+/// Keyboard, as shown [here](https://www.splorp.com/pdf/stowawayhwref.pdf) on
+/// page 18
 ///
 /// |     | X0        | X1         | X2          | X3          | X4 | X5 | X6 | X7      |
 /// |-----|-----------|------------|-------------|-------------|----|----|----|---------|
@@ -17,7 +27,7 @@ use crate::key_codes::KeyCode as Kc;
 /// | Y9  | /         | UP ARROW   | SPECIAL FN4 |             | M  | ,  | .  | DONE    |
 /// | Y10 | DEL       | LEFT ARROW | DOWN ARROW  | RIGHT ARROW |    |    |    |         |
 /// | Y11 | LSHIFT    | RSHIFT     |             |             |    |    |    |         |
-pub const MATRIX: [Option<(Kc, Option<Kc>)>; 90] = [
+pub const PALM_PORTABLE: &Matrix = &[
     // Y0
     Some((Kc::Keyboard1AndExclamation, None)),
     Some((Kc::Keyboard2AndAt, None)),
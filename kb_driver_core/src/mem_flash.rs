@@ -0,0 +1,149 @@
+//! NOR flash in RAM, for running the config store off-device
+//!
+//! Behaves like the real thing: writes can only clear bits, so writing over
+//! something that wasn't erased ANDs the two together, and everything has to
+//! line up with the write and erase sizes.
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemFlashError {
+    NotAligned,
+    OutOfBounds,
+    /// The write budget set with [`MemFlash::fail_after`] ran out
+    PowerLoss
+}
+
+impl NorFlashError for MemFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::PowerLoss => NorFlashErrorKind::Other
+        }
+    }
+}
+
+pub struct MemFlash<const SIZE: usize, const ERASE: usize, const WRITE: usize> {
+    pub data: [u8; SIZE],
+    /// How many more bytes can be written before writes start failing halfway
+    write_budget: Option<usize>,
+    /// How many times each erase block was erased
+    pub erase_counts: [u32; 64]
+}
+
+impl<const SIZE: usize, const ERASE: usize, const WRITE: usize>
+    MemFlash<SIZE, ERASE, WRITE>
+{
+    /// Fully erased flash
+    pub const fn new() -> Self {
+        assert!(SIZE.is_multiple_of(ERASE) && ERASE.is_multiple_of(WRITE));
+        assert!(SIZE / ERASE <= 64);
+        Self {
+            data: [0xFF; SIZE],
+            write_budget: None,
+            erase_counts: [0; 64]
+        }
+    }
+
+    /// Makes writes stop after `bytes` more bytes, like the power got cut in
+    /// the middle of one. The write that runs out returns
+    /// [`MemFlashError::PowerLoss`] with only part of it written
+    pub fn fail_after(&mut self, bytes: usize) {
+        self.write_budget = Some(bytes);
+    }
+
+    /// Lets writes go through again
+    pub fn restore_power(&mut self) {
+        self.write_budget = None;
+    }
+
+    fn check(
+        &self,
+        offset: u32,
+        len: usize,
+        align: usize
+    ) -> Result<(), MemFlashError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(align) || !len.is_multiple_of(align) {
+            Err(MemFlashError::NotAligned)
+        } else if offset + len > SIZE {
+            Err(MemFlashError::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const SIZE: usize, const ERASE: usize, const WRITE: usize> Default
+    for MemFlash<SIZE, ERASE, WRITE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const ERASE: usize, const WRITE: usize> ErrorType
+    for MemFlash<SIZE, ERASE, WRITE>
+{
+    type Error = MemFlashError;
+}
+
+impl<const SIZE: usize, const ERASE: usize, const WRITE: usize> ReadNorFlash
+    for MemFlash<SIZE, ERASE, WRITE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const ERASE: usize, const WRITE: usize> NorFlash
+    for MemFlash<SIZE, ERASE, WRITE>
+{
+    const WRITE_SIZE: usize = WRITE;
+    const ERASE_SIZE: usize = ERASE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if to < from {
+            return Err(MemFlashError::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, ERASE)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        self.erase_counts[from as usize / ERASE..to as usize / ERASE]
+            .iter_mut()
+            .for_each(|count| *count += 1);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), WRITE)?;
+        let (len, result) = match self.write_budget {
+            Some(budget) if budget < bytes.len() => {
+                self.write_budget = Some(0);
+                (budget, Err(MemFlashError::PowerLoss))
+            }
+            Some(budget) => {
+                self.write_budget = Some(budget - bytes.len());
+                (bytes.len(), Ok(()))
+            }
+            None => (bytes.len(), Ok(()))
+        };
+        let offset = offset as usize;
+        for (cell, byte) in self.data[offset..offset + len].iter_mut().zip(bytes) {
+            *cell &= *byte;
+        }
+        result
+    }
+}
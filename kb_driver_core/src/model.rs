@@ -0,0 +1,113 @@
+//! The keyboards this can talk to, and what's different between them
+//!
+//! Every one of them sends a handshake once it's powered up and RTS goes high,
+//! then a byte per key event, its position on the [`Matrix`] with the top bit
+//! set for releases. What changes is the handshake, the serial settings, how
+//! long the keyboard needs between the steps of waking it up, and the matrix.
+//!
+//! The firmware gets built for one [`MODEL`], picked with a `model-*` cargo
+//! feature, as the default keymap and VIA layout are laid out for its matrix.
+//! The handshake tells the keyboard that's actually plugged in apart from the
+//! others, see [`KeyboardModel::detect`], but that's only used to say which one
+//! it is, the keymap wouldn't fit it.
+//!
+//! Only the Palm Portable Keyboard is here so far. The hardware reference
+//! doesn't say what the other Think Outside and Targus keyboards send, so each
+//! one needs a capture of its handshake and key bytes before it can be added.
+//! Adding one is a `const` here, an entry in [`MODELS`], a `model-*` feature in
+//! this crate's and `kb_driver`'s manifests and a line picking it for
+//! [`MODEL`].
+
+use crate::{
+    key_codes::KeyCode,
+    matrix::{self, Matrix}
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Parity {
+    None,
+    Even,
+    Odd
+}
+
+/// How the keyboard's TXD line is set up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineSettings {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8
+}
+
+/// How long the keyboard needs between the steps of waking it up. Powering up
+/// goes RTS low, wait, power on and RTS high, then the handshake
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerSequence {
+    /// How long RTS stays low, the keyboard doesn't notice shorter toggles
+    pub rts_low_ms: u16,
+    /// How long the handshake can take after powering up
+    pub power_up_timeout_ms: u16,
+    /// How long the handshake can take when the keyboard already had power
+    pub handshake_timeout_ms: u16,
+    /// How often RTS gets toggled so the keyboard doesn't doze off
+    pub keep_awake_secs: u16
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardModel {
+    pub name: &'static str,
+    /// What it sends after being powered up or poked with RTS
+    pub handshake: [u8; 2],
+    pub line: LineSettings,
+    pub power: PowerSequence,
+    pub matrix: &'static Matrix
+}
+
+/// The Palm Portable Keyboard, made by Think Outside as the Stowaway
+pub const PALM_PORTABLE: KeyboardModel = KeyboardModel {
+    name: "Palm Portable Keyboard",
+    handshake: [0xFA, 0xFD],
+    line: LineSettings {
+        baud: 9600,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1
+    },
+    power: PowerSequence {
+        rts_low_ms: 15,
+        power_up_timeout_ms: 100,
+        handshake_timeout_ms: 30,
+        keep_awake_secs: 60
+    },
+    matrix: matrix::PALM_PORTABLE
+};
+
+/// Every keyboard there's a profile for. The handshakes have to differ, or
+/// there'd be no telling them apart
+pub const MODELS: &[&KeyboardModel] = &[&PALM_PORTABLE];
+
+/// The keyboard the firmware's built for
+#[cfg(feature = "model-palm-portable")]
+pub const MODEL: &KeyboardModel = &PALM_PORTABLE;
+
+#[cfg(not(any(feature = "model-palm-portable")))]
+compile_error!("pick the keyboard to build for with one of the `model-*` features");
+
+impl KeyboardModel {
+    /// Finds the keyboard that sends a handshake
+    pub fn detect(handshake: [u8; 2]) -> Option<&'static KeyboardModel> {
+        MODELS.iter().copied().find(|m| m.handshake == handshake)
+    }
+
+    /// What a byte from the keyboard is on its matrix, if it's a key at all
+    #[inline]
+    pub fn key(&self, byte: u8) -> Option<(KeyCode, Option<KeyCode>)> {
+        self.matrix
+            .get((byte & 0b0111_1111) as usize)
+            .copied()
+            .flatten()
+    }
+}
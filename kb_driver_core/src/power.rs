@@ -0,0 +1,98 @@
+//! What happens to the keyboard while the host is suspended or it goes unused
+//! for a while, kept in the config as [`Config::power`]
+//!
+//! [`Config::power`]: crate::config::Config::power
+
+/// What to do with the keyboard when it's been idle for a while
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum IdleAction {
+    /// Stop the periodic handshake and let the keyboard drop into its own
+    /// low-power mode, it raises DCD on the next key press
+    #[default]
+    KeyboardSleep = 0,
+    /// Cut power to the keyboard entirely, it can't raise DCD by itself like
+    /// this, so only use it if something else (like a HotSync button) is wired
+    /// to the DCD line
+    CutPower = 1
+}
+
+impl TryFrom<u8> for IdleAction {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::KeyboardSleep,
+            1 => Self::CutPower,
+            _ => return Err(())
+        })
+    }
+}
+
+/// How the keyboard's power is managed while suspended or idle
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Policy {
+    /// Cut power to the keyboard while suspended, this saves a few mA but it
+    /// also means key presses can't wake the host up anymore
+    pub cut_vcc_on_suspend: bool,
+    /// How many minutes to go without key events before the keyboard is put
+    /// to sleep, 0 keeps it awake forever. This is only checked once a minute
+    pub idle_timeout_mins: u16,
+    pub idle_action: IdleAction
+}
+
+impl Policy {
+    /// Keeps the keyboard awake and powered no matter what
+    pub const ALWAYS_ON: Self = Self {
+        cut_vcc_on_suspend: false,
+        idle_timeout_mins: 0,
+        idle_action: IdleAction::KeyboardSleep
+    };
+
+    /// Sensible settings when running off a phone or tablet's battery
+    pub const BATTERY: Self = Self {
+        cut_vcc_on_suspend: true,
+        idle_timeout_mins: 5,
+        idle_action: IdleAction::KeyboardSleep
+    };
+
+    /// How long the keyboard can go without key events before it's put to
+    /// sleep, `None` if it never is
+    #[inline]
+    pub fn idle_timeout_ms(&self) -> Option<u32> {
+        match self.idle_timeout_mins {
+            0 => None,
+            mins => Some(mins as u32 * 60 * 1000)
+        }
+    }
+
+    /// `[cut_vcc_on_suspend, idle_action, idle_timeout_mins (u16)]`
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let [lo, hi] = self.idle_timeout_mins.to_le_bytes();
+        [
+            self.cut_vcc_on_suspend as u8,
+            self.idle_action as u8,
+            lo,
+            hi
+        ]
+    }
+
+    /// Reads what [`to_bytes`](Self::to_bytes) wrote, an idle action that
+    /// doesn't exist falls back to the default
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        let [cut_vcc_on_suspend, idle_action, lo, hi] = bytes;
+        Self {
+            cut_vcc_on_suspend: cut_vcc_on_suspend != 0,
+            idle_timeout_mins: u16::from_le_bytes([lo, hi]),
+            idle_action: IdleAction::try_from(idle_action).unwrap_or_default()
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::ALWAYS_ON
+    }
+}
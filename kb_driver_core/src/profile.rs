@@ -0,0 +1,205 @@
+//! Host profiles, so the same keyboard can be plugged into Macs and PCs
+//!
+//! Every profile has its own keymap, a way of rearranging the modifiers and
+//! the way Unicode characters get typed on that OS. `PROFILE(n)` actions
+//! switch between them, Fn + 1 to 3 in the default keymap.
+
+use heapless::Vec;
+
+use crate::{
+    key_codes::{KeyCode, Modifiers},
+    keymap::Keymap,
+    report::Report
+};
+
+pub const PROFILES: usize = 3;
+/// Longest a profile's name can be, in bytes
+pub const NAME_LEN: usize = 16;
+
+bitflags::bitflags! {
+    /// Modifiers that trade places on their way out, left and right ones
+    /// alike
+    #[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct ModifierSwaps: u8 {
+        /// CMD acts as Ctrl and Ctrl as GUI, for PCs
+        const CTRL_GUI = 0b01;
+        const ALT_GUI  = 0b10;
+    }
+}
+
+impl ModifierSwaps {
+    /// Rearranges the modifiers of a report, with Ctrl and GUI swapped first
+    /// if both swaps are on
+    pub fn apply(self, mods: Modifiers) -> Modifiers {
+        let mut bits = mods.bits();
+        if self.contains(Self::CTRL_GUI) {
+            bits = swap_bits(bits, Modifiers::LEFT_CTRL | Modifiers::RIGHT_CTRL, 3);
+        }
+        if self.contains(Self::ALT_GUI) {
+            bits = swap_bits(bits, Modifiers::LEFT_ALT | Modifiers::RIGHT_ALT, 1);
+        }
+        Modifiers::from_bits_truncate(bits)
+    }
+}
+
+/// Swaps the bits in `low` with the ones `shift` bits above them
+fn swap_bits(bits: u8, low: Modifiers, shift: u32) -> u8 {
+    let low = low.bits();
+    let high = low << shift;
+    (bits & !(low | high)) | ((bits & low) << shift) | ((bits & high) >> shift)
+}
+
+/// How the host expects Unicode characters to be typed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum UnicodeMode {
+    /// Alt held down while typing the code point, with the "Unicode Hex Input"
+    /// input source selected
+    MacOs = 0,
+    /// Ctrl+Shift+U, the code point, then space, which IBus understands
+    Linux = 1,
+    /// Right Alt, U, the code point, then enter, for WinCompose
+    WinCompose = 2
+}
+
+impl TryFrom<u8> for UnicodeMode {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::MacOs,
+            1 => Self::Linux,
+            2 => Self::WinCompose,
+            _ => return Err(())
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// NUL padded, see [`name`](Self::name)
+    pub name: [u8; NAME_LEN],
+    pub keymap: Keymap,
+    pub swaps: ModifierSwaps,
+    pub unicode: UnicodeMode,
+    /// Report the Fn key to the host as Apple's fn key, on top of it switching
+    /// layers. macOS uses it for dictation and Globe shortcuts
+    pub apple_fn: bool
+}
+
+impl Profile {
+    /// What every profile starts out as, only the keymap is the same for all
+    /// of them
+    pub const DEFAULTS: [Self; PROFILES] = [
+        Self::new(
+            "macOS",
+            Keymap::DEFAULT,
+            ModifierSwaps::empty(),
+            UnicodeMode::MacOs,
+            true
+        ),
+        Self::new(
+            "Windows",
+            Keymap::DEFAULT,
+            ModifierSwaps::CTRL_GUI,
+            UnicodeMode::WinCompose,
+            false
+        ),
+        Self::new(
+            "Linux",
+            Keymap::DEFAULT,
+            ModifierSwaps::CTRL_GUI,
+            UnicodeMode::Linux,
+            false
+        )
+    ];
+
+    /// Makes a profile, names too long to fit get cut off
+    pub const fn new(
+        name: &str,
+        keymap: Keymap,
+        swaps: ModifierSwaps,
+        unicode: UnicodeMode,
+        apple_fn: bool
+    ) -> Self {
+        let mut out = [0u8; NAME_LEN];
+        let bytes = name.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() && i < NAME_LEN {
+            out[i] = bytes[i];
+            i += 1;
+        }
+        Self {
+            name: out,
+            keymap,
+            swaps,
+            unicode,
+            apple_fn
+        }
+    }
+
+    /// The name without its padding, empty if it isn't valid UTF-8
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|b| *b == 0).unwrap_or(NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    /// Renames the profile, returns `false` without changing anything if the
+    /// name doesn't fit
+    pub fn set_name(&mut self, name: &str) -> bool {
+        if name.len() > NAME_LEN || name.contains('\0') {
+            return false;
+        }
+        self.name = [0; NAME_LEN];
+        self.name[..name.len()].copy_from_slice(name.as_bytes());
+        true
+    }
+}
+
+/// Presses a key on top of `held`, then lets go of it
+fn tap(out: &mut Vec<Report, 16>, held: Report, key: KeyCode) {
+    let mut report = held;
+    report.press(key);
+    let _ = out.push(report);
+    let _ = out.push(held);
+}
+
+/// The reports that type out a code point in a [`UnicodeMode`], code points
+/// past `0x7FFF` can't be mapped to a key in the first place
+pub fn unicode_reports(mode: UnicodeMode, code_point: u16) -> Vec<Report, 16> {
+    let mut out = Vec::new();
+    let mut held = Report::new();
+    match mode {
+        UnicodeMode::MacOs => {
+            held.press(KeyCode::KeyboardLeftAlt);
+            let _ = out.push(held);
+        }
+        UnicodeMode::Linux => {
+            let mut report = held;
+            report.press(KeyCode::KeyboardLeftControl);
+            report.press(KeyCode::KeyboardLeftShift);
+            report.press(KeyCode::KeyboardU);
+            let _ = out.push(report);
+            let _ = out.push(held);
+        }
+        UnicodeMode::WinCompose => {
+            tap(&mut out, held, KeyCode::KeyboardRightAlt);
+            tap(&mut out, held, KeyCode::KeyboardU);
+        }
+    }
+    for shift in [12, 8, 4, 0] {
+        let digit = b"0123456789abcdef"[(code_point >> shift) as usize & 0xF];
+        if let Some((key, _)) = KeyCode::from_ascii(digit) {
+            tap(&mut out, held, key);
+        }
+    }
+    match mode {
+        UnicodeMode::MacOs => {
+            let _ = out.push(Report::new());
+        }
+        UnicodeMode::Linux => tap(&mut out, held, KeyCode::KeyboardSpacebar),
+        UnicodeMode::WinCompose => tap(&mut out, held, KeyCode::KeyboardEnter)
+    }
+    out
+}
@@ -0,0 +1,146 @@
+//! Program mode, for remapping keys without any tools on the host
+//!
+//! Holding [`PROGRAM_MODE_COMBO`] switches it on. Tapping a key picks it, then
+//! tapping another one makes the first key do whatever the second one does.
+//! Holding Fn while tapping picks the Fn layer instead, for either of them.
+//! There's nothing on the keyboard to show what's going on with, so every step
+//! gets typed out instead. Holding the combo again switches it back off.
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::{
+    key_codes::KeyCode,
+    keymap::{Action, FN_LAYER},
+    report::Report
+};
+
+/// Positions that switch program mode on and off when held down together:
+/// Fn, CMD and P
+pub const PROGRAM_MODE_COMBO: [u8; 3] = [34, 8, 63];
+/// Where the Fn key is, it picks the Fn layer in program mode no matter what
+/// it's mapped to
+pub const FN_KEY: u8 = 34;
+
+/// What program mode types out
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    On,
+    /// A key got picked, along with what it does right now
+    Picked {
+        fn_layer: bool,
+        action: Action
+    },
+    /// The picked key does this now
+    Remapped(Action),
+    Off
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::On => write!(f, "remap: pick a key "),
+            Message::Picked { fn_layer, action } => {
+                let fn_layer = if *fn_layer { "Fn+" } else { "" };
+                write!(f, "{fn_layer}{action} -> ")
+            }
+            Message::Remapped(action) => write!(f, "{action} saved "),
+            Message::Off => write!(f, "remap done ")
+        }
+    }
+}
+
+/// What's going on in program mode
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Program {
+    /// Positions held down
+    pub held: Vec<u8, 16>,
+    /// Another key went down while one was already held, nothing gets picked
+    /// until they're all let go of
+    pub chord: bool,
+    /// The combo was held, keys get ignored until they're all let go of
+    pub settling: bool,
+    /// Program mode ends once it's done settling
+    pub leaving: bool,
+    /// The key being remapped, along with the layer
+    pub picked: Option<(u8, u8)>
+}
+
+impl Program {
+    /// Starts program mode with the combo still held down
+    pub fn new(held: impl Iterator<Item = u8>) -> Self {
+        Self {
+            held: held.collect(),
+            chord: false,
+            settling: true,
+            leaving: false,
+            picked: None
+        }
+    }
+
+    /// The layer keys get picked from right now
+    #[inline]
+    pub fn layer(&self) -> u8 {
+        if self.held.contains(&FN_KEY) {
+            FN_LAYER
+        } else {
+            0
+        }
+    }
+
+    /// Keys held down other than Fn
+    #[inline]
+    pub fn keys_held(&self) -> usize {
+        self.held.iter().filter(|p| **p != FN_KEY).count()
+    }
+}
+
+/// Longest a message can get, anything past it gets cut off
+const TEXT_LEN: usize = 96;
+
+/// Types out a [`Message`] on a US layout, one report at a time
+pub struct Typer {
+    text: String<TEXT_LEN>,
+    offset: usize,
+    /// The release half of a key
+    pending: Option<Report>
+}
+
+impl Typer {
+    pub fn new(message: Message) -> Self {
+        let mut text = String::new();
+        // too long just means it gets cut off
+        let _ = write!(text, "{message}");
+        Self {
+            text,
+            offset: 0,
+            pending: None
+        }
+    }
+}
+
+impl Iterator for Typer {
+    type Item = Report;
+
+    fn next(&mut self) -> Option<Report> {
+        if let Some(report) = self.pending.take() {
+            return Some(report);
+        }
+        loop {
+            let c = *self.text.as_bytes().get(self.offset)?;
+            self.offset += 1;
+            let Some((key, shift)) = KeyCode::from_ascii(c) else {
+                continue;
+            };
+            let mut report = Report::new();
+            if shift {
+                report.press(KeyCode::KeyboardLeftShift);
+            }
+            report.press(key);
+            self.pending = Some(Report::new());
+            return Some(report);
+        }
+    }
+}
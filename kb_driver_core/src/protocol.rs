@@ -0,0 +1,656 @@
+//! Vendor raw-HID configuration protocol
+//!
+//! Every request is one 32 byte output report, and gets answered with one 32
+//! byte input report. Unused bytes are zero, multi-byte values little endian.
+//!
+//! ```text
+//! request:  [command, args...]
+//! response: [command, status, data...]
+//! ```
+//!
+//! | command | name             | args                        | data                                   |
+//! |---------|------------------|-----------------------------|----------------------------------------|
+//! | `0x40`  | get version      |                             | protocol version, firmware version as a NUL padded string |
+//! | `0x41`  | get capabilities |                             | see [`Capabilities::to_bytes`]         |
+//! | `0x42`  | get keymap       | layer, position, count      | layer, position, count, `count` actions |
+//! | `0x43`  | set keymap       | layer, position, count, `count` actions | layer, position, count     |
+//! | `0x44`  | get diagnostics  | first counter, count        | first, count, `count` u32s, see [`Counter`] |
+//! | `0x45`  | commit           |                             |                                        |
+//! | `0x46`  | revert           |                             |                                        |
+//! | `0x47`  | get setting      | [`Setting`]                 | setting, value (u32)                   |
+//! | `0x48`  | set setting      | [`Setting`], value (u32)    | setting                                |
+//! | `0x49`  | read crash report | offset (u16)               | crashed, offset (u16), count, `count` bytes of [`CrashReport::to_bytes`] |
+//! | `0x4A`  | clear crash report |                           |                                        |
+//! | `0x4B`  | reboot to bootloader |                         |                                        |
+//! | `0x4C`  | begin update     | image length (u32)          |                                        |
+//! | `0x4D`  | write update     | offset (u32), count, `count` bytes of the image | offset (u32)   |
+//! | `0x4E`  | finish update    | CRC-32 of the image (u32)   |                                        |
+//! | `0x4F`  | capture          | 1 to start, 0 to stop       | samples kept (u16), samples dropped (u32) |
+//! | `0x50`  | read capture     | index (u16)                 | index (u16), count, `count` samples of [`Sample::to_bytes`](crate::capture::Sample::to_bytes) |
+//!
+//! Actions are the QMK keycodes from [`Action::to_u16`], at most
+//! [`MAX_ACTIONS`] per request. Changes made with set keymap and set setting
+//! only take effect in RAM until they're committed, revert throws them away.
+//! The device goes away right after answering reboot to bootloader.
+//!
+//! Firmware updates start by erasing room for the image, then it's written
+//! [`MAX_UPDATE_CHUNK`] bytes at a time. Finish update checks the whole image
+//! against its CRC and, if it matches, the device resets into the bootloader
+//! to swap it in. The status is [`Status::InvalidArgument`] for an image that
+//! doesn't fit, a chunk out of place or a CRC that doesn't match.
+//!
+//! Capture records every byte the keyboard sends, see [`capture`](crate::capture).
+//! Starting one throws the last one away, and it can only be read once it's
+//! stopped, until then read capture is [`Status::Busy`]. A count of 0 means
+//! the index is past the end.
+//!
+//! Commands below `0x40` and `0xFE` belong to [VIA and Vial](crate::via),
+//! [`dispatch`] sends each request to the right one.
+
+use crate::{
+    capture::{Capture, SAMPLE_LEN},
+    config::Config,
+    crash_report::{CrashReport, REPORT_LEN as CRASH_REPORT_LEN},
+    firmware_update,
+    keymap::{Action, COLS, LAYERS, POSITIONS, ROWS},
+    macros::{BUFFER_LEN, MACRO_COUNT},
+    power::IdleAction,
+    profile::{ModifierSwaps, UnicodeMode, PROFILES}
+};
+
+/// Size of every request and response
+pub const REPORT_LEN: usize = 32;
+/// Bumped whenever a command changes in a way old hosts can't deal with
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Most keymap actions that fit in one request or response
+pub const MAX_ACTIONS: usize = (REPORT_LEN - 5) / 2;
+/// Most diagnostic counters that fit in one response
+pub const MAX_COUNTERS: usize = (REPORT_LEN - 4) / 4;
+/// Most bytes of the crash report that fit in one response
+pub const MAX_CRASH_CHUNK: usize = REPORT_LEN - 6;
+/// Most bytes of a firmware image in one write update request
+pub const MAX_UPDATE_CHUNK: usize = firmware_update::MAX_CHUNK;
+/// Most capture samples that fit in one response
+pub const MAX_CAPTURE_SAMPLES: usize = (REPORT_LEN - 5) / SAMPLE_LEN;
+
+/// Vendor usage page 0xFF60, usage 0x61, with one 32 byte input and output
+/// report. Same as QMK's raw HID, so the usual tools can find it
+#[rustfmt::skip]
+pub const RAW_HID_DESCRIPTOR: [u8; 34] = [
+    0x06, 0x60, 0xFF, // Usage Page (Vendor 0xFF60)
+    0x09, 0x61,       // Usage (0x61)
+    0xA1, 0x01,       // Collection (Application)
+    0x09, 0x62,       //   Usage (0x62)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x95, 0x20,       //   Report Count (32)
+    0x75, 0x08,       //   Report Size (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x09, 0x63,       //   Usage (0x63)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x95, 0x20,       //   Report Count (32)
+    0x75, 0x08,       //   Report Size (8)
+    0x91, 0x02,       //   Output (Data, Variable, Absolute)
+    0xC0              // End Collection
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Command {
+    GetVersion = 0x40,
+    GetCapabilities = 0x41,
+    GetKeymap = 0x42,
+    SetKeymap = 0x43,
+    GetDiagnostics = 0x44,
+    Commit = 0x45,
+    Revert = 0x46,
+    GetSetting = 0x47,
+    SetSetting = 0x48,
+    ReadCrashReport = 0x49,
+    ClearCrashReport = 0x4A,
+    RebootToBootloader = 0x4B,
+    BeginUpdate = 0x4C,
+    WriteUpdate = 0x4D,
+    FinishUpdate = 0x4E,
+    Capture = 0x4F,
+    ReadCapture = 0x50
+}
+
+impl TryFrom<u8> for Command {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x40 => Self::GetVersion,
+            0x41 => Self::GetCapabilities,
+            0x42 => Self::GetKeymap,
+            0x43 => Self::SetKeymap,
+            0x44 => Self::GetDiagnostics,
+            0x45 => Self::Commit,
+            0x46 => Self::Revert,
+            0x47 => Self::GetSetting,
+            0x48 => Self::SetSetting,
+            0x49 => Self::ReadCrashReport,
+            0x4A => Self::ClearCrashReport,
+            0x4B => Self::RebootToBootloader,
+            0x4C => Self::BeginUpdate,
+            0x4D => Self::WriteUpdate,
+            0x4E => Self::FinishUpdate,
+            0x4F => Self::Capture,
+            0x50 => Self::ReadCapture,
+            _ => return Err(())
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0,
+    UnknownCommand = 1,
+    /// An argument was out of range or an action couldn't be decoded
+    InvalidArgument = 2,
+    /// The firmware couldn't do it right now, try again later
+    Busy = 3
+}
+
+impl TryFrom<u8> for Status {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Ok,
+            1 => Self::UnknownCommand,
+            2 => Self::InvalidArgument,
+            3 => Self::Busy,
+            _ => return Err(())
+        })
+    }
+}
+
+/// Diagnostic counters, in the order get diagnostics returns them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Counter {
+    UptimeMs = 0,
+    /// The reset reason as numbered by the firmware's supervisor
+    ResetReason = 1,
+    KeyEvents = 2,
+    UartErrors = 3,
+    HandshakeFailures = 4,
+    ReportsSent = 5,
+    ConfigCommits = 6
+}
+
+impl Counter {
+    /// Counters from here on are how many times each task was restarted, in
+    /// the firmware's task order
+    pub const TASK_RESTARTS: u8 = 7;
+}
+
+/// Config values that aren't part of the keymap
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Setting {
+    /// [`Config::profile`]
+    Profile = 0,
+    /// [`Config::tapping_term_ms`]
+    TappingTermMs = 1,
+    /// [`Profile::swaps`](crate::profile::Profile::swaps) of the profile in use
+    ModifierSwaps = 2,
+    /// [`Profile::unicode`](crate::profile::Profile::unicode) of the profile in
+    /// use
+    UnicodeMode = 3,
+    /// [`Config::detect_host`], 0 or 1
+    DetectHost = 4,
+    /// [`Profile::apple_fn`](crate::profile::Profile::apple_fn) of the profile
+    /// in use, 0 or 1
+    AppleFn = 5,
+    /// [`Policy::cut_vcc_on_suspend`](crate::power::Policy::cut_vcc_on_suspend),
+    /// 0 or 1
+    CutVccOnSuspend = 6,
+    /// [`Policy::idle_timeout_mins`](crate::power::Policy::idle_timeout_mins)
+    IdleTimeoutMins = 7,
+    /// [`Policy::idle_action`](crate::power::Policy::idle_action)
+    IdleAction = 8
+}
+
+impl TryFrom<u8> for Setting {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Profile,
+            1 => Self::TappingTermMs,
+            2 => Self::ModifierSwaps,
+            3 => Self::UnicodeMode,
+            4 => Self::DetectHost,
+            5 => Self::AppleFn,
+            6 => Self::CutVccOnSuspend,
+            7 => Self::IdleTimeoutMins,
+            8 => Self::IdleAction,
+            _ => return Err(())
+        })
+    }
+}
+
+impl Setting {
+    pub fn get(self, config: &Config) -> u32 {
+        match self {
+            Self::Profile => config.profile as u32,
+            Self::TappingTermMs => config.tapping_term_ms as u32,
+            Self::ModifierSwaps => config.active_profile().swaps.bits() as u32,
+            Self::UnicodeMode => config.active_profile().unicode as u32,
+            Self::DetectHost => config.detect_host as u32,
+            Self::AppleFn => config.active_profile().apple_fn as u32,
+            Self::CutVccOnSuspend => config.power.cut_vcc_on_suspend as u32,
+            Self::IdleTimeoutMins => config.power.idle_timeout_mins as u32,
+            Self::IdleAction => config.power.idle_action as u32
+        }
+    }
+
+    /// Changes the setting, returns `false` if the value is out of range
+    pub fn set(self, config: &mut Config, value: u32) -> bool {
+        match self {
+            Self::Profile => match u8::try_from(value) {
+                Ok(profile) if (profile as usize) < PROFILES => {
+                    config.profile = profile
+                }
+                _ => return false
+            },
+            Self::TappingTermMs => match u16::try_from(value) {
+                Ok(ms) => config.tapping_term_ms = ms,
+                Err(_) => return false
+            },
+            Self::ModifierSwaps => {
+                match u8::try_from(value).ok().and_then(ModifierSwaps::from_bits) {
+                    Some(swaps) => config.active_profile_mut().swaps = swaps,
+                    None => return false
+                }
+            }
+            Self::UnicodeMode => {
+                match u8::try_from(value).ok().map(UnicodeMode::try_from) {
+                    Some(Ok(mode)) => config.active_profile_mut().unicode = mode,
+                    _ => return false
+                }
+            }
+            Self::DetectHost => match value {
+                0 | 1 => config.detect_host = value == 1,
+                _ => return false
+            },
+            Self::AppleFn => match value {
+                0 | 1 => config.active_profile_mut().apple_fn = value == 1,
+                _ => return false
+            },
+            Self::CutVccOnSuspend => match value {
+                0 | 1 => config.power.cut_vcc_on_suspend = value == 1,
+                _ => return false
+            },
+            Self::IdleTimeoutMins => match u16::try_from(value) {
+                Ok(mins) => config.power.idle_timeout_mins = mins,
+                Err(_) => return false
+            },
+            Self::IdleAction => {
+                match u8::try_from(value).ok().map(IdleAction::try_from) {
+                    Some(Ok(action)) => config.power.idle_action = action,
+                    _ => return false
+                }
+            }
+        }
+        true
+    }
+}
+
+/// What the firmware supports, so hosts don't have to hardcode it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Capabilities {
+    pub layers: u8,
+    pub rows: u8,
+    pub cols: u8,
+    pub macro_count: u8,
+    pub macro_buffer_len: u16,
+    /// How many diagnostic counters there are, including task restarts
+    pub counters: u8
+}
+
+impl Capabilities {
+    pub const fn new(counters: u8) -> Self {
+        Self {
+            layers: LAYERS as u8,
+            rows: ROWS as u8,
+            cols: COLS as u8,
+            macro_count: MACRO_COUNT as u8,
+            macro_buffer_len: BUFFER_LEN as u16,
+            counters
+        }
+    }
+
+    /// `[layers, rows, cols, macro count, macro buffer len (u16), counters]`
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let [lo, hi] = self.macro_buffer_len.to_le_bytes();
+        [
+            self.layers,
+            self.rows,
+            self.cols,
+            self.macro_count,
+            lo,
+            hi,
+            self.counters
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let [layers, rows, cols, macro_count, lo, hi, counters, ..] = *bytes else {
+            return None;
+        };
+        Some(Self {
+            layers,
+            rows,
+            cols,
+            macro_count,
+            macro_buffer_len: u16::from_le_bytes([lo, hi]),
+            counters
+        })
+    }
+}
+
+/// Whatever is on the other side of the protocol, the firmware or something
+/// pretending to be it
+pub trait Backend {
+    /// Gives access to the config in RAM, changes aren't saved until
+    /// [`commit`](Self::commit)
+    fn with_config<R>(&mut self, f: impl FnOnce(&mut Config) -> R) -> R;
+    /// Saves the config, returns `false` if that can't happen right now
+    fn commit(&mut self) -> bool;
+    /// Goes back to the last saved config
+    fn revert(&mut self) -> bool;
+    /// Reads a [`Counter`], `None` past the last one
+    fn counter(&mut self, index: u8) -> Option<u32>;
+    fn counter_count(&self) -> u8;
+    fn firmware_version(&self) -> &str;
+    /// Erases the saved config and goes back to the defaults
+    fn factory_reset(&mut self) -> bool;
+    /// The config a factory reset goes back to
+    fn default_config(&self) -> &Config;
+    /// The compressed keyboard definition Vial downloads
+    fn vial_definition(&self) -> &[u8];
+    /// What the firmware crashed with before its last reset, if it did
+    fn crash_report(&mut self) -> Option<CrashReport>;
+    fn clear_crash_report(&mut self);
+    /// Reboots into the bootloader once the response has been sent, returns
+    /// `false` if that can't happen right now
+    fn reboot_to_bootloader(&mut self) -> bool;
+    /// Gets ready to take a firmware image of `len` bytes
+    fn begin_update(&mut self, len: u32) -> Result<(), UpdateError>;
+    /// Writes part of the image, chunks have to line up like
+    /// [`UpdateSlot::write`](firmware_update::UpdateSlot::write) wants
+    fn write_update(&mut self, offset: u32, data: &[u8]) -> Result<(), UpdateError>;
+    /// Checks the image and, if it's fine, resets into it once the response
+    /// has been sent
+    fn finish_update(&mut self, crc: u32) -> Result<(), UpdateError>;
+    /// Gives access to the capture of the keyboard's raw bytes
+    fn with_capture<R>(&mut self, f: impl FnOnce(&mut Capture) -> R) -> R;
+}
+
+/// What can go wrong with an update, the flash's own errors don't say much
+/// over USB anyway
+pub type UpdateError = firmware_update::Error<()>;
+
+/// Answers a request from either this protocol or VIA
+pub fn dispatch<B: Backend>(
+    request: &[u8; REPORT_LEN],
+    response: &mut [u8; REPORT_LEN],
+    backend: &mut B
+) {
+    if crate::via::is_via_command(request[0]) {
+        crate::via::handle(request, response, backend)
+    } else {
+        handle(request, response, backend)
+    }
+}
+
+/// Answers a request, the response always has the command echoed back and a
+/// [`Status`]
+pub fn handle<B: Backend>(
+    request: &[u8; REPORT_LEN],
+    response: &mut [u8; REPORT_LEN],
+    backend: &mut B
+) {
+    *response = [0; REPORT_LEN];
+    response[0] = request[0];
+    let (head, data) = response.split_at_mut(2);
+    let status = match Command::try_from(request[0]) {
+        Ok(command) => run(command, &request[1..], data, backend),
+        Err(_) => Status::UnknownCommand
+    };
+    head[1] = status as u8;
+}
+
+fn run<B: Backend>(
+    command: Command,
+    args: &[u8],
+    out: &mut [u8],
+    backend: &mut B
+) -> Status {
+    match command {
+        Command::GetVersion => {
+            out[0] = PROTOCOL_VERSION;
+            let version = backend.firmware_version().as_bytes();
+            // leave room for at least one NUL
+            let len = version.len().min(out.len() - 2);
+            out[1..1 + len].copy_from_slice(&version[..len]);
+            Status::Ok
+        }
+        Command::GetCapabilities => {
+            let caps = Capabilities::new(backend.counter_count()).to_bytes();
+            out[..caps.len()].copy_from_slice(&caps);
+            Status::Ok
+        }
+        Command::GetKeymap => {
+            let [layer, pos, count, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            let Some(range) = keymap_range(layer, pos, count) else {
+                return Status::InvalidArgument;
+            };
+            out[..3].copy_from_slice(&[layer, pos, count]);
+            backend.with_config(|config| {
+                let actions = &config.keymap().layers[layer as usize][range];
+                let (chunks, _) = out[3..].as_chunks_mut::<2>();
+                for (chunk, action) in chunks.iter_mut().zip(actions) {
+                    *chunk = action.to_u16().to_le_bytes();
+                }
+            });
+            Status::Ok
+        }
+        Command::SetKeymap => {
+            let [layer, pos, count, ref values @ ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            let Some(range) = keymap_range(layer, pos, count) else {
+                return Status::InvalidArgument;
+            };
+            if values.len() < range.len() * 2 {
+                return Status::InvalidArgument;
+            }
+            let mut actions = [Action::None; MAX_ACTIONS];
+            let (chunks, _) = values.as_chunks::<2>();
+            for (action, &chunk) in actions.iter_mut().zip(chunks) {
+                match Action::from_u16(u16::from_le_bytes(chunk)) {
+                    Some(a) => *action = a,
+                    None => return Status::InvalidArgument
+                }
+            }
+            backend.with_config(|config| {
+                config.keymap_mut().layers[layer as usize][range]
+                    .copy_from_slice(&actions[..count as usize]);
+            });
+            out[..3].copy_from_slice(&[layer, pos, count]);
+            Status::Ok
+        }
+        Command::GetDiagnostics => {
+            let [first, count, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            if count as usize > MAX_COUNTERS {
+                return Status::InvalidArgument;
+            }
+            let mut read = 0;
+            let (chunks, _) = out[2..].as_chunks_mut::<4>();
+            for (i, chunk) in chunks.iter_mut().take(count as usize).enumerate() {
+                let Some(value) = backend.counter(first.wrapping_add(i as u8))
+                else {
+                    break;
+                };
+                chunk.copy_from_slice(&value.to_le_bytes());
+                read += 1;
+            }
+            out[..2].copy_from_slice(&[first, read]);
+            Status::Ok
+        }
+        Command::Commit => match backend.commit() {
+            true => Status::Ok,
+            false => Status::Busy
+        },
+        Command::Revert => match backend.revert() {
+            true => Status::Ok,
+            false => Status::Busy
+        },
+        Command::GetSetting => {
+            let Some(setting) =
+                args.first().and_then(|s| Setting::try_from(*s).ok())
+            else {
+                return Status::InvalidArgument;
+            };
+            let value = backend.with_config(|config| setting.get(config));
+            out[0] = setting as u8;
+            out[1..5].copy_from_slice(&value.to_le_bytes());
+            Status::Ok
+        }
+        Command::SetSetting => {
+            let [setting, a, b, c, d, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            let Ok(setting) = Setting::try_from(setting) else {
+                return Status::InvalidArgument;
+            };
+            let value = u32::from_le_bytes([a, b, c, d]);
+            if !backend.with_config(|config| setting.set(config, value)) {
+                return Status::InvalidArgument;
+            }
+            out[0] = setting as u8;
+            Status::Ok
+        }
+        Command::ReadCrashReport => {
+            let [lo, hi, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            let offset = u16::from_le_bytes([lo, hi]);
+            out[1..3].copy_from_slice(&offset.to_le_bytes());
+            let Some(report) = backend.crash_report() else {
+                return Status::Ok;
+            };
+            let bytes = report.to_bytes();
+            let start = (offset as usize).min(CRASH_REPORT_LEN);
+            let count = (CRASH_REPORT_LEN - start).min(MAX_CRASH_CHUNK);
+            out[0] = 1;
+            out[3] = count as u8;
+            out[4..4 + count].copy_from_slice(&bytes[start..start + count]);
+            Status::Ok
+        }
+        Command::ClearCrashReport => {
+            backend.clear_crash_report();
+            Status::Ok
+        }
+        Command::RebootToBootloader => match backend.reboot_to_bootloader() {
+            true => Status::Ok,
+            false => Status::Busy
+        },
+        Command::BeginUpdate => {
+            let [a, b, c, d, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            update_status(backend.begin_update(u32::from_le_bytes([a, b, c, d])))
+        }
+        Command::WriteUpdate => {
+            let [a, b, c, d, count, ref data @ ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            if count as usize > MAX_UPDATE_CHUNK {
+                return Status::InvalidArgument;
+            }
+            let offset = u32::from_le_bytes([a, b, c, d]);
+            out[..4].copy_from_slice(&offset.to_le_bytes());
+            update_status(backend.write_update(offset, &data[..count as usize]))
+        }
+        Command::FinishUpdate => {
+            let [a, b, c, d, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            update_status(backend.finish_update(u32::from_le_bytes([a, b, c, d])))
+        }
+        Command::Capture => {
+            let (len, dropped) = match args.first() {
+                Some(1) => backend.with_capture(|c| {
+                    c.start();
+                    (0, 0)
+                }),
+                Some(0) => backend.with_capture(|c| {
+                    c.stop();
+                    (c.len() as u16, c.dropped())
+                }),
+                _ => return Status::InvalidArgument
+            };
+            out[..2].copy_from_slice(&len.to_le_bytes());
+            out[2..6].copy_from_slice(&dropped.to_le_bytes());
+            Status::Ok
+        }
+        Command::ReadCapture => {
+            let [lo, hi, ..] = *args else {
+                return Status::InvalidArgument;
+            };
+            let index = u16::from_le_bytes([lo, hi]);
+            out[..2].copy_from_slice(&index.to_le_bytes());
+            backend.with_capture(|capture| {
+                if capture.is_recording() {
+                    return Status::Busy;
+                }
+                let mut count = 0;
+                let (chunks, _) = out[3..].as_chunks_mut::<SAMPLE_LEN>();
+                for (chunk, i) in chunks
+                    .iter_mut()
+                    .zip(index as usize..)
+                    .take(MAX_CAPTURE_SAMPLES)
+                {
+                    let Some(sample) = capture.get(i) else {
+                        break;
+                    };
+                    chunk.copy_from_slice(&sample.to_bytes());
+                    count += 1;
+                }
+                out[2] = count;
+                Status::Ok
+            })
+        }
+    }
+}
+
+fn update_status(result: Result<(), UpdateError>) -> Status {
+    match result {
+        Ok(()) => Status::Ok,
+        Err(UpdateError::Flash(()) | UpdateError::Busy) => Status::Busy,
+        Err(_) => Status::InvalidArgument
+    }
+}
+
+/// Checks that `count` positions starting at `pos` exist on `layer`
+fn keymap_range(layer: u8, pos: u8, count: u8) -> Option<core::ops::Range<usize>> {
+    let (pos, count) = (pos as usize, count as usize);
+    ((layer as usize) < LAYERS && count <= MAX_ACTIONS && pos + count <= POSITIONS)
+        .then_some(pos..pos + count)
+}
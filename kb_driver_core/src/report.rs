@@ -0,0 +1,102 @@
+use crate::key_codes::{KeyCode, Modifiers};
+
+/// HID report descriptor of the keyboard interface, a boot keyboard with
+/// Apple's fn key in the lowest bit of the reserved byte, which hosts going by
+/// the boot protocol ignore anyway
+#[rustfmt::skip]
+pub const KEYBOARD_DESCRIPTOR: [u8; 64] = [
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x06,       // Usage (Keyboard)
+    0xA1, 0x01,       // Collection (Application)
+    0x05, 0x07,       //   Usage Page (Keyboard/Keypad)
+    0x19, 0xE0,       //   Usage Minimum (Left Control)
+    0x29, 0xE7,       //   Usage Maximum (Right GUI)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x75, 0x01,       //   Report Size (1)
+    0x95, 0x08,       //   Report Count (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x05, 0xFF,       //   Usage Page (Apple Vendor Top Case)
+    0x09, 0x03,       //   Usage (Keyboard Fn)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x95, 0x07,       //   Report Count (7)
+    0x81, 0x01,       //   Input (Constant)
+    0x05, 0x08,       //   Usage Page (LEDs)
+    0x19, 0x01,       //   Usage Minimum (Num Lock)
+    0x29, 0x05,       //   Usage Maximum (Kana)
+    0x95, 0x05,       //   Report Count (5)
+    0x91, 0x02,       //   Output (Data, Variable, Absolute)
+    0x95, 0x03,       //   Report Count (3)
+    0x91, 0x01,       //   Output (Constant)
+    0x05, 0x07,       //   Usage Page (Keyboard/Keypad)
+    0x19, 0x00,       //   Usage Minimum (0)
+    0x29, 0xFF,       //   Usage Maximum (255)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x06,       //   Report Count (6)
+    0x81, 0x00,       //   Input (Data, Array, Absolute)
+    0xC0              // End Collection
+];
+
+/// A boot keyboard report, without the LED byte
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub modifiers: Modifiers,
+    /// Apple's fn key, see [`KEYBOARD_DESCRIPTOR`]
+    pub apple_fn: bool,
+    pub keycodes: [u8; 6]
+}
+
+impl Report {
+    pub const fn new() -> Self {
+        Self {
+            modifiers: Modifiers::empty(),
+            apple_fn: false,
+            keycodes: [0; 6]
+        }
+    }
+
+    /// Adds a key to the report, modifier keys are added as modifiers. Returns
+    /// `false` if all 6 slots are taken
+    pub fn press(&mut self, key: KeyCode) -> bool {
+        if key.is_modifier() {
+            self.modifiers.insert(Modifiers::from(key));
+            return true;
+        }
+        if self.keycodes.contains(&(key as u8)) {
+            return true;
+        }
+        match self.keycodes.iter_mut().find(|k| **k == 0) {
+            Some(slot) => {
+                *slot = key as u8;
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Removes a key from the report, keeping the remaining keys in order
+    pub fn release(&mut self, key: KeyCode) {
+        if key.is_modifier() {
+            self.modifiers.remove(Modifiers::from(key));
+            return;
+        }
+        if let Some(i) = self.keycodes.iter().position(|k| *k == key as u8) {
+            self.keycodes.copy_within(i + 1.., i);
+            self.keycodes[5] = 0;
+        }
+    }
+}
+
+#[cfg(feature = "usbd-hid")]
+impl From<Report> for usbd_hid::descriptor::KeyboardReport {
+    fn from(value: Report) -> Self {
+        Self {
+            modifier: value.modifiers.bits(),
+            reserved: value.apple_fn as u8,
+            leds: 0,
+            keycodes: value.keycodes
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use heapless::{Deque, Vec};
+
+use crate::{
+    combo::{Combo, COMBO_TERM_MS, MAX_KEYS},
+    config::{Config, FACTORY_RESET_COMBO},
+    debug, error,
+    key_codes::KeyCode,
+    keymap::Action,
+    profile::ModifierSwaps,
+    program::{Message, Program, FN_KEY, PROGRAM_MODE_COMBO},
+    report::Report,
+    warn
+};
+
+/// Positions that reboot into the bootloader for flashing when held down
+/// together: Fn, CMD and B
+pub const BOOTLOADER_COMBO: [u8; 3] = [34, 8, 46];
+
+/// Things the keyboard asked for that the state can't do on its own
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    PlayMacro(u8),
+    FactoryReset,
+    /// Reboot into the system bootloader
+    Bootloader,
+    /// Type out a message from program mode
+    Type(Message),
+    /// Change what a position does on a layer and save it
+    Remap {
+        layer: u8,
+        pos: u8,
+        action: Action
+    },
+    /// Switch to another host profile and save that
+    SwitchProfile(u8),
+    /// Type a Unicode character the way the profile in use says to
+    Unicode(u16)
+}
+
+/// A tap-hold key that hasn't been decided on yet
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PendingTap {
+    pos: u8,
+    action: Action,
+    deadline_ms: u32
+}
+
+/// Combo keys that went down recently, held back until it's clear whether
+/// they're a combo or not
+#[derive(Clone, PartialEq, Eq)]
+struct PendingCombo {
+    /// Positions along with what they'd do on their own
+    presses: Vec<(u8, Action), MAX_KEYS>,
+    /// The tapping term when they went down, in case they turn out to be
+    /// tap-hold keys
+    tapping_term_ms: u16,
+    deadline_ms: u32
+}
+
+pub struct State {
+    last_key_up: Option<u8>,
+    /// Positions held down, along with what they did when pressed
+    held: Vec<(u8, Action), 16>,
+    /// Bitmask of the layers switched on, layer 0 is always on
+    layers: u8,
+    report: Report,
+    /// From the profile in use, applied to reports as they're queued up
+    swaps: ModifierSwaps,
+    pending_tap: Option<PendingTap>,
+    pending_combo: Option<PendingCombo>,
+    /// Set while in program mode, which takes over every key
+    program: Option<Program>,
+    reports: Deque<Report, 16>,
+    commands: Deque<Command, 4>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum InputType {
+    KeyUp,
+    KeyDown
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The bit for a layer in a layer bitmask, layers that don't fit in it
+/// don't exist anyway
+#[inline]
+fn layer_bit(layer: u8) -> u8 {
+    1u8.checked_shl(layer as u32).unwrap_or(0)
+}
+
+/// `now` is at or past `deadline`, with both wrapping around
+#[inline]
+fn reached(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            last_key_up: None,
+            held: Vec::new(),
+            layers: 1,
+            report: Report::new(),
+            swaps: ModifierSwaps::empty(),
+            pending_tap: None,
+            pending_combo: None,
+            program: None,
+            reports: Deque::new(),
+            commands: Deque::new()
+        }
+    }
+
+    /// Lets go of everything, sending an empty report if anything was held
+    pub fn reset(&mut self) {
+        let had_keys = self.report != Report::new();
+        let reports = core::mem::take(&mut self.reports);
+        let commands = core::mem::take(&mut self.commands);
+        *self = Self::new();
+        self.reports = reports;
+        self.commands = commands;
+        if had_keys {
+            self.push_report();
+        }
+    }
+
+    /// Uses values received directly from the UART line to update the state,
+    /// `now_ms` is only compared to itself so it can start anywhere
+    pub fn update_from_kb_input(&mut self, input: u8, config: &Config, now_ms: u32) {
+        let pos = input & 0b0111_1111;
+        if KeyCode::try_from_matrix_key(input).is_none() {
+            error!("received invalid matrix coordinates from device");
+            return;
+        }
+        let input_type = InputType::from(input);
+        debug!("received key at {} with input type {:?}", pos, input_type);
+        self.swaps = config.active_profile().swaps;
+
+        if self.program.is_some() {
+            self.update_program(pos, input_type, config);
+            self.last_key_up = None;
+            return;
+        }
+        match input_type {
+            InputType::KeyUp => {
+                // the keyboard sends the last key up twice once everything
+                // has been let go of
+                if Some(pos) == self.last_key_up {
+                    self.reset();
+                } else {
+                    self.release(pos, config, now_ms);
+                }
+                self.last_key_up = Some(pos);
+            }
+            InputType::KeyDown => {
+                self.press(pos, config, now_ms);
+                self.last_key_up = None;
+            }
+        }
+
+        // Fn still does whatever it's mapped to, this only tells the host.
+        // Program mode could've just been switched on, and Fn going up
+        // wouldn't make it here then
+        if let Some((KeyCode::KeyboardFn, _)) = KeyCode::try_from_matrix_key(input) {
+            let apple_fn = input_type == InputType::KeyDown
+                && config.active_profile().apple_fn
+                && self.program.is_none();
+            if apple_fn != self.report.apple_fn {
+                self.report.apple_fn = apple_fn;
+                self.push_report();
+            }
+        }
+    }
+
+    /// Gives up on combos and decides on tap-hold keys that have been held
+    /// long enough, MUST be called once [`next_deadline`](Self::next_deadline)
+    /// is reached
+    pub fn tick(&mut self, now_ms: u32) {
+        if let Some(pending) = self
+            .pending_combo
+            .take_if(|c| reached(now_ms, c.deadline_ms))
+        {
+            self.flush_combo(pending, now_ms);
+        }
+        if let Some(pending) = self.pending_tap {
+            if reached(now_ms, pending.deadline_ms) {
+                self.resolve_hold();
+            }
+        }
+    }
+
+    /// When [`tick`](Self::tick) has to be called next, if at all
+    #[inline]
+    pub fn next_deadline(&self) -> Option<u32> {
+        let tap = self.pending_tap.map(|p| p.deadline_ms);
+        match (tap, self.pending_combo.as_ref().map(|c| c.deadline_ms)) {
+            (Some(tap), Some(combo)) if reached(combo, tap) => Some(tap),
+            (tap, combo) => combo.or(tap)
+        }
+    }
+
+    /// Takes the oldest report that hasn't been sent yet
+    #[inline]
+    pub fn pop_report(&mut self) -> Option<Report> {
+        self.reports.pop_front()
+    }
+
+    #[inline]
+    pub fn pop_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    /// Whether there are reports or commands waiting to be popped
+    #[inline]
+    pub fn has_output(&self) -> bool {
+        !self.reports.is_empty() || !self.commands.is_empty()
+    }
+
+    /// What's held down right now, as the host sees it
+    #[inline]
+    pub fn report(&self) -> Report {
+        let mut report = self.report;
+        report.modifiers = self.swaps.apply(report.modifiers);
+        report
+    }
+
+    /// Checks if program mode is on
+    #[inline]
+    pub fn programming(&self) -> bool {
+        self.program.is_some()
+    }
+
+    /// The highest keymap layer currently switched on
+    #[inline]
+    pub fn active_layer(&self) -> u8 {
+        7 - self.layers.leading_zeros() as u8
+    }
+
+    fn press(&mut self, pos: u8, config: &Config, now_ms: u32) {
+        let buffered = self
+            .pending_combo
+            .as_ref()
+            .is_some_and(|c| c.presses.iter().any(|(p, _)| *p == pos));
+        if buffered || self.held.iter().any(|(p, _)| *p == pos) {
+            warn!("tried to insert pressed key that was already pressed");
+            return;
+        }
+        // another key going down means a pending tap-hold key is being held
+        if self.pending_tap.is_some() {
+            self.resolve_hold();
+        }
+
+        let action = config.keymap().resolve(pos as usize, self.layers);
+        if let Some(mut pending) = self.pending_combo.take() {
+            let could_be_combo = config.combos.iter().any(|combo| {
+                combo.contains(pos)
+                    && pending.presses.iter().all(|(p, _)| combo.contains(*p))
+            });
+            if could_be_combo && pending.presses.push((pos, action)).is_ok() {
+                match config.combos.iter().find(|c| is_complete(c, &pending)) {
+                    Some(combo) => self.fire_combo(combo, pending, now_ms),
+                    None => self.pending_combo = Some(pending)
+                }
+                return;
+            }
+            // not a combo after all
+            self.flush_combo(pending, now_ms);
+            // the held back keys could've been the program mode combo
+            if self.program.is_some() {
+                return self.update_program(pos, InputType::KeyDown, config);
+            }
+        }
+        if config.combos.iter().any(|c| c.contains(pos)) {
+            let mut presses = Vec::new();
+            let _ = presses.push((pos, action));
+            self.pending_combo = Some(PendingCombo {
+                presses,
+                tapping_term_ms: config.tapping_term_ms,
+                deadline_ms: now_ms.wrapping_add(COMBO_TERM_MS)
+            });
+            return;
+        }
+        self.activate(pos, action, config.tapping_term_ms, now_ms);
+    }
+
+    /// Does what a position's action does when it goes down
+    fn activate(
+        &mut self,
+        pos: u8,
+        action: Action,
+        tapping_term_ms: u16,
+        now_ms: u32
+    ) {
+        // it couldn't be let go of later
+        if self.held.is_full() {
+            warn!("too many keys held down, ignoring one");
+            return;
+        }
+        if self.pending_tap.is_some() {
+            self.resolve_hold();
+        }
+        match action {
+            Action::Key(key) => self.press_key(key),
+            Action::Layer(layer) => self.layers |= layer_bit(layer),
+            Action::Macro(index) => self.command(Command::PlayMacro(index)),
+            Action::Profile(profile) => {
+                self.command(Command::SwitchProfile(profile))
+            }
+            Action::Unicode(code_point) => {
+                self.command(Command::Unicode(code_point))
+            }
+            Action::ModTap(..) | Action::LayerTap(..) => {
+                self.pending_tap = Some(PendingTap {
+                    pos,
+                    action,
+                    deadline_ms: now_ms.wrapping_add(tapping_term_ms as u32)
+                });
+            }
+            Action::None | Action::Transparent => ()
+        }
+        // checked above
+        let _ = self.held.push((pos, action));
+
+        if FACTORY_RESET_COMBO
+            .iter()
+            .all(|p| self.held.iter().any(|(held, _)| held == p))
+        {
+            warn!("factory reset combo pressed");
+            self.command(Command::FactoryReset);
+        }
+        if BOOTLOADER_COMBO
+            .iter()
+            .all(|p| self.held.iter().any(|(held, _)| held == p))
+        {
+            warn!("bootloader combo pressed");
+            self.command(Command::Bootloader);
+        }
+        if PROGRAM_MODE_COMBO
+            .iter()
+            .all(|p| self.held.iter().any(|(held, _)| held == p))
+        {
+            debug!("entering program mode");
+            let program = Program::new(self.held.iter().map(|(p, _)| *p));
+            self.reset();
+            self.program = Some(program);
+            self.command(Command::Type(Message::On));
+        }
+    }
+
+    /// Handles a key in program mode, see [`program`](crate::program)
+    fn update_program(&mut self, pos: u8, input_type: InputType, config: &Config) {
+        let Some(program) = self.program.as_mut() else {
+            return;
+        };
+        if input_type == InputType::KeyDown {
+            if program.held.contains(&pos) || program.held.push(pos).is_err() {
+                return;
+            }
+            if program.keys_held() > 1 {
+                program.chord = true;
+            }
+            if PROGRAM_MODE_COMBO.iter().all(|p| program.held.contains(p)) {
+                program.settling = true;
+                program.leaving = true;
+                program.picked = None;
+            }
+            return;
+        }
+
+        // the keyboard sends the last key up twice, the second one isn't held
+        let Some(i) = program.held.iter().position(|p| *p == pos) else {
+            return;
+        };
+        let layer = program.layer();
+        program.held.swap_remove(i);
+        if program.settling {
+            if program.held.is_empty() {
+                program.settling = false;
+                program.chord = false;
+                if program.leaving {
+                    debug!("leaving program mode");
+                    self.program = None;
+                    self.command(Command::Type(Message::Off));
+                }
+            }
+            return;
+        }
+        if pos == FN_KEY {
+            return;
+        }
+        if program.chord {
+            program.chord = program.keys_held() > 0;
+            return;
+        }
+
+        // what the key does on that layer, layer 0 is always on
+        let action = config.keymap().resolve(pos as usize, 1 | layer_bit(layer));
+        match program.picked.take() {
+            None => {
+                program.picked = Some((pos, layer));
+                let fn_layer = layer != 0;
+                self.command(Command::Type(Message::Picked { fn_layer, action }));
+            }
+            Some((picked, picked_layer)) => {
+                self.command(Command::Remap {
+                    layer: picked_layer,
+                    pos: picked,
+                    action
+                });
+                self.command(Command::Type(Message::Remapped(action)));
+            }
+        }
+    }
+
+    /// Presses keys that were held back for a combo that didn't happen
+    fn flush_combo(&mut self, pending: PendingCombo, now_ms: u32) {
+        for (pos, action) in pending.presses {
+            match self.program.as_mut() {
+                // one of them switched program mode on, which takes the rest
+                Some(program) => {
+                    let _ = program.held.push(pos);
+                }
+                None => self.activate(pos, action, pending.tapping_term_ms, now_ms)
+            }
+        }
+    }
+
+    /// The combo's action happens on its first key, the others do nothing
+    /// until they're let go of
+    fn fire_combo(&mut self, combo: &Combo, pending: PendingCombo, now_ms: u32) {
+        debug!("combo pressed");
+        let [(first, _), ref rest @ ..] = pending.presses[..] else {
+            return;
+        };
+        self.activate(first, combo.action, pending.tapping_term_ms, now_ms);
+        for (pos, _) in rest {
+            if self.held.push((*pos, Action::None)).is_err() {
+                warn!("too many keys held down, ignoring one");
+            }
+        }
+    }
+
+    fn release(&mut self, pos: u8, config: &Config, now_ms: u32) {
+        if let Some(pending) = self
+            .pending_combo
+            .take_if(|c| c.presses.iter().any(|(p, _)| *p == pos))
+        {
+            self.flush_combo(pending, now_ms);
+            // the held back keys could've been the program mode combo
+            if self.program.is_some() {
+                return self.update_program(pos, InputType::KeyUp, config);
+            }
+        }
+        let Some(i) = self.held.iter().position(|(p, _)| *p == pos) else {
+            return;
+        };
+        let (_, action) = self.held.swap_remove(i);
+
+        if let Some(pending) = self.pending_tap.filter(|p| p.pos == pos) {
+            self.pending_tap = None;
+            if let Action::ModTap(_, key) | Action::LayerTap(_, key) = pending.action
+            {
+                self.press_key(key);
+                self.release_key(key);
+            }
+            return;
+        }
+
+        match action {
+            Action::Key(key) => self.release_key(key),
+            Action::Layer(layer) | Action::LayerTap(layer, _) => {
+                // another key could still be holding the same layer
+                let still_held = self.held.iter().any(|(_, a)| {
+                    matches!(a, Action::Layer(l) | Action::LayerTap(l, _) if *l == layer)
+                });
+                if !still_held && layer != 0 {
+                    self.layers &= !layer_bit(layer);
+                }
+            }
+            Action::ModTap(mods, _) => {
+                self.report.modifiers.remove(mods);
+                self.push_report();
+            }
+            Action::Macro(_)
+            | Action::Profile(_)
+            | Action::Unicode(_)
+            | Action::None
+            | Action::Transparent => ()
+        }
+    }
+
+    fn resolve_hold(&mut self) {
+        let Some(pending) = self.pending_tap.take() else {
+            return;
+        };
+        match pending.action {
+            Action::ModTap(mods, _) => {
+                self.report.modifiers.insert(mods);
+                self.push_report();
+            }
+            Action::LayerTap(layer, _) => self.layers |= layer_bit(layer),
+            _ => ()
+        }
+    }
+
+    fn press_key(&mut self, key: KeyCode) {
+        if !self.report.press(key) {
+            warn!("tried to push new key code into full keycode vec");
+            self.report.keycodes.copy_within(1.., 0);
+            self.report.keycodes[5] = key as u8;
+        }
+        self.push_report();
+    }
+
+    fn release_key(&mut self, key: KeyCode) {
+        self.report.release(key);
+        self.push_report();
+    }
+
+    fn push_report(&mut self) {
+        if self.reports.is_full() {
+            warn!("report queue full, dropping the oldest one");
+            self.reports.pop_front();
+        }
+        let _ = self.reports.push_back(self.report());
+    }
+
+    fn command(&mut self, command: Command) {
+        if self.commands.push_back(command).is_err() {
+            warn!("command queue full, dropping {}", command);
+        }
+    }
+}
+
+/// Checks if every key of a combo is down, and nothing else
+fn is_complete(combo: &Combo, pending: &PendingCombo) -> bool {
+    combo.len() == pending.presses.len()
+        && pending.presses.iter().all(|(p, _)| combo.contains(*p))
+}
+
+/// Checks if a value received directly from the UART line is a key press
+#[inline]
+pub fn is_key_down(input: u8) -> bool {
+    InputType::from(input) == InputType::KeyDown
+}
+
+impl From<u8> for InputType {
+    /// MUST be used with a value received directly from the UART line
+    ///
+    /// MSB 1 means KeyUp, MSB 0 means KeyDown
+    fn from(value: u8) -> Self {
+        if value & (1 << 7) == 0 {
+            Self::KeyDown
+        } else {
+            Self::KeyUp
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Usages from HID pages other than the keyboard's, generated by `build.rs`
+//! the same way as [`KeyCode`](crate::key_codes::KeyCode)
+
+use crate::key_codes::UnknownName;
+
+include!(concat!(env!("OUT_DIR"), "/consumer.rs"));
+include!(concat!(env!("OUT_DIR"), "/desktop.rs"));
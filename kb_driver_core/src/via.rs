@@ -0,0 +1,411 @@
+//! Enough of VIA's raw HID protocol (version 12) and Vial's extensions to it
+//! for both apps to remap the keyboard
+//!
+//! VIA shares the raw HID interface with [`protocol`](crate::protocol), its
+//! commands are all below `0x40` and Vial's start with `0xFE`. Unlike the
+//! vendor protocol, VIA answers by echoing the request back with the results
+//! filled in, multi-byte values are big endian, and anything unknown gets its
+//! first byte replaced with `0xFF`.
+//!
+//! Keymap positions are the matrix's `(Y, X)`, which VIA calls `(row, col)`.
+//! VIA expects every change to be saved right away, so each one asks the
+//! backend to commit.
+
+use core::fmt::{self, Write};
+
+use crate::{
+    keymap::{Action, COLS, LAYERS, POSITIONS, ROWS},
+    macros::{Macros, BUFFER_LEN, MACRO_COUNT},
+    matrix::MATRIX,
+    protocol::{Backend, Counter, REPORT_LEN}
+};
+
+pub const VIA_PROTOCOL_VERSION: u16 = 12;
+/// Vial protocol 6 is the first one with QMK's current keycodes, which is
+/// what [`Action::to_u16`] uses
+pub const VIAL_PROTOCOL_VERSION: u32 = 6;
+/// Identifies the keyboard to Vial, which uses it to cache the definition
+pub const VIAL_KEYBOARD_ID: u64 = 0x5A4C_7E09_D3B1_6F42;
+
+pub const VENDOR_ID: u16 = 0x1209;
+pub const PRODUCT_ID: u16 = 0x0011;
+/// Vial only looks at devices with this in their serial number
+pub const VIAL_SERIAL_NUMBER: &str = "vial:f64c2b3c";
+
+const UNHANDLED: u8 = 0xFF;
+const VIAL_PREFIX: u8 = 0xFE;
+
+// VIA's commands
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+const CUSTOM_SAVE: u8 = 0x09;
+const EEPROM_RESET: u8 = 0x0A;
+const DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0C;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+const DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+const DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+const DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+
+// keyboard values
+const UPTIME: u8 = 0x01;
+const LAYOUT_OPTIONS: u8 = 0x02;
+const FIRMWARE_VERSION: u8 = 0x04;
+const DEVICE_INDICATION: u8 = 0x05;
+
+// Vial's commands, after the prefix
+const VIAL_GET_KEYBOARD_ID: u8 = 0x00;
+const VIAL_GET_SIZE: u8 = 0x01;
+const VIAL_GET_DEFINITION: u8 = 0x02;
+const VIAL_GET_UNLOCK_STATUS: u8 = 0x05;
+const VIAL_QMK_SETTINGS_QUERY: u8 = 0x09;
+const VIAL_DYNAMIC_ENTRY_OP: u8 = 0x0D;
+
+/// Most bytes a buffer request can carry
+const MAX_BUFFER_CHUNK: usize = REPORT_LEN - 4;
+/// Size of the keymap as VIA sees it, two bytes per key
+const KEYMAP_BUFFER_LEN: usize = LAYERS * POSITIONS * 2;
+
+/// Whether a request belongs to VIA or Vial rather than the vendor protocol
+#[inline]
+pub fn is_via_command(command: u8) -> bool {
+    command < 0x40 || command == VIAL_PREFIX
+}
+
+/// Answers a VIA or Vial request
+pub fn handle<B: Backend>(
+    request: &[u8; REPORT_LEN],
+    response: &mut [u8; REPORT_LEN],
+    backend: &mut B
+) {
+    *response = *request;
+    let handled = match request[0] {
+        VIAL_PREFIX => vial(request[1], request, response, backend),
+        command => via(command, response, backend)
+    };
+    if !handled {
+        *response = *request;
+        response[0] = UNHANDLED;
+    }
+}
+
+fn via<B: Backend>(
+    command: u8,
+    data: &mut [u8; REPORT_LEN],
+    backend: &mut B
+) -> bool {
+    match command {
+        GET_PROTOCOL_VERSION => {
+            data[1..3].copy_from_slice(&VIA_PROTOCOL_VERSION.to_be_bytes());
+        }
+        GET_KEYBOARD_VALUE => {
+            let value = match data[1] {
+                UPTIME => backend.counter(Counter::UptimeMs as u8).unwrap_or(0),
+                LAYOUT_OPTIONS => backend.with_config(|c| c.layout_options),
+                FIRMWARE_VERSION => version_number(backend.firmware_version()),
+                _ => return false
+            };
+            data[2..6].copy_from_slice(&value.to_be_bytes());
+        }
+        SET_KEYBOARD_VALUE => match data[1] {
+            LAYOUT_OPTIONS => {
+                let options =
+                    u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+                backend.with_config(|c| c.layout_options = options);
+                backend.commit();
+            }
+            // there's nothing to blink yet, but VIA gets upset if it's refused
+            DEVICE_INDICATION => {}
+            _ => return false
+        },
+        DYNAMIC_KEYMAP_GET_KEYCODE => {
+            let Some(pos) = position(data[2], data[3]) else {
+                return false;
+            };
+            let layer = data[1] as usize;
+            let action = backend
+                .with_config(|c| c.keymap().layers.get(layer).map(|l| l[pos]));
+            data[4..6]
+                .copy_from_slice(&action.unwrap_or_default().to_u16().to_be_bytes());
+        }
+        DYNAMIC_KEYMAP_SET_KEYCODE => {
+            let (Some(pos), Some(action)) = (
+                position(data[2], data[3]),
+                Action::from_u16(u16::from_be_bytes([data[4], data[5]]))
+            ) else {
+                return false;
+            };
+            let layer = data[1] as usize;
+            if layer >= LAYERS {
+                return false;
+            }
+            backend.with_config(|c| c.keymap_mut().layers[layer][pos] = action);
+            backend.commit();
+        }
+        DYNAMIC_KEYMAP_RESET => {
+            // only the profile in use goes back to its defaults
+            let profile = backend.with_config(|c| c.profile as usize);
+            let defaults = &backend.default_config().profiles;
+            let keymap =
+                defaults.get(profile).unwrap_or(&defaults[0]).keymap.clone();
+            backend.with_config(|c| *c.keymap_mut() = keymap);
+            backend.commit();
+        }
+        CUSTOM_SAVE => {
+            backend.commit();
+        }
+        EEPROM_RESET => {
+            backend.factory_reset();
+        }
+        DYNAMIC_KEYMAP_MACRO_GET_COUNT => data[1] = MACRO_COUNT as u8,
+        DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => {
+            data[1..3].copy_from_slice(&(BUFFER_LEN as u16).to_be_bytes());
+        }
+        DYNAMIC_KEYMAP_MACRO_GET_BUFFER => {
+            let Some(range) = buffer_range(data, BUFFER_LEN) else {
+                return false;
+            };
+            let len = range.len();
+            backend.with_config(|c| {
+                data[4..4 + len].copy_from_slice(&c.macros.buffer[range]);
+            });
+        }
+        DYNAMIC_KEYMAP_MACRO_SET_BUFFER => {
+            let Some(range) = buffer_range(data, BUFFER_LEN) else {
+                return false;
+            };
+            let len = range.len();
+            backend.with_config(|c| {
+                c.macros.buffer[range].copy_from_slice(&data[4..4 + len]);
+            });
+            backend.commit();
+        }
+        DYNAMIC_KEYMAP_MACRO_RESET => {
+            backend.with_config(|c| c.macros = Macros::EMPTY);
+            backend.commit();
+        }
+        DYNAMIC_KEYMAP_GET_LAYER_COUNT => data[1] = LAYERS as u8,
+        DYNAMIC_KEYMAP_GET_BUFFER => {
+            let Some(range) = buffer_range(data, KEYMAP_BUFFER_LEN) else {
+                return false;
+            };
+            backend.with_config(|c| {
+                for (i, offset) in range.enumerate() {
+                    let action = c.keymap().layers[offset / (POSITIONS * 2)]
+                        [offset / 2 % POSITIONS];
+                    data[4 + i] = action.to_u16().to_be_bytes()[offset % 2];
+                }
+            });
+        }
+        DYNAMIC_KEYMAP_SET_BUFFER => {
+            let Some(range) = buffer_range(data, KEYMAP_BUFFER_LEN) else {
+                return false;
+            };
+            // VIA always sends whole keycodes, anything else can't be decoded
+            if range.start % 2 != 0 || range.len() % 2 != 0 {
+                return false;
+            }
+            let mut actions = [Action::None; MAX_BUFFER_CHUNK / 2];
+            let (chunks, _) = data[4..4 + range.len()].as_chunks::<2>();
+            for (action, &chunk) in actions.iter_mut().zip(chunks) {
+                let Some(a) = Action::from_u16(u16::from_be_bytes(chunk)) else {
+                    return false;
+                };
+                *action = a;
+            }
+            backend.with_config(|c| {
+                for (i, action) in actions[..range.len() / 2].iter().enumerate() {
+                    let key = range.start / 2 + i;
+                    c.keymap_mut().layers[key / POSITIONS][key % POSITIONS] =
+                        *action;
+                }
+            });
+            backend.commit();
+        }
+        _ => return false
+    }
+    true
+}
+
+fn vial<B: Backend>(
+    command: u8,
+    request: &[u8; REPORT_LEN],
+    data: &mut [u8; REPORT_LEN],
+    backend: &mut B
+) -> bool {
+    // Vial replies don't echo anything back
+    *data = [0; REPORT_LEN];
+    match command {
+        VIAL_GET_KEYBOARD_ID => {
+            data[0..4].copy_from_slice(&VIAL_PROTOCOL_VERSION.to_le_bytes());
+            data[4..12].copy_from_slice(&VIAL_KEYBOARD_ID.to_le_bytes());
+        }
+        VIAL_GET_SIZE => {
+            let size = backend.vial_definition().len() as u32;
+            data[0..4].copy_from_slice(&size.to_le_bytes());
+        }
+        VIAL_GET_DEFINITION => {
+            let page = u16::from_le_bytes([request[2], request[3]]) as usize;
+            let definition = backend.vial_definition();
+            let start = (page * REPORT_LEN).min(definition.len());
+            let chunk =
+                &definition[start..(start + REPORT_LEN).min(definition.len())];
+            data[..chunk.len()].copy_from_slice(chunk);
+        }
+        VIAL_GET_UNLOCK_STATUS => {
+            // always unlocked, there's no unlock combo to list
+            data[0] = 1;
+            data[2..].fill(0xFF);
+        }
+        // no QMK settings, no tap dances, combos or key overrides
+        VIAL_QMK_SETTINGS_QUERY => data.fill(0xFF),
+        VIAL_DYNAMIC_ENTRY_OP => {}
+        _ => return false
+    }
+    true
+}
+
+/// Turns VIA's row and column into a keymap position
+fn position(row: u8, col: u8) -> Option<usize> {
+    let (row, col) = (row as usize, col as usize);
+    (row < ROWS && col < COLS).then_some(row * COLS + col)
+}
+
+/// Reads the `[command, offset (u16), size]` of a buffer request, checking
+/// that it fits in both the buffer and the report
+fn buffer_range(data: &[u8], buffer_len: usize) -> Option<core::ops::Range<usize>> {
+    let offset = u16::from_be_bytes([data[1], data[2]]) as usize;
+    let size = data[3] as usize;
+    (size <= MAX_BUFFER_CHUNK && offset + size <= buffer_len)
+        .then_some(offset..offset + size)
+}
+
+/// Packs a `major.minor.patch` version into `0xMMmmpppp`, anything that
+/// doesn't parse counts as zero
+fn version_number(version: &str) -> u32 {
+    let mut parts = version
+        .split(['.', '-', '+'])
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let mut next = || parts.next().unwrap_or(0);
+    let (major, minor, patch) = (next(), next(), next());
+    (major & 0xFF) << 24 | (minor & 0xFF) << 16 | (patch & 0xFFFF)
+}
+
+/// Which app a keyboard definition is for, Vial still wants the old VIA
+/// format
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flavor {
+    Via,
+    Vial
+}
+
+/// A key on the physical keyboard: its matrix row and column, and how wide
+/// it is in quarter keys
+type PhysicalKey = (u8, u8, u8);
+
+/// Where every key in [`MATRIX`] is on the actual keyboard, top row first.
+/// The top row is the four special function keys, which sit above the
+/// right half of the number row
+#[rustfmt::skip]
+const PHYSICAL_LAYOUT: [&[PhysicalKey]; 6] = [
+    &[(6, 3, 4), (7, 3, 4), (8, 2, 4), (9, 2, 4)],
+    &[
+        (1, 7, 4), (0, 0, 4), (0, 1, 4), (0, 2, 4), (0, 4, 4), (0, 5, 4),
+        (0, 6, 4), (0, 7, 4), (6, 4, 4), (6, 5, 4), (6, 6, 4), (6, 0, 4),
+        (6, 1, 4), (6, 2, 8)
+    ],
+    &[
+        (3, 1, 6), (1, 1, 4), (1, 2, 4), (1, 3, 4), (1, 4, 4), (1, 5, 4),
+        (1, 6, 4), (7, 4, 4), (7, 5, 4), (7, 6, 4), (7, 7, 4), (7, 0, 4),
+        (7, 1, 4), (7, 2, 6)
+    ],
+    &[
+        (3, 0, 7), (2, 1, 4), (2, 2, 4), (2, 3, 4), (2, 4, 4), (2, 5, 4),
+        (2, 6, 4), (8, 4, 4), (8, 5, 4), (8, 6, 4), (8, 7, 4), (8, 0, 4),
+        (8, 1, 9)
+    ],
+    &[
+        (11, 0, 9), (0, 3, 4), (2, 0, 4), (5, 4, 4), (5, 5, 4), (5, 6, 4),
+        (5, 7, 4), (9, 4, 4), (9, 5, 4), (9, 6, 4), (9, 0, 4), (9, 1, 4),
+        (11, 1, 7)
+    ],
+    &[
+        (3, 2, 4), (4, 2, 4), (1, 0, 4), (4, 3, 4), (2, 7, 12), (6, 7, 12),
+        (9, 7, 4), (10, 0, 4), (10, 1, 4), (10, 2, 4), (10, 3, 4)
+    ]
+];
+
+/// Quarter keys the special function keys are shifted right by
+const SPECIAL_FN_OFFSET: u8 = 40;
+
+/// Writes the keyboard definition VIA or Vial needs to know what the keyboard
+/// looks like. Every key on it is checked against [`MATRIX`], special
+/// function keys included since they're remappable even without a keycode
+pub fn write_definition(out: &mut impl Write, flavor: Flavor) -> fmt::Result {
+    write!(out, "{{\n  \"name\": \"Palm Portable Keyboard\",\n")?;
+    if flavor == Flavor::Via {
+        write!(
+            out,
+            "  \"vendorId\": \"{VENDOR_ID:#06X}\",\n  \"productId\": \"{PRODUCT_ID:#06X}\",\n"
+        )?;
+        write!(out, "  \"keycodes\": [],\n  \"menus\": [],\n")?;
+    } else {
+        writeln!(out, "  \"lighting\": \"none\",")?;
+    }
+    writeln!(
+        out,
+        "  \"matrix\": {{ \"rows\": {ROWS}, \"cols\": {COLS} }},"
+    )?;
+    write!(out, "  \"layouts\": {{\n    \"keymap\": [\n")?;
+    for (i, row) in PHYSICAL_LAYOUT.iter().enumerate() {
+        write!(out, "      [")?;
+        if i == 0 {
+            write!(out, "{{ \"x\": {} }}, ", quarters(SPECIAL_FN_OFFSET))?;
+        }
+        for (j, &(y, x, width)) in row.iter().enumerate() {
+            debug_assert!(
+                MATRIX
+                    .get(y as usize * COLS + x as usize)
+                    .is_some_and(Option::is_some),
+                "{y},{x} isn't a key on the matrix"
+            );
+            if j > 0 {
+                write!(out, ", ")?;
+            }
+            if width != 4 {
+                write!(out, "{{ \"w\": {} }}, ", quarters(width))?;
+            }
+            write!(out, "\"{y},{x}\"")?;
+        }
+        let comma = if i + 1 < PHYSICAL_LAYOUT.len() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(out, "]{comma}")?;
+    }
+    write!(out, "    ]\n  }}\n}}\n")
+}
+
+/// Formats quarter keys as key units, without trailing zeros
+fn quarters(q: u8) -> impl fmt::Display {
+    struct Units(u8);
+    impl fmt::Display for Units {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.0 / 4)?;
+            match self.0 % 4 {
+                0 => Ok(()),
+                1 => write!(f, ".25"),
+                2 => write!(f, ".5"),
+                _ => write!(f, ".75")
+            }
+        }
+    }
+    Units(q)
+}
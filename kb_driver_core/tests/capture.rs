@@ -0,0 +1,63 @@
+use kb_driver_core::capture::{Capture, Sample, CAPACITY};
+
+#[test]
+fn only_records_while_started() {
+    let mut capture = Capture::new();
+    capture.record(1, 0x0C);
+    assert!(capture.is_empty());
+
+    capture.start();
+    capture.record(2, 0x0C);
+    capture.stop();
+    capture.record(3, 0x8C);
+    assert_eq!(capture.len(), 1);
+
+    // starting again throws the old one away
+    capture.start();
+    assert!(capture.is_empty());
+}
+
+#[test]
+fn keeps_the_newest_samples() {
+    let mut capture = Capture::new();
+    capture.start();
+    for i in 0..CAPACITY as u32 + 10 {
+        capture.record(i, i as u8);
+    }
+    assert_eq!(capture.len(), CAPACITY);
+    assert_eq!(capture.dropped(), 10);
+    assert_eq!(
+        capture.get(0),
+        Some(Sample {
+            at_ms: 10,
+            byte: 10
+        })
+    );
+    // past where the ring buffer wraps around
+    let last = CAPACITY as u32 + 9;
+    assert_eq!(
+        capture.get(CAPACITY - 1),
+        Some(Sample {
+            at_ms: last,
+            byte: last as u8
+        })
+    );
+    assert_eq!(capture.get(CAPACITY), None);
+    assert!(capture.iter().zip(10..).all(|(s, i)| s.at_ms == i));
+}
+
+#[test]
+fn samples_round_trip() {
+    let sample = Sample {
+        at_ms: 123_456,
+        byte: 0x8C
+    };
+    assert_eq!(sample.to_string(), "123456 8C");
+    assert_eq!("123456 8C".parse(), Ok(sample));
+    assert_eq!("  123456\t8c ".parse(), Ok(sample));
+    assert_eq!(Sample::from_bytes(&sample.to_bytes()), Some(sample));
+
+    assert!("123456".parse::<Sample>().is_err());
+    assert!("123456 100".parse::<Sample>().is_err());
+    assert!("soon 8C".parse::<Sample>().is_err());
+}
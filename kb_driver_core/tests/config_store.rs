@@ -0,0 +1,305 @@
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use kb_driver_core::{
+    config::{store, Config, Error, Store, MAX_ENCODED_LEN, SCHEMA_VERSION},
+    crc::Crc32,
+    key_codes::{KeyCode, Modifiers},
+    keymap::Action,
+    mem_flash::{MemFlash, MemFlashError},
+    power::Policy
+};
+
+const ERASE: usize = 4096;
+const BANK: u32 = 2 * ERASE as u32;
+/// Leaves a sector in front of the store so offsets aren't all zero based
+const BASE: u32 = ERASE as u32;
+
+type Flash = MemFlash<{ 5 * ERASE }, ERASE, 4>;
+
+fn store() -> Store<Flash> {
+    Store::new(Flash::new(), BASE, BANK)
+}
+
+fn custom_config(n: u8) -> Config {
+    let mut config = Config::DEFAULT;
+    config.profile = n;
+    config.tapping_term_ms = 150 + n as u16;
+    config
+        .keymap_mut()
+        .set(2, 10, Action::Key(KeyCode::KeyboardF13));
+    config.keymap_mut().set(
+        0,
+        3,
+        Action::ModTap(Modifiers::LEFT_CTRL, KeyCode::KeyboardZ)
+    );
+    config
+        .keymap_mut()
+        .set(0, 34, Action::LayerTap(2, KeyCode::KeyboardEscape));
+    assert!(config.macros.set(n % 16, b"hello\x01\x04100|world"));
+    config.power = Policy::BATTERY;
+    config
+}
+
+#[test]
+fn encode_decode_round_trip() {
+    let config = custom_config(3);
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let len = config.encode(&mut buf).unwrap();
+    assert!(Config::decode(SCHEMA_VERSION, &buf[..len]).unwrap() == config);
+}
+
+#[test]
+fn decode_rejects_bad_input() {
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let len = Config::DEFAULT.encode(&mut buf).unwrap();
+    assert!(matches!(
+        Config::decode(SCHEMA_VERSION + 1, &buf[..len]),
+        Err(Error::UnsupportedVersion(_))
+    ));
+    assert!(matches!(
+        Config::decode(SCHEMA_VERSION, &buf[..len - 1]),
+        Err(Error::Malformed)
+    ));
+    assert!(matches!(
+        Config::DEFAULT.encode(&mut buf[..100]),
+        Err(Error::TooLarge)
+    ));
+}
+
+#[test]
+fn decode_skips_unknown_tags() {
+    let mut buf = [0u8; MAX_ENCODED_LEN + 8];
+    buf[..5].copy_from_slice(&[0xEE, 2, 0, 0xAB, 0xCD]);
+    let len = custom_config(1).encode(&mut buf[5..]).unwrap();
+    let config = Config::decode(SCHEMA_VERSION, &buf[..5 + len]).unwrap();
+    assert!(config == custom_config(1));
+}
+
+/// A config like firmware from before profiles saved it: the profile, then a
+/// keymap layer every profile shared with F13 second in layer 1
+fn version_1_config() -> Vec<u8> {
+    let f13 = Action::Key(KeyCode::KeyboardF13).to_u16().to_le_bytes();
+    let none = Action::None.to_u16().to_le_bytes();
+    let mut data = vec![1, 1, 0, 2];
+    data.extend([3, 5, 0, 1]);
+    data.extend(none);
+    data.extend(f13);
+    data
+}
+
+fn assert_migrated(config: &Config) {
+    assert!(config.profile == 2);
+    for (profile, default) in config.profiles.iter().zip(&Config::DEFAULT.profiles) {
+        let mut expected = default.keymap.clone();
+        expected.set(1, 0, Action::None);
+        expected.set(1, 1, Action::Key(KeyCode::KeyboardF13));
+        assert!(profile.keymap == expected);
+    }
+}
+
+#[test]
+fn decode_migrates_version_1() {
+    assert_migrated(&Config::decode(1, &version_1_config()).unwrap());
+    // the shared layers went away with version 2
+    let config = Config::decode(2, &version_1_config()).unwrap();
+    assert!(config.profiles == Config::DEFAULT.profiles);
+    assert!(matches!(
+        Config::decode(0, &version_1_config()),
+        Err(Error::UnsupportedVersion(0))
+    ));
+}
+
+#[test]
+fn loads_and_upgrades_a_version_1_record() {
+    let data = version_1_config();
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(b"PKCF");
+    header[4..8].copy_from_slice(&7u32.to_le_bytes());
+    header[8..10].copy_from_slice(&1u16.to_le_bytes());
+    header[10..12].copy_from_slice(&(data.len() as u16).to_le_bytes());
+    let mut crc = Crc32::new();
+    crc.update(&header[4..12]);
+    crc.update(&data);
+    header[12..16].copy_from_slice(&crc.finish().to_le_bytes());
+    let mut flash = Flash::new();
+    let start = BASE as usize;
+    flash.data[start..start + 16].copy_from_slice(&header);
+    flash.data[start + 16..start + 16 + data.len()].copy_from_slice(&data);
+
+    let mut store = Store::new(flash, BASE, BANK);
+    let config = store.load().unwrap().unwrap();
+    assert_migrated(&config);
+    // saving it again writes it the current way, which loads the same
+    store.save(&config).unwrap();
+    let mut store = Store::new(store.release(), BASE, BANK);
+    assert!(store.load().unwrap() == Some(config));
+}
+
+#[test]
+fn empty_flash_has_no_config() {
+    let mut store = store();
+    assert!(store.load().unwrap().is_none());
+}
+
+#[test]
+fn save_then_load() {
+    let mut store = store();
+    store.save(&custom_config(1)).unwrap();
+    store.save(&custom_config(2)).unwrap();
+
+    let mut store = Store::new(store.release(), BASE, BANK);
+    assert!(store.load().unwrap() == Some(custom_config(2)));
+}
+
+#[test]
+fn saving_the_same_config_twice_writes_once() {
+    let mut store = store();
+    store.save(&custom_config(1)).unwrap();
+    let before = store.release();
+    let snapshot = before.data;
+
+    let mut store = Store::new(before, BASE, BANK);
+    store.load().unwrap();
+    store.save(&custom_config(1)).unwrap();
+    assert!(store.release().data == snapshot);
+}
+
+#[test]
+fn stays_inside_its_banks() {
+    let mut store = store();
+    for i in 0..20 {
+        store.save(&custom_config(i)).unwrap();
+    }
+    let flash = store.release();
+    let end = (BASE + 2 * BANK) as usize;
+    assert!(flash.data[..BASE as usize].iter().all(|b| *b == 0xFF));
+    assert!(flash.data[end..].iter().all(|b| *b == 0xFF));
+}
+
+#[test]
+fn banks_take_turns_being_erased() {
+    let mut store = store();
+    for i in 0..40 {
+        store.save(&custom_config(i)).unwrap();
+        let mut reloaded = Store::new(store.release(), BASE, BANK);
+        assert!(reloaded.load().unwrap() == Some(custom_config(i)));
+        store = reloaded;
+    }
+    let flash = store.release();
+    let bank_a = flash.erase_counts[1];
+    let bank_b = flash.erase_counts[3];
+    assert!(bank_a > 0 && bank_b > 0);
+    assert!(bank_a.abs_diff(bank_b) <= 1);
+    // erases are per bank, never per record
+    assert!(bank_a + bank_b < 40);
+}
+
+#[test]
+fn interrupted_save_keeps_the_previous_config() {
+    for cut_at in [0, 3, 16, 17, 200, 1000] {
+        let mut store = store();
+        store.save(&custom_config(1)).unwrap();
+
+        let mut flash = store.release();
+        flash.fail_after(cut_at);
+        let mut store = Store::new(flash, BASE, BANK);
+        store.load().unwrap();
+        assert!(matches!(
+            store.save(&custom_config(2)),
+            Err(store::Error::Flash(MemFlashError::PowerLoss))
+        ));
+
+        let mut flash = store.release();
+        flash.restore_power();
+        let mut store = Store::new(flash, BASE, BANK);
+        assert!(store.load().unwrap() == Some(custom_config(1)));
+
+        // and the store carries on past the torn record
+        store.save(&custom_config(3)).unwrap();
+        let mut store = Store::new(store.release(), BASE, BANK);
+        assert!(store.load().unwrap() == Some(custom_config(3)));
+    }
+}
+
+#[test]
+fn interrupted_bank_switch_keeps_the_previous_config() {
+    let mut store = store();
+    let mut i = 0;
+    // fill up the first bank
+    loop {
+        store.save(&custom_config(i)).unwrap();
+        let flash = store.release();
+        let next = flash.data[(BASE + BANK) as usize..]
+            .iter()
+            .any(|b| *b != 0xFF);
+        store = Store::new(flash, BASE, BANK);
+        store.load().unwrap();
+        if next {
+            break;
+        }
+        i += 1;
+    }
+    let mut flash = store.release();
+    // wipe the new bank like the switch never happened, then cut the power
+    // while writing the next one
+    flash.erase(BASE + BANK, BASE + 2 * BANK).unwrap();
+    flash.fail_after(40);
+    let mut store = Store::new(flash, BASE, BANK);
+    assert!(store.load().unwrap() == Some(custom_config(i - 1)));
+    assert!(store.save(&custom_config(100)).is_err());
+
+    let mut flash = store.release();
+    flash.restore_power();
+    let mut store = Store::new(flash, BASE, BANK);
+    assert!(store.load().unwrap() == Some(custom_config(i - 1)));
+}
+
+#[test]
+fn corrupted_record_is_ignored() {
+    let mut store = store();
+    store.save(&custom_config(1)).unwrap();
+    store.save(&custom_config(2)).unwrap();
+    let mut flash = store.release();
+
+    // flip a bit in the middle of the newest record's config
+    let start = BASE as usize;
+    let first_len =
+        u16::from_le_bytes([flash.data[start + 10], flash.data[start + 11]]);
+    let second = start + (16 + first_len as usize).next_multiple_of(store::ALIGN);
+    flash.data[second + 40] ^= 0x01;
+
+    let mut store = Store::new(flash, BASE, BANK);
+    assert!(store.load().unwrap() == Some(custom_config(1)));
+}
+
+#[test]
+fn factory_reset_erases_everything() {
+    let mut store = store();
+    for i in 0..10 {
+        store.save(&custom_config(i)).unwrap();
+    }
+    store.factory_reset().unwrap();
+    assert!(store.load().unwrap().is_none());
+
+    store.save(&Config::DEFAULT).unwrap();
+    let mut store = Store::new(store.release(), BASE, BANK);
+    assert!(store.load().unwrap() == Some(Config::DEFAULT));
+}
+
+#[test]
+fn mem_flash_behaves_like_nor() {
+    let mut flash = MemFlash::<1024, 256, 4>::new();
+    assert!(flash.write(1, &[0; 4]) == Err(MemFlashError::NotAligned));
+    assert!(flash.write(0, &[0; 3]) == Err(MemFlashError::NotAligned));
+    assert!(flash.write(1024, &[0; 4]) == Err(MemFlashError::OutOfBounds));
+    assert!(flash.erase(0, 100) == Err(MemFlashError::NotAligned));
+
+    flash.write(0, &[0b1100, 0xFF, 0xFF, 0xFF]).unwrap();
+    flash.write(0, &[0b1010, 0xFF, 0xFF, 0xFF]).unwrap();
+    let mut buf = [0u8; 1];
+    flash.read(0, &mut buf).unwrap();
+    assert!(buf[0] == 0b1000);
+
+    flash.erase(0, 256).unwrap();
+    flash.read(0, &mut buf).unwrap();
+    assert!(buf[0] == 0xFF);
+}
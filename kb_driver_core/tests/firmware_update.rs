@@ -0,0 +1,86 @@
+use kb_driver_core::{
+    crc::crc32,
+    firmware_update::{Error, UpdateSlot, MAX_CHUNK},
+    mem_flash::MemFlash
+};
+
+const ERASE: usize = 1024;
+/// Leaves a sector in front of the slot so offsets aren't all zero based
+const BASE: u32 = ERASE as u32;
+const CAPACITY: u32 = 4 * ERASE as u32;
+
+type Flash = MemFlash<{ 5 * ERASE }, ERASE, 4>;
+
+fn slot() -> UpdateSlot<Flash> {
+    UpdateSlot::new(Flash::new(), BASE, CAPACITY)
+}
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+fn write_all(slot: &mut UpdateSlot<Flash>, image: &[u8]) {
+    for (i, chunk) in image.chunks(MAX_CHUNK).enumerate() {
+        slot.write((i * MAX_CHUNK) as u32, chunk).unwrap();
+    }
+}
+
+#[test]
+fn writes_and_verifies_an_image() {
+    let mut slot = slot();
+    // doesn't end on a chunk or write boundary
+    let image = image(1501);
+    slot.begin(image.len() as u32).unwrap();
+    write_all(&mut slot, &image);
+    assert_eq!(slot.finish(crc32(&image)), Ok(1501));
+
+    let data = &slot.flash().data;
+    assert_eq!(&data[BASE as usize..][..image.len()], &image[..]);
+    // nothing before the slot or after the image got touched
+    assert!(data[..BASE as usize].iter().all(|b| *b == 0xFF));
+    assert!(data[BASE as usize + image.len()..]
+        .iter()
+        .all(|b| *b == 0xFF));
+    // only the sectors the image needed were erased
+    assert_eq!(slot.flash().erase_counts[..5], [0, 1, 1, 0, 0]);
+}
+
+#[test]
+fn rejects_a_bad_crc() {
+    let mut slot = slot();
+    let image = image(100);
+    slot.begin(100).unwrap();
+    write_all(&mut slot, &image);
+    assert_eq!(slot.finish(crc32(&image) ^ 1), Err(Error::Mismatch));
+    // and has to start over after that
+    assert_eq!(slot.finish(crc32(&image)), Err(Error::OutOfRange));
+    assert_eq!(slot.write(0, &image[..MAX_CHUNK]), Err(Error::OutOfRange));
+}
+
+#[test]
+fn catches_a_missing_chunk() {
+    let mut slot = slot();
+    let image = image(100);
+    slot.begin(100).unwrap();
+    for (i, chunk) in image.chunks(MAX_CHUNK).enumerate().skip(1) {
+        slot.write((i * MAX_CHUNK) as u32, chunk).unwrap();
+    }
+    assert_eq!(slot.finish(crc32(&image)), Err(Error::Mismatch));
+}
+
+#[test]
+fn rejects_chunks_out_of_place() {
+    let mut slot = slot();
+    assert_eq!(slot.write(0, &[0; MAX_CHUNK]), Err(Error::OutOfRange));
+    assert_eq!(slot.begin(0), Err(Error::TooLarge));
+    assert_eq!(slot.begin(CAPACITY + 1), Err(Error::TooLarge));
+
+    slot.begin(100).unwrap();
+    // not on a chunk boundary
+    assert_eq!(slot.write(4, &[0; MAX_CHUNK]), Err(Error::OutOfRange));
+    // short, but not the last one
+    assert_eq!(slot.write(0, &[0; 4]), Err(Error::OutOfRange));
+    // past the end of the image
+    assert_eq!(slot.write(96, &[0; 8]), Err(Error::OutOfRange));
+    assert_eq!(slot.write(96, &[0; 4]), Ok(()));
+}
@@ -0,0 +1,143 @@
+use kb_driver_core::{
+    config::Config,
+    host_os::{
+        DescriptorRequest, Fingerprint, HostOs, DESCRIPTOR_CONFIGURATION,
+        DESCRIPTOR_STRING
+    },
+    profile::PROFILES
+};
+
+/// Records a config descriptor request, then string requests of `lengths`
+fn fingerprint(config: u16, strings: &[u16]) -> Fingerprint {
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.record(DESCRIPTOR_CONFIGURATION, 9);
+    fingerprint.record(DESCRIPTOR_CONFIGURATION, config);
+    for length in strings {
+        fingerprint.record(DESCRIPTOR_STRING, *length);
+    }
+    fingerprint
+}
+
+#[test]
+fn guesses_every_os() {
+    let windows = fingerprint(0xFF, &[0xFF, 0x04, 0xFF, 0xFF]);
+    assert_eq!(windows.guess(), Some(HostOs::Windows));
+    let linux = fingerprint(0x5B, &[0xFF, 0xFF, 0xFF]);
+    assert_eq!(linux.guess(), Some(HostOs::Linux));
+    let mac = fingerprint(0x5B, &[0x02, 0x1A, 0x02, 0x3E, 0xFF]);
+    assert_eq!(mac.guess(), Some(HostOs::MacOs));
+    let ios = fingerprint(0x5B, &[0x02, 0x1A, 0x02, 0x3E]);
+    assert_eq!(ios.guess(), Some(HostOs::Ios));
+}
+
+#[test]
+fn waits_for_enough_requests() {
+    assert_eq!(fingerprint(0x5B, &[0xFF, 0xFF]).guess(), None);
+
+    let mut fingerprint = fingerprint(0x5B, &[0xFF, 0xFF, 0xFF]);
+    fingerprint.clear();
+    assert_eq!(fingerprint.guess(), None);
+}
+
+#[test]
+fn ignores_other_descriptors() {
+    let mut fingerprint = fingerprint(0x5B, &[0xFF, 0xFF, 0xFF]);
+    // device and HID report descriptors
+    fingerprint.record(1, 0x40);
+    fingerprint.record(0x22, 0xFF);
+    assert_eq!(fingerprint.guess(), Some(HostOs::Linux));
+}
+
+/// A GET_DESCRIPTOR SETUP packet for one of the device's descriptors
+fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> [u8; 8] {
+    let [lo, hi] = length.to_le_bytes();
+    [0x80, 6, index, descriptor_type, 0x09, 0x04, lo, hi]
+}
+
+/// Everything a host sends while enumerating, the way the control pipe hands
+/// it over: device descriptors, setting the address, the configuration
+/// descriptor, strings, setting the configuration and then the HID report
+/// descriptor, which goes to the interface
+fn enumeration(config: u16, strings: &[u16]) -> Vec<[u8; 8]> {
+    let mut packets = vec![
+        get_descriptor(1, 0, 0x40),
+        [0x00, 5, 7, 0, 0, 0, 0, 0],
+        get_descriptor(1, 0, 0x12),
+        get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 9),
+        get_descriptor(DESCRIPTOR_CONFIGURATION, 0, config),
+    ];
+    for (i, length) in strings.iter().enumerate() {
+        packets.push(get_descriptor(DESCRIPTOR_STRING, i as u8, *length));
+    }
+    packets.extend([
+        [0x00, 9, 1, 0, 0, 0, 0, 0],
+        [0x81, 6, 0, 0x22, 0, 0, 0x3F, 0],
+        // GET_STATUS and a vendor request
+        [0x80, 0, 0, 0, 0, 0, 2, 0],
+        [0xC0, 1, 0, 0, 0, 0, 0x40, 0]
+    ]);
+    packets
+}
+
+/// Feeds SETUP packets in like the firmware does, returns the ones that
+/// counted
+fn record_setups(fingerprint: &mut Fingerprint, packets: &[[u8; 8]]) -> usize {
+    packets
+        .iter()
+        .filter(|packet| fingerprint.record_setup(packet))
+        .count()
+}
+
+#[test]
+fn guesses_from_setup_packets() {
+    let hosts = [
+        (0xFF, &[0xFF, 0x04, 0xFF, 0xFF][..], HostOs::Windows),
+        (0x5B, &[0xFF, 0xFF, 0xFF], HostOs::Linux),
+        (0x5B, &[0x02, 0x1A, 0x02, 0x3E, 0xFF], HostOs::MacOs),
+        (0x5B, &[0x02, 0x1A, 0x02, 0x3E], HostOs::Ios)
+    ];
+    for (config, strings, os) in hosts {
+        let mut fingerprint = Fingerprint::new();
+        let counted = record_setups(&mut fingerprint, &enumeration(config, strings));
+        // the device and configuration descriptors, then the strings
+        assert_eq!(counted, 4 + strings.len());
+        assert_eq!(fingerprint.guess(), Some(os));
+    }
+}
+
+#[test]
+fn keeps_requests_in_order() {
+    let mut fingerprint = Fingerprint::new();
+    record_setups(&mut fingerprint, &enumeration(0x5B, &[0x02, 0xFF]));
+    let request = |descriptor_type, length| DescriptorRequest {
+        descriptor_type,
+        length
+    };
+    assert_eq!(
+        fingerprint.requests(),
+        [
+            request(1, 0x40),
+            request(1, 0x12),
+            request(DESCRIPTOR_CONFIGURATION, 9),
+            request(DESCRIPTOR_CONFIGURATION, 0x5B),
+            request(DESCRIPTOR_STRING, 0x02),
+            request(DESCRIPTOR_STRING, 0xFF)
+        ]
+    );
+    // macOS asks for the whole string last, not first
+    let mut reversed = Fingerprint::new();
+    record_setups(
+        &mut reversed,
+        &enumeration(0x5B, &[0xFF, 0x1A, 0x02, 0x3E, 0x02])
+    );
+    assert_ne!(reversed.guess(), Some(HostOs::MacOs));
+}
+
+#[test]
+fn every_os_has_a_default_profile() {
+    for os in HostOs::ALL {
+        assert!((Config::DEFAULT.profile_for(os) as usize) < PROFILES);
+        assert_eq!(HostOs::from_name(os.name()), Some(os));
+        assert_eq!(HostOs::try_from(os as u8), Ok(os));
+    }
+}
@@ -0,0 +1,54 @@
+//! Needs `--features log`, the macros don't go through the `log` crate without
+//! it, see `required-features` in the manifest
+
+use std::sync::Mutex;
+
+use kb_driver_core::{debug, info, trace, warn};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+static LINES: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Debug
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[derive(Debug)]
+enum Thing {
+    Widget
+}
+
+// the logger is global, so this is one test
+#[test]
+fn goes_through_log() {
+    log::set_logger(&Capture).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    info!("plain {} and {:?}", 42, Thing::Widget);
+    trace!("not shown");
+    warn!("hex {=u8:#x}, binary {:08b}", 16, 5);
+    debug!("{{braces}}");
+
+    assert_eq!(
+        *LINES.lock().unwrap(),
+        [
+            (Level::Info, "plain 42 and Widget".to_string()),
+            (Level::Warn, "hex 0x10, binary 00000101".to_string()),
+            (Level::Debug, "{braces}".to_string())
+        ]
+    );
+}
@@ -0,0 +1,50 @@
+//! Needs `--features log-buffer`, the macros don't log here without it, see
+//! `required-features` in the manifest
+
+use std::sync::Mutex;
+
+use kb_driver_core::{
+    debug, error, info,
+    log_sink::{self, Level}
+};
+
+static LINES: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+fn capture(level: Level, args: std::fmt::Arguments) {
+    LINES.lock().unwrap().push((level, args.to_string()));
+}
+
+#[derive(Debug)]
+enum Thing {
+    Widget
+}
+
+// the sink and the level are global, so this is one test
+#[test]
+fn formats_and_filters() {
+    log_sink::set_sink(capture);
+    log_sink::set_level(Level::Info);
+
+    info!("plain {} and {:?}", 42, Thing::Widget);
+    debug!("not shown");
+    error!(
+        "hex {:02X} {=u8:#x}, binary {:08b}, {{braces}}",
+        [1u8, 0xAB],
+        16,
+        5
+    );
+    log_sink::set_level(Level::Debug);
+    debug!("shown now");
+
+    assert_eq!(
+        *LINES.lock().unwrap(),
+        [
+            (Level::Info, "plain 42 and Widget".to_string()),
+            (
+                Level::Error,
+                "hex [01, AB] 0x10, binary 00000101, {braces}".to_string()
+            ),
+            (Level::Debug, "shown now".to_string())
+        ]
+    );
+}
@@ -0,0 +1,52 @@
+use kb_driver_core::{
+    config::FACTORY_RESET_COMBO,
+    key_codes::KeyCode,
+    keymap::POSITIONS,
+    model::{KeyboardModel, MODEL, MODELS, PALM_PORTABLE},
+    program::{FN_KEY, PROGRAM_MODE_COMBO},
+    state::BOOTLOADER_COMBO
+};
+
+#[test]
+fn detects_models_by_handshake() {
+    assert!(KeyboardModel::detect([0xFA, 0xFD]) == Some(&PALM_PORTABLE));
+    assert!(KeyboardModel::detect([0xFD, 0xFA]).is_none());
+    for (i, model) in MODELS.iter().enumerate() {
+        assert!(KeyboardModel::detect(model.handshake) == Some(*model));
+        assert!(
+            MODELS[..i].iter().all(|m| m.handshake != model.handshake),
+            "{} has the same handshake as another model",
+            model.name
+        );
+    }
+}
+
+#[test]
+fn matrices_fit_the_keymap() {
+    for model in MODELS {
+        assert!(model.matrix.len() <= POSITIONS, "{}", model.name);
+    }
+}
+
+#[test]
+fn key_events() {
+    let a = MODEL.key(17).unwrap();
+    assert!(a.0 == KeyCode::KeyboardA);
+    assert!(MODEL.key(17 | 0x80) == Some(a));
+    assert!(MODEL.key(27).is_none());
+    assert!(MODEL.key(0x7F).is_none());
+}
+
+/// The built in combos go by position, so they have to be where the keys
+/// they're named after are
+#[test]
+fn combos_are_on_the_matrix() {
+    let key = |pos: u8| MODEL.key(pos).map(|(key, _)| key);
+    assert!(key(FN_KEY) == Some(KeyCode::KeyboardFn));
+    let [fn_key, cmd, p] = PROGRAM_MODE_COMBO;
+    assert!(key(fn_key) == Some(KeyCode::KeyboardFn));
+    assert!(key(cmd) == Some(KeyCode::KeyboardLeftGui));
+    assert!(key(p) == Some(KeyCode::KeyboardP));
+    assert!(key(FACTORY_RESET_COMBO[2]) == Some(KeyCode::KeyboardBackspace));
+    assert!(key(BOOTLOADER_COMBO[2]) == Some(KeyCode::KeyboardB));
+}
@@ -0,0 +1,257 @@
+//! Arbitrary input against the invariants of [`State`], the same ones the fuzz
+//! target in `fuzz/` checks
+
+use kb_driver_core::{
+    combo::Combo,
+    config::Config,
+    key_codes::{KeyCode, Modifiers},
+    keymap::{Action, LAYERS, POSITIONS},
+    report::Report,
+    state::{is_key_down, State}
+};
+use proptest::prelude::*;
+
+const KEY_UP: u8 = 0x80;
+
+/// Keeps track of what's physically held down, going by the bytes alone
+struct Harness<'c> {
+    state: State,
+    config: &'c Config,
+    now_ms: u32,
+    held: Vec<u8>,
+    /// Positions that can put modifiers in a report on some layer
+    modifier_keys: Vec<u8>,
+    last_report: Option<Report>
+}
+
+fn is_modifier_action(action: Action) -> bool {
+    match action {
+        Action::Key(key) | Action::LayerTap(_, key) => key.is_modifier(),
+        Action::ModTap(..) => true,
+        _ => false
+    }
+}
+
+fn reached(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+impl<'c> Harness<'c> {
+    fn new(config: &'c Config) -> Self {
+        let mut modifier_keys: Vec<u8> = (0..POSITIONS)
+            .filter(|pos| {
+                (0..LAYERS).any(|layer| {
+                    config
+                        .keymap()
+                        .get(layer, *pos)
+                        .is_some_and(is_modifier_action)
+                })
+            })
+            .map(|pos| pos as u8)
+            .collect();
+        for combo in config
+            .combos
+            .iter()
+            .filter(|c| is_modifier_action(c.action))
+        {
+            modifier_keys.extend(combo.keys());
+        }
+        Self {
+            state: State::new(),
+            config,
+            now_ms: 0,
+            held: Vec::new(),
+            modifier_keys,
+            last_report: None
+        }
+    }
+
+    /// Waits `ms`, ticking like the firmware does
+    fn wait(&mut self, ms: u32) -> Result<(), TestCaseError> {
+        let target = self.now_ms.wrapping_add(ms);
+        while let Some(deadline) = self.state.next_deadline() {
+            if !reached(target, deadline) {
+                break;
+            }
+            self.now_ms = deadline;
+            self.state.tick(deadline);
+            self.check(false)?;
+        }
+        self.now_ms = target;
+        Ok(())
+    }
+
+    /// Waits `ms`, then sends a byte
+    fn feed(&mut self, byte: u8, ms: u32) -> Result<(), TestCaseError> {
+        self.wait(ms)?;
+        let was_modifier_held = self.modifier_held();
+        self.state
+            .update_from_kb_input(byte, self.config, self.now_ms);
+        if KeyCode::try_from_matrix_key(byte).is_some() {
+            let pos = byte & !KEY_UP;
+            match is_key_down(byte) {
+                true if !self.held.contains(&pos) => self.held.push(pos),
+                true => (),
+                false => self.held.retain(|p| *p != pos)
+            }
+        }
+        self.check(was_modifier_held)
+    }
+
+    fn modifier_held(&self) -> bool {
+        self.held.iter().any(|p| self.modifier_keys.contains(p))
+    }
+
+    /// Goes through the reports queued up since last time. A key that was let
+    /// go of can still be behind the reports it caused
+    fn check(&mut self, was_modifier_held: bool) -> Result<(), TestCaseError> {
+        let modifier_held = was_modifier_held || self.modifier_held();
+        while let Some(report) = self.state.pop_report() {
+            let keys: Vec<u8> =
+                report.keycodes.into_iter().filter(|k| *k != 0).collect();
+            for (i, key) in keys.iter().enumerate() {
+                prop_assert!(!keys[..i].contains(key), "{key:#04x} is in twice");
+            }
+            prop_assert!(
+                report.modifiers.is_empty() || modifier_held,
+                "modifiers {:08b} without a modifier key held",
+                report.modifiers.bits()
+            );
+            self.last_report = Some(report);
+        }
+        while self.state.pop_command().is_some() {}
+        prop_assert!(!self.state.has_output());
+        Ok(())
+    }
+
+    /// Lets go of everything the way the keyboard does, the last key twice
+    fn release_all(&mut self) -> Result<(), TestCaseError> {
+        let mut last = None;
+        while let Some(&pos) = self.held.first() {
+            self.feed(pos | KEY_UP, 10)?;
+            last = Some(pos);
+        }
+        if let Some(pos) = last {
+            self.feed(pos | KEY_UP, 0)?;
+        }
+        // let pending tap-hold keys and combos time out
+        self.wait(1000)?;
+        prop_assert!(self.state.report() == Report::new(), "keys left held");
+        prop_assert!(
+            self.last_report.is_none_or(|r| r == Report::new()),
+            "the last report wasn't empty"
+        );
+        Ok(())
+    }
+}
+
+/// Mostly real key events, some of them past the end of the matrix
+fn input() -> impl Strategy<Value = (u8, u32)> {
+    let byte = prop_oneof![
+        4 => 0u8..90,
+        4 => 128u8..218,
+        1 => 90u8..=127,
+        1 => 218u8..=255
+    ];
+    (byte, prop_oneof![0u32..20, 0u32..400])
+}
+
+/// Something with a key in it, from a usage
+fn with_key(f: impl Fn(u8, KeyCode) -> Action) -> impl Strategy<Value = Action> {
+    (any::<u8>(), 4u8..=0xE7)
+        .prop_filter_map("not a key", move |(n, k)| Some(f(n, k.try_into().ok()?)))
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        Just(Action::None),
+        Just(Action::Transparent),
+        with_key(|_, k| Action::Key(k)),
+        (0..LAYERS as u8).prop_map(Action::Layer),
+        with_key(|m, k| Action::ModTap(Modifiers::from_bits_truncate(m), k)),
+        with_key(|l, k| Action::LayerTap(l % LAYERS as u8, k)),
+        (0u8..4).prop_map(Action::Macro),
+        (0u8..4).prop_map(Action::Profile),
+        any::<u16>().prop_map(Action::Unicode)
+    ]
+}
+
+/// Changes to the default config, as a config isn't `Debug`
+#[derive(Debug)]
+struct Edits {
+    remaps: Vec<(usize, usize, Action)>,
+    combos: Vec<(Vec<u8>, Action)>,
+    tapping_term_ms: u16
+}
+
+impl Edits {
+    fn config(&self) -> Config {
+        let mut config = Config::DEFAULT;
+        config.tapping_term_ms = self.tapping_term_ms;
+        for (layer, pos, action) in &self.remaps {
+            config.keymap_mut().set(*layer, *pos, *action);
+        }
+        for (slot, (keys, action)) in config.combos.iter_mut().zip(&self.combos) {
+            *slot = Combo::new(keys, *action).unwrap_or(Combo::NONE);
+        }
+        config
+    }
+}
+
+fn edits() -> impl Strategy<Value = Edits> {
+    let remap = (0..LAYERS, 0..POSITIONS, action());
+    let combo = (proptest::collection::vec(0u8..90, 2..=4), action());
+    (
+        proptest::collection::vec(remap, 0..40),
+        proptest::collection::vec(combo, 0..4),
+        50u16..400
+    )
+        .prop_map(|(remaps, combos, tapping_term_ms)| Edits {
+            remaps,
+            combos,
+            tapping_term_ms
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn default_keymap(inputs in proptest::collection::vec(input(), 0..200)) {
+        let config = Config::DEFAULT;
+        let mut harness = Harness::new(&config);
+        for (byte, ms) in inputs {
+            harness.feed(byte, ms)?;
+        }
+        harness.release_all()?;
+    }
+
+    #[test]
+    fn any_keymap(
+        edits in edits(),
+        inputs in proptest::collection::vec(input(), 0..200)
+    ) {
+        let config = edits.config();
+        let mut harness = Harness::new(&config);
+        for (byte, ms) in inputs {
+            harness.feed(byte, ms)?;
+        }
+        harness.release_all()?;
+    }
+
+    #[test]
+    fn ignores_positions_off_the_matrix(
+        bytes in proptest::collection::vec(90u8..=127, 1..50),
+        up in proptest::collection::vec(any::<bool>(), 50)
+    ) {
+        let mut state = State::new();
+        for (byte, up) in bytes.into_iter().zip(up) {
+            let byte = if up { byte | KEY_UP } else { byte };
+            state.update_from_kb_input(byte, &Config::DEFAULT, 0);
+        }
+        prop_assert!(!state.has_output());
+        prop_assert!(state.pop_report().is_none());
+        prop_assert!(state.pop_command().is_none());
+        prop_assert!(state.report() == Report::new());
+    }
+}
@@ -0,0 +1,48 @@
+use kb_driver_core::{
+    key_codes::{KeyCode, Modifiers, Os},
+    usages::{ConsumerUsage, DesktopUsage}
+};
+
+// the usage enums don't implement `Debug`, so no `assert_eq!` on them
+
+#[test]
+fn key_code_names_round_trip() {
+    for key in KeyCode::ALL {
+        assert!(key.name().parse() == Ok(key), "{}", key.name());
+        assert!(KeyCode::try_from(key as u8) == Ok(key), "{}", key.name());
+    }
+    assert!("KeyboardA".parse() == Ok(KeyCode::KeyboardA));
+    assert!("Keyboard A".parse::<KeyCode>().is_err());
+    assert!(KeyCode::try_from(0xA5).is_err());
+}
+
+#[test]
+fn modifiers_are_the_dynamic_values() {
+    for key in KeyCode::ALL {
+        assert_eq!(key.is_modifier(), !Modifiers::from(key).is_empty());
+    }
+}
+
+#[test]
+fn support_comes_from_the_tables() {
+    assert_eq!(KeyCode::KeyboardA.supported_on(Os::Windows), Some(true));
+    assert_eq!(
+        KeyCode::KeyboardPower.supported_on(Os::Windows),
+        Some(false)
+    );
+    assert_eq!(KeyCode::KeyboardPower.supported_on(Os::MacOs), Some(true));
+    assert_eq!(KeyCode::KeyboardFn.supported_on(Os::Unix), None);
+}
+
+#[test]
+fn other_pages() {
+    for usage in ConsumerUsage::ALL {
+        assert!(usage.name().parse() == Ok(usage), "{}", usage.name());
+        assert!(ConsumerUsage::try_from(usage as u16) == Ok(usage));
+    }
+    for usage in DesktopUsage::ALL {
+        assert!(usage.name().parse() == Ok(usage), "{}", usage.name());
+    }
+    assert!(ConsumerUsage::try_from(0xCD) == Ok(ConsumerUsage::PlayPause));
+    assert!(DesktopUsage::try_from(0x82) == Ok(DesktopUsage::SystemSleep));
+}
@@ -0,0 +1,95 @@
+//! Turns the logging macros' defmt messages into `core::fmt` ones, for every
+//! backend that isn't defmt
+//!
+//! defmt's format strings mostly look like `core::fmt`'s, but `{}` means
+//! `defmt::Format` instead of `Display`, and plenty of what gets logged only
+//! has `Format` and `Debug`. So every placeholder is turned into its `Debug`
+//! version, with display hints kept where `Debug` has an equivalent.
+
+use alloc::string::String;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    Error, LitStr, Result
+};
+
+pub struct LogArgs {
+    format: LitStr,
+    /// Everything after the format string, leading comma included
+    rest: TokenStream
+}
+
+impl Parse for LogArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        Ok(Self {
+            format: input.parse()?,
+            rest: input.parse()?
+        })
+    }
+}
+
+impl LogArgs {
+    /// The same arguments with the format string converted, ready for
+    /// `format_args!` or anything that takes the same
+    pub fn expand(&self) -> Result<TokenStream> {
+        let format = convert(&self.format.value())
+            .map_err(|e| Error::new(self.format.span(), e))?;
+        let format = LitStr::new(&format, self.format.span());
+        let rest = &self.rest;
+        Ok(quote!(#format #rest))
+    }
+}
+
+/// Turns a defmt format string into a `core::fmt` one
+fn convert(format: &str) -> core::result::Result<String, &'static str> {
+    let mut out = String::with_capacity(format.len() + 8);
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push_str("{{");
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("unclosed `{` in format string")
+                    }
+                }
+                out.push('{');
+                out.push_str(&convert_placeholder(&placeholder));
+                out.push('}');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push_str("}}");
+            }
+            '}' => return Err("unmatched `}` in format string"),
+            c => out.push(c)
+        }
+    }
+    Ok(out)
+}
+
+/// `position=type:hint` to `position:hint?`
+fn convert_placeholder(placeholder: &str) -> String {
+    let (position, spec) = match placeholder.split_once(':') {
+        Some((position, spec)) => (position, spec),
+        None => (placeholder, "")
+    };
+    // the type is only there for defmt's encoding
+    let position = position.split('=').next().unwrap_or_default();
+    let mut out = String::from(position);
+    out.push(':');
+    out.push_str(spec);
+    // binary and octal only work on integers, which are fine without `Debug`
+    if !spec.ends_with(['?', 'b', 'o']) {
+        out.push('?');
+    }
+    out
+}
@@ -0,0 +1,297 @@
+//! `keymap!`, keymaps written out as a grid laid out like the matrix
+//!
+//! This crate can't depend on `kb_driver_core`, so anything that needs to know
+//! about the matrix or the key codes gets checked by the code it expands to,
+//! which still happens at compile time.
+
+use alloc::{format, string::ToString, vec::Vec};
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use syn::{
+    braced, bracketed, parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    token, Error, Ident, LitInt, Result, Token
+};
+
+// these have to match `kb_driver_core::keymap`, the expanded code checks that
+// they still do
+const ROWS: usize = 12;
+const COLS: usize = 8;
+
+pub struct Keymap {
+    layers: Vec<Layer>
+}
+
+struct Layer {
+    /// `ROWS` rows of `COLS` entries each
+    rows: Vec<Vec<Entry>>
+}
+
+enum Entry {
+    /// `_`, for positions that don't have a key
+    Empty(Span),
+    None(Span),
+    Trans(Span),
+    Key(Ident),
+    /// `MO(layer)`
+    Layer(Span, LitInt),
+    /// `MACRO(index)`
+    Macro(Span, LitInt),
+    /// `MT(LEFT_CTRL | LEFT_SHIFT, key)`
+    ModTap(Span, Vec<Ident>, Ident),
+    /// `LT(layer, key)`
+    LayerTap(Span, LitInt, Ident),
+    /// `PROFILE(index)`
+    Profile(Span, LitInt),
+    /// `UC(code point)`
+    Unicode(Span, LitInt)
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![_]) {
+            let underscore: Token![_] = input.parse()?;
+            return Ok(Entry::Empty(underscore.span));
+        }
+        let name: Ident = input.parse()?;
+        let span = name.span();
+        if !input.peek(token::Paren) {
+            return Ok(match name.to_string().as_str() {
+                "none" => Entry::None(span),
+                "trans" => Entry::Trans(span),
+                _ => Entry::Key(name)
+            });
+        }
+        let args;
+        parenthesized!(args in input);
+        Ok(match name.to_string().as_str() {
+            "MO" => Entry::Layer(span, args.parse()?),
+            "MACRO" => Entry::Macro(span, args.parse()?),
+            "PROFILE" => Entry::Profile(span, args.parse()?),
+            "UC" => Entry::Unicode(span, args.parse()?),
+            "MT" => {
+                let mods =
+                    Punctuated::<Ident, Token![|]>::parse_separated_nonempty(&args)?;
+                args.parse::<Token![,]>()?;
+                Entry::ModTap(span, mods.into_iter().collect(), args.parse()?)
+            }
+            "LT" => {
+                let layer = args.parse()?;
+                args.parse::<Token![,]>()?;
+                Entry::LayerTap(span, layer, args.parse()?)
+            }
+            _ => {
+                return Err(Error::new(
+                    span,
+                    "expected `MO`, `MACRO`, `MT`, `LT`, `PROFILE` or `UC`"
+                ))
+            }
+        })
+    }
+}
+
+impl Parse for Keymap {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut layers = Vec::new();
+        while !input.is_empty() {
+            let keyword: Ident = input.parse()?;
+            if keyword != "layer" {
+                return Err(Error::new(keyword.span(), "expected `layer`"));
+            }
+            let index: LitInt = input.parse()?;
+            if index.base10_parse::<usize>()? != layers.len() {
+                return Err(Error::new(
+                    index.span(),
+                    format!("expected layer {}, layers go in order", layers.len())
+                ));
+            }
+            let content;
+            braced!(content in input);
+            let mut rows = Vec::new();
+            while !content.is_empty() {
+                let label: Ident = content.parse()?;
+                if label != format!("Y{}", rows.len()) {
+                    return Err(Error::new(
+                        label.span(),
+                        format!("expected `Y{}`, rows go in order", rows.len())
+                    ));
+                }
+                let entries;
+                bracketed!(entries in content);
+                let entries =
+                    Punctuated::<Entry, Token![,]>::parse_terminated(&entries)?;
+                if entries.len() != COLS {
+                    return Err(Error::new(
+                        label.span(),
+                        format!(
+                            "{label} has {} keys instead of {COLS}",
+                            entries.len()
+                        )
+                    ));
+                }
+                rows.push(entries.into_iter().collect::<Vec<_>>());
+                if !content.is_empty() {
+                    content.parse::<Token![,]>()?;
+                }
+            }
+            if rows.len() != ROWS {
+                return Err(Error::new(
+                    index.span(),
+                    format!(
+                        "layer {index} has {} rows instead of {ROWS}",
+                        rows.len()
+                    )
+                ));
+            }
+            layers.push(Layer { rows });
+        }
+        if layers.is_empty() {
+            return Err(input.error("a keymap needs at least one layer"));
+        }
+
+        // layers can only be switched to if they're in the keymap
+        for entry in layers.iter().flat_map(|l| l.rows.iter().flatten()) {
+            let (Entry::Layer(_, layer) | Entry::LayerTap(_, layer, _)) = entry
+            else {
+                continue;
+            };
+            match layer.base10_parse::<usize>()? {
+                0 => return Err(Error::new(layer.span(), "layer 0 is always on")),
+                l if l >= layers.len() => {
+                    return Err(Error::new(
+                        layer.span(),
+                        format!("there's no layer {l} in this keymap")
+                    ))
+                }
+                _ => ()
+            }
+        }
+        Ok(Self { layers })
+    }
+}
+
+impl Entry {
+    fn expand(&self, row: usize, col: usize) -> TokenStream {
+        let pos = row * COLS + col;
+        let is_key = quote! { ::kb_driver_core::keymap::is_key(#pos) };
+        let no_key = format!("there's no key at Y{row} X{col}, it has to be `_`");
+        let (span, check, action): (Span, Option<TokenStream>, TokenStream) =
+            match self {
+                Entry::Empty(span) => {
+                    let message = format!(
+                    "Y{row} X{col} is a key, it has to be mapped to something or \
+                     `none`"
+                );
+                    let check = quote! { ::core::assert!(!#is_key, #message) };
+                    return quote_spanned! {*span=>
+                        {
+                            const { #check };
+                            Action::None
+                        }
+                    };
+                }
+                Entry::None(span) => (*span, None, quote! { Action::None }),
+                Entry::Trans(span) => (*span, None, quote! { Action::Transparent }),
+                Entry::Key(key) => {
+                    let span = key.span();
+                    (
+                        span,
+                        None,
+                        quote_spanned! {span=> Action::Key(KeyCode::#key) }
+                    )
+                }
+                Entry::Layer(span, layer) => {
+                    (*span, None, quote! { Action::Layer(#layer) })
+                }
+                Entry::Macro(span, index) => {
+                    let message = format!("there's no macro {index}");
+                    let check = quote! {
+                        ::core::assert!(
+                            #index < ::kb_driver_core::macros::MACRO_COUNT,
+                            #message
+                        )
+                    };
+                    (*span, Some(check), quote! { Action::Macro(#index) })
+                }
+                Entry::ModTap(span, mods, key) => {
+                    let mods = mods
+                        .iter()
+                        .map(|m| quote_spanned! {m.span()=> Modifiers::#m });
+                    let key = quote_spanned! {key.span()=> KeyCode::#key };
+                    let action = quote! {
+                        Action::ModTap(Modifiers::empty() #(.union(#mods))*, #key)
+                    };
+                    (*span, None, action)
+                }
+                Entry::LayerTap(span, layer, key) => {
+                    let key = quote_spanned! {key.span()=> KeyCode::#key };
+                    (*span, None, quote! { Action::LayerTap(#layer, #key) })
+                }
+                Entry::Profile(span, index) => {
+                    let message = format!("there's no profile {index}");
+                    let check = quote! {
+                        ::core::assert!(
+                            #index < ::kb_driver_core::profile::PROFILES,
+                            #message
+                        )
+                    };
+                    (*span, Some(check), quote! { Action::Profile(#index) })
+                }
+                Entry::Unicode(span, code_point) => {
+                    let check = quote! {
+                        ::core::assert!(
+                            #code_point <= 0x7FFF,
+                            "code points only go up to 0x7FFF"
+                        )
+                    };
+                    (*span, Some(check), quote! { Action::Unicode(#code_point) })
+                }
+            };
+        quote_spanned! {span=>
+            {
+                const {
+                    ::core::assert!(#is_key, #no_key);
+                    #check
+                };
+                #action
+            }
+        }
+    }
+}
+
+impl Keymap {
+    pub fn expand(&self) -> TokenStream {
+        let count = self.layers.len();
+        let layers = self.layers.iter().enumerate().map(|(i, layer)| {
+            let actions =
+                layer.rows.iter().enumerate().flat_map(|(row, entries)| {
+                    entries
+                        .iter()
+                        .enumerate()
+                        .map(move |(col, entry)| entry.expand(row, col))
+                });
+            quote! { layers[#i] = [#(#actions),*]; }
+        });
+        quote! {
+            {
+                use ::kb_driver_core::{
+                    key_codes::{KeyCode, Modifiers},
+                    keymap::{Action, Keymap, COLS, LAYERS, POSITIONS, ROWS}
+                };
+                const {
+                    ::core::assert!(
+                        #ROWS == ROWS && #COLS == COLS,
+                        "keymap! doesn't know the matrix's size anymore"
+                    );
+                    ::core::assert!(#count <= LAYERS, "the keymap has too many layers");
+                };
+                // layers that aren't in the keymap don't do anything
+                let mut layers = [[Action::Transparent; POSITIONS]; LAYERS];
+                #(#layers)*
+                Keymap { layers }
+            }
+        }
+    }
+}
@@ -1,11 +1,26 @@
 //! yes, i did make this exclusively so i don't have to type
 //! `#[cfg(feature = "defmt")]` a bajillion times
+//!
+//! The logging macros pick a backend from the calling crate's features:
+//! - `defmt` logs with defmt, same as everything else here
+//! - `log` goes through the `log` crate, for host builds and tests
+//! - `log-buffer` formats the message for `kb_driver_core::log_sink`, which
+//!   the firmware keeps in a buffer (and sends out over USB serial)
+//!
+//! With none of them the message is still type checked, and its arguments
+//! count as used, so nothing needs a `#[cfg(feature = "defmt")]` around it
 
 #![no_std]
 
+extern crate alloc;
+
 use proc_macro::TokenStream;
+use proc_macro2::Span;
 use quote::quote;
 
+mod core_fmt;
+mod keymap;
+
 macro_rules! defmt_stmt {
     ($name: tt) => {
         #[proc_macro]
@@ -17,7 +32,50 @@ macro_rules! defmt_stmt {
                     #[cfg(feature = "defmt")]
                     ::defmt::$name!(#stream)
                 }
-            }.into()
+            }
+            .into()
+        }
+    };
+}
+
+macro_rules! log_stmt {
+    ($name: tt, $level: ident) => {
+        #[proc_macro]
+        pub fn $name(stream: TokenStream) -> TokenStream {
+            let level =
+                proc_macro2::Ident::new(stringify!($level), Span::call_site());
+            // only ends up compiled without `defmt`, so neither do its errors
+            let args = syn::parse::<core_fmt::LogArgs>(stream.clone())
+                .and_then(|args| args.expand())
+                .unwrap_or_else(syn::Error::into_compile_error);
+            let stream = proc_macro2::TokenStream::from(stream);
+
+            // not every crate that logs has every one of these features
+            quote! {
+                {
+                    #[allow(unexpected_cfgs)]
+                    {
+                        #[cfg(feature = "defmt")]
+                        ::defmt::$name!(#stream);
+                        #[cfg(feature = "log")]
+                        ::log::$name!(#args);
+                        #[cfg(feature = "log-buffer")]
+                        ::kb_driver_core::log_sink::write(
+                            ::kb_driver_core::log_sink::Level::#level,
+                            format_args!(#args)
+                        );
+                        #[cfg(not(any(
+                            feature = "defmt",
+                            feature = "log",
+                            feature = "log-buffer"
+                        )))]
+                        if false {
+                            let _ = format_args!(#args);
+                        }
+                    };
+                }
+            }
+            .into()
         }
     };
 }
@@ -47,11 +105,11 @@ defmt_stmt!(debug_assert_ne);
 
 // logging
 
-defmt_stmt!(trace);
-defmt_stmt!(debug);
-defmt_stmt!(info);
-defmt_stmt!(warn);
-defmt_stmt!(error);
+log_stmt!(trace, Trace);
+log_stmt!(debug, Debug);
+log_stmt!(info, Info);
+log_stmt!(warn, Warn);
+log_stmt!(error, Error);
 
 // convenience
 
@@ -61,3 +119,31 @@ defmt_stmt!(unreachable);
 defmt_stmt!(unwrap);
 defmt_stmt!(write);
 defmt_stmt!(bitflags);
+
+// keymaps
+
+/// Writes out a `kb_driver_core::keymap::Keymap` as a grid laid out like the
+/// matrix, one `layer` after another, each with rows `Y0` to `Y11` of 8
+/// positions
+///
+/// A position is a `KeyCode` variant, `none`, `trans`, `MO(layer)`,
+/// `MACRO(index)`, `MT(LEFT_CTRL | LEFT_SHIFT, key)`, `LT(layer, key)`,
+/// `PROFILE(index)`, `UC(code point)`, or `_` where the matrix doesn't have a
+/// key. Anything wrong with it, including keys left as `_` and layers that
+/// aren't there, fails the build. Layers after the last one are transparent.
+///
+/// ```ignore
+/// const KEYMAP: Keymap = keymap! {
+///     layer 0 {
+///         Y0 [Keyboard1AndExclamation, Keyboard2AndAt, ...],
+///         ...
+///         Y11 [KeyboardLeftShift, KeyboardRightShift, _, _, _, _, _, _]
+///     }
+/// };
+/// ```
+#[proc_macro]
+pub fn keymap(stream: TokenStream) -> TokenStream {
+    syn::parse_macro_input!(stream as keymap::Keymap)
+        .expand()
+        .into()
+}
@@ -0,0 +1,383 @@
+//! The command line, kept apart from `main` so it can run against any
+//! [`Transport`]
+
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use kb_driver_core::{
+    crash_report::EventKind,
+    power::{IdleAction, Policy},
+    protocol::{Counter, Setting}
+};
+use palmkb_keymap::{Format, KeymapFile};
+
+use crate::{device::Device, macro_text, transport::Transport};
+
+/// Configures and inspects the Palm keyboard USB adapter
+#[derive(Parser, Debug)]
+#[command(name = "palmkb", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Cmd
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Reads or replaces the keymap
+    #[command(subcommand)]
+    Keymap(KeymapCmd),
+    /// Reads or changes macros
+    #[command(subcommand)]
+    Macro(MacroCmd),
+    /// Switches between host profiles
+    #[command(subcommand)]
+    Profile(ProfileCmd),
+    /// Shows or changes what happens to the keyboard while the host is
+    /// suspended or it isn't used
+    #[command(subcommand)]
+    Power(PowerCmd),
+    /// Shows the diagnostic counters
+    Stats,
+    /// Shows what the firmware crashed with before its last reset
+    Logs {
+        /// Forgets the crash after showing it
+        #[arg(long)]
+        clear: bool
+    },
+    /// Reboots into the STM32 bootloader for flashing
+    ResetToBootloader,
+    /// Installs a new firmware, a raw `.bin` image. If it doesn't come up
+    /// the bootloader goes back to the old one
+    Update { path: PathBuf },
+    /// Records the raw bytes from the keyboard, for `palmkb-replay`
+    #[command(subcommand)]
+    Capture(CaptureCmd),
+    /// Shows the firmware and protocol versions
+    Version
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeymapCmd {
+    /// Writes the keymap to a file, or stdout
+    Dump {
+        /// Where to write it, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Guessed from the output's extension, JSON if that doesn't work
+        #[arg(short, long)]
+        format: Option<Format>
+    },
+    /// Loads a keymap from a file and saves it on the adapter
+    Load {
+        path: PathBuf,
+        /// Guessed from the extension if not given
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// Only changes the keymap in RAM, so a replug undoes it
+        #[arg(long)]
+        no_commit: bool
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MacroCmd {
+    /// Shows a macro
+    Get { index: u8 },
+    /// Replaces a macro, see the README for the syntax
+    Set { index: u8, text: String }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CaptureCmd {
+    /// Starts recording, throwing away whatever was recorded before
+    Start,
+    /// Stops recording and writes the trace to a file, or stdout
+    Save {
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCmd {
+    /// Shows the active profile
+    Get,
+    /// Switches to another profile and saves that
+    Switch { profile: u8 },
+    /// Turns switching profiles to match the host OS on plug in on or off,
+    /// and saves that
+    DetectHost {
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PowerCmd {
+    /// Shows the power settings
+    Get,
+    /// Changes the power settings and saves them, anything not given stays
+    /// the way it was
+    Set {
+        /// Starts from one of the presets
+        #[arg(long)]
+        preset: Option<PowerPreset>,
+        /// Cuts power to the keyboard while the host is suspended, so key
+        /// presses can't wake it up
+        #[arg(long)]
+        cut_vcc_on_suspend: Option<bool>,
+        /// Minutes without key presses before the keyboard goes to sleep, 0
+        /// keeps it awake
+        #[arg(long)]
+        idle_timeout: Option<u16>,
+        /// What going to sleep means
+        #[arg(long)]
+        idle_action: Option<IdleActionArg>
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum PowerPreset {
+    /// Keeps the keyboard awake and powered no matter what
+    AlwaysOn,
+    /// Sleeps after 5 minutes and cuts power while suspended
+    Battery
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum IdleActionArg {
+    /// Lets the keyboard sleep, a key press wakes it up
+    Sleep,
+    /// Cuts its power, only a HotSync button wired to DCD wakes it up
+    CutPower
+}
+
+/// Runs a command, writing whatever it prints to `out`
+pub fn run(cli: Cli, transport: impl Transport, out: &mut impl Write) -> Result<()> {
+    let mut device = Device::new(transport);
+    match cli.command {
+        Cmd::Keymap(KeymapCmd::Dump { output, format }) => {
+            let format = format
+                .or_else(|| output.as_deref().and_then(Format::from_path))
+                .unwrap_or(Format::Json);
+            let text =
+                KeymapFile::from_keymap(&device.keymap()?).to_string(format)?;
+            match output {
+                Some(path) => fs::write(&path, text)
+                    .with_context(|| format!("couldn't write {}", path.display()))?,
+                None => write!(out, "{text}")?
+            }
+        }
+        Cmd::Keymap(KeymapCmd::Load {
+            path,
+            format,
+            no_commit
+        }) => {
+            let format = format.or_else(|| Format::from_path(&path)).context(
+                "can't tell the format from the extension, pass --format"
+            )?;
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("couldn't read {}", path.display()))?;
+            let file = KeymapFile::parse(&text, format)?;
+            let mut keymap = device.keymap()?;
+            file.apply(&mut keymap)
+                .with_context(|| format!("in {}", path.display()))?;
+            for (layer, actions) in keymap.layers.iter().enumerate() {
+                device.set_layer(layer as u8, actions)?;
+            }
+            if !no_commit {
+                device.commit()?;
+            }
+            writeln!(out, "loaded {} layers", file.layers.len())?;
+        }
+        Cmd::Macro(MacroCmd::Get { index }) => {
+            let macros = device.macros()?;
+            writeln!(out, "{}", macro_text::format(&macros, index))?;
+        }
+        Cmd::Macro(MacroCmd::Set { index, text }) => {
+            let data = macro_text::parse(&text)?;
+            let mut macros = device.macros()?;
+            anyhow::ensure!(
+                macros.set(index, &data),
+                "macro {index} doesn't exist or doesn't fit"
+            );
+            device.set_macros(&macros)?;
+            writeln!(out, "macro {index} set")?;
+        }
+        Cmd::Profile(ProfileCmd::Get) => {
+            writeln!(out, "{}", device.setting(Setting::Profile)?)?;
+        }
+        Cmd::Profile(ProfileCmd::Switch { profile }) => {
+            device.set_setting(Setting::Profile, profile as u32)?;
+            device.commit()?;
+            writeln!(out, "switched to profile {profile}")?;
+        }
+        Cmd::Profile(ProfileCmd::DetectHost { enabled }) => {
+            device.set_setting(Setting::DetectHost, enabled as u32)?;
+            device.commit()?;
+            let state = if enabled { "on" } else { "off" };
+            writeln!(out, "host detection {state}")?;
+        }
+        Cmd::Power(PowerCmd::Get) => {
+            let policy = power_policy(&mut device)?;
+            writeln!(out, "cut power on suspend {}", policy.cut_vcc_on_suspend)?;
+            match policy.idle_timeout_mins {
+                0 => writeln!(out, "idle timeout        never")?,
+                mins => writeln!(out, "idle timeout        {mins} min")?
+            }
+            writeln!(out, "idle action         {:?}", policy.idle_action)?;
+        }
+        Cmd::Power(PowerCmd::Set {
+            preset,
+            cut_vcc_on_suspend,
+            idle_timeout,
+            idle_action
+        }) => {
+            let mut policy = match preset {
+                Some(PowerPreset::AlwaysOn) => Policy::ALWAYS_ON,
+                Some(PowerPreset::Battery) => Policy::BATTERY,
+                None => power_policy(&mut device)?
+            };
+            if let Some(cut_vcc_on_suspend) = cut_vcc_on_suspend {
+                policy.cut_vcc_on_suspend = cut_vcc_on_suspend;
+            }
+            if let Some(mins) = idle_timeout {
+                policy.idle_timeout_mins = mins;
+            }
+            match idle_action {
+                Some(IdleActionArg::Sleep) => {
+                    policy.idle_action = IdleAction::KeyboardSleep
+                }
+                Some(IdleActionArg::CutPower) => {
+                    policy.idle_action = IdleAction::CutPower
+                }
+                None => ()
+            }
+            device.set_setting(
+                Setting::CutVccOnSuspend,
+                policy.cut_vcc_on_suspend as u32
+            )?;
+            device.set_setting(
+                Setting::IdleTimeoutMins,
+                policy.idle_timeout_mins as u32
+            )?;
+            device.set_setting(Setting::IdleAction, policy.idle_action as u32)?;
+            device.commit()?;
+            writeln!(out, "power settings saved")?;
+        }
+        Cmd::Stats => {
+            for (i, value) in device.counters()?.into_iter().enumerate() {
+                writeln!(out, "{:<20} {value}", counter_name(i as u8))?;
+            }
+        }
+        Cmd::Logs { clear } => {
+            match device.crash_report()? {
+                None => writeln!(out, "no crash since the last clear")?,
+                Some(report) => {
+                    writeln!(
+                        out,
+                        "crashed at {}:{}:{}: {}",
+                        report.file(),
+                        report.line,
+                        report.column,
+                        report.message()
+                    )?;
+                    for event in report.events() {
+                        let kind = EventKind::try_from(event.kind)
+                            .map(|k| format!("{k:?}"))
+                            .unwrap_or_else(|_| format!("unknown {}", event.kind));
+                        writeln!(
+                            out,
+                            "{:>10}ms {kind} ({})",
+                            event.timestamp_ms, event.data
+                        )?;
+                    }
+                }
+            }
+            if clear {
+                device.clear_crash_report()?;
+            }
+        }
+        Cmd::ResetToBootloader => {
+            device.reboot_to_bootloader()?;
+            writeln!(out, "rebooting into the bootloader")?;
+        }
+        Cmd::Update { path } => {
+            let image = fs::read(&path)
+                .with_context(|| format!("couldn't read {}", path.display()))?;
+            // every 10% is plenty, there's thousands of chunks
+            let mut shown = 0;
+            device.update(&image, |sent| {
+                let percent = sent * 100 / image.len();
+                if percent / 10 != shown / 10 {
+                    eprint!("\r{percent}%");
+                    shown = percent;
+                }
+            })?;
+            eprintln!();
+            writeln!(out, "sent {} bytes, the adapter is restarting", image.len())?;
+        }
+        Cmd::Capture(CaptureCmd::Start) => {
+            device.start_capture()?;
+            writeln!(
+                out,
+                "recording, use the keyboard then `palmkb capture save`"
+            )?;
+        }
+        Cmd::Capture(CaptureCmd::Save { output }) => {
+            let (len, dropped) = device.stop_capture()?;
+            let mut text = format!("# palmkb capture, {len} samples\n");
+            if dropped > 0 {
+                text += &format!("# {dropped} older samples were dropped\n");
+            }
+            for sample in device.capture(len)? {
+                text += &format!("{sample}\n");
+            }
+            match output {
+                Some(path) => {
+                    fs::write(&path, text).with_context(|| {
+                        format!("couldn't write {}", path.display())
+                    })?;
+                    writeln!(out, "saved {len} samples")?;
+                }
+                None => write!(out, "{text}")?
+            }
+        }
+        Cmd::Version => {
+            let (protocol, firmware) = device.version()?;
+            writeln!(out, "palmkb {}", env!("CARGO_PKG_VERSION"))?;
+            writeln!(out, "firmware {firmware}")?;
+            writeln!(out, "protocol {protocol}")?;
+        }
+    }
+    Ok(())
+}
+
+fn power_policy(device: &mut Device<impl Transport>) -> Result<Policy> {
+    let action = device.setting(Setting::IdleAction)?;
+    Ok(Policy {
+        cut_vcc_on_suspend: device.setting(Setting::CutVccOnSuspend)? != 0,
+        idle_timeout_mins: device.setting(Setting::IdleTimeoutMins)? as u16,
+        idle_action: u8::try_from(action)
+            .ok()
+            .and_then(|a| IdleAction::try_from(a).ok())
+            .context("the adapter sent an unknown idle action")?
+    })
+}
+
+fn counter_name(index: u8) -> String {
+    const NAMES: [(Counter, &str); 7] = [
+        (Counter::UptimeMs, "uptime (ms)"),
+        (Counter::ResetReason, "reset reason"),
+        (Counter::KeyEvents, "key events"),
+        (Counter::UartErrors, "UART errors"),
+        (Counter::HandshakeFailures, "handshake failures"),
+        (Counter::ReportsSent, "reports sent"),
+        (Counter::ConfigCommits, "config commits")
+    ];
+    match NAMES.iter().find(|(c, _)| *c as u8 == index) {
+        Some((_, name)) => name.to_string(),
+        None => format!("task {} restarts", index - Counter::TASK_RESTARTS)
+    }
+}
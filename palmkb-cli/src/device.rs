@@ -0,0 +1,287 @@
+//! The firmware's raw HID protocols wrapped up as method calls
+
+use anyhow::{bail, ensure, Context, Result};
+use kb_driver_core::{
+    capture::{Sample, SAMPLE_LEN},
+    crash_report::{CrashReport, REPORT_LEN as CRASH_REPORT_LEN},
+    crc::crc32,
+    keymap::{Action, Keymap, LAYERS, POSITIONS},
+    macros::{Macros, BUFFER_LEN},
+    protocol::{
+        Capabilities, Command, Setting, Status, MAX_ACTIONS, MAX_CAPTURE_SAMPLES,
+        MAX_COUNTERS, MAX_UPDATE_CHUNK, REPORT_LEN
+    }
+};
+
+use crate::transport::Transport;
+
+/// VIA's macro buffer commands, the vendor protocol doesn't have its own
+const VIA_MACRO_GET_BUFFER: u8 = 0x0E;
+const VIA_MACRO_SET_BUFFER: u8 = 0x0F;
+const VIA_UNHANDLED: u8 = 0xFF;
+const VIA_MAX_CHUNK: usize = REPORT_LEN - 4;
+
+pub struct Device<T: Transport> {
+    transport: T
+}
+
+impl<T: Transport> Device<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Sends a vendor protocol command, returning the data after the status
+    fn request(
+        &mut self,
+        command: Command,
+        args: &[u8]
+    ) -> Result<[u8; REPORT_LEN - 2]> {
+        let mut request = [0u8; REPORT_LEN];
+        request[0] = command as u8;
+        request[1..1 + args.len()].copy_from_slice(args);
+        let response = self.transport.exchange(&request)?;
+        ensure!(
+            response[0] == command as u8,
+            "got an answer to {:#04x} instead of {command:?}",
+            response[0]
+        );
+        match Status::try_from(response[1]) {
+            Ok(Status::Ok) => Ok(response[2..].try_into().unwrap()),
+            Ok(status) => bail!("{command:?} failed: {status:?}"),
+            Err(_) => bail!("{command:?} failed with unknown status {}", response[1])
+        }
+    }
+
+    /// Sends a VIA command, returning the whole echoed response
+    fn via_request(
+        &mut self,
+        request: [u8; REPORT_LEN]
+    ) -> Result<[u8; REPORT_LEN]> {
+        let response = self.transport.exchange(&request)?;
+        ensure!(
+            response[0] != VIA_UNHANDLED,
+            "VIA command {:#04x} was refused",
+            request[0]
+        );
+        Ok(response)
+    }
+
+    /// The protocol version and the firmware's version
+    pub fn version(&mut self) -> Result<(u8, String)> {
+        let data = self.request(Command::GetVersion, &[])?;
+        let version = &data[1..];
+        let len = version
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(version.len());
+        Ok((
+            data[0],
+            String::from_utf8_lossy(&version[..len]).into_owned()
+        ))
+    }
+
+    pub fn capabilities(&mut self) -> Result<Capabilities> {
+        let data = self.request(Command::GetCapabilities, &[])?;
+        Capabilities::from_bytes(&data).context("capabilities are cut off")
+    }
+
+    pub fn keymap(&mut self) -> Result<Keymap> {
+        let mut keymap = Keymap::DEFAULT;
+        for (layer, actions) in keymap.layers.iter_mut().enumerate() {
+            for start in (0..POSITIONS).step_by(MAX_ACTIONS) {
+                let count = MAX_ACTIONS.min(POSITIONS - start);
+                let data = self.request(
+                    Command::GetKeymap,
+                    &[layer as u8, start as u8, count as u8]
+                )?;
+                let (chunks, _) = data[3..].as_chunks::<2>();
+                for (i, &chunk) in chunks.iter().take(count).enumerate() {
+                    let value = u16::from_le_bytes(chunk);
+                    actions[start + i] =
+                        Action::from_u16(value).with_context(|| {
+                            format!(
+                                "the adapter sent an unknown action {value:#06x}"
+                            )
+                        })?;
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// Sends a whole layer, it only takes effect in RAM until it's committed
+    pub fn set_layer(
+        &mut self,
+        layer: u8,
+        actions: &[Action; POSITIONS]
+    ) -> Result<()> {
+        ensure!((layer as usize) < LAYERS, "there's no layer {layer}");
+        for start in (0..POSITIONS).step_by(MAX_ACTIONS) {
+            let chunk = &actions[start..(start + MAX_ACTIONS).min(POSITIONS)];
+            let mut args = vec![layer, start as u8, chunk.len() as u8];
+            for action in chunk {
+                args.extend_from_slice(&action.to_u16().to_le_bytes());
+            }
+            self.request(Command::SetKeymap, &args)?;
+        }
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        self.request(Command::Commit, &[]).map(drop)
+    }
+
+    pub fn revert(&mut self) -> Result<()> {
+        self.request(Command::Revert, &[]).map(drop)
+    }
+
+    pub fn setting(&mut self, setting: Setting) -> Result<u32> {
+        let data = self.request(Command::GetSetting, &[setting as u8])?;
+        Ok(u32::from_le_bytes(data[1..5].try_into().unwrap()))
+    }
+
+    /// Changes a setting, it only takes effect in RAM until it's committed
+    pub fn set_setting(&mut self, setting: Setting, value: u32) -> Result<()> {
+        let mut args = vec![setting as u8];
+        args.extend_from_slice(&value.to_le_bytes());
+        self.request(Command::SetSetting, &args).map(drop)
+    }
+
+    /// Every diagnostic counter, see [`Counter`](kb_driver_core::protocol::Counter)
+    pub fn counters(&mut self) -> Result<Vec<u32>> {
+        let count = self.capabilities()?.counters;
+        let mut counters = Vec::with_capacity(count as usize);
+        while counters.len() < count as usize {
+            let first = counters.len() as u8;
+            let want = MAX_COUNTERS.min(count as usize - counters.len());
+            let data =
+                self.request(Command::GetDiagnostics, &[first, want as u8])?;
+            let read = data[1] as usize;
+            ensure!(
+                read > 0,
+                "the adapter stopped returning counters at {first}"
+            );
+            let (chunks, _) = data[2..].as_chunks::<4>();
+            counters
+                .extend(chunks.iter().take(read).map(|&c| u32::from_le_bytes(c)));
+        }
+        Ok(counters)
+    }
+
+    pub fn crash_report(&mut self) -> Result<Option<CrashReport>> {
+        let mut bytes = Vec::with_capacity(CRASH_REPORT_LEN);
+        while bytes.len() < CRASH_REPORT_LEN {
+            let offset = (bytes.len() as u16).to_le_bytes();
+            let data = self.request(Command::ReadCrashReport, &offset)?;
+            if data[0] == 0 {
+                return Ok(None);
+            }
+            let count = data[3] as usize;
+            ensure!(count > 0, "the crash report is cut off");
+            bytes.extend_from_slice(&data[4..4 + count]);
+        }
+        CrashReport::from_bytes(&bytes)
+            .context("the crash report is in a format we don't know")
+            .map(Some)
+    }
+
+    pub fn clear_crash_report(&mut self) -> Result<()> {
+        self.request(Command::ClearCrashReport, &[]).map(drop)
+    }
+
+    /// The device disconnects right after this
+    pub fn reboot_to_bootloader(&mut self) -> Result<()> {
+        self.request(Command::RebootToBootloader, &[]).map(drop)
+    }
+
+    /// Sends a firmware image, calling `progress` with how many bytes made it
+    /// so far. The device resets into the new firmware right after
+    pub fn update(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize)
+    ) -> Result<()> {
+        let len = u32::try_from(image.len()).context("the image is way too big")?;
+        self.request(Command::BeginUpdate, &len.to_le_bytes())
+            .context("the adapter won't take an image that big")?;
+        for (i, chunk) in image.chunks(MAX_UPDATE_CHUNK).enumerate() {
+            let offset = (i * MAX_UPDATE_CHUNK) as u32;
+            let mut args = [0u8; 5 + MAX_UPDATE_CHUNK];
+            args[..4].copy_from_slice(&offset.to_le_bytes());
+            args[4] = chunk.len() as u8;
+            args[5..5 + chunk.len()].copy_from_slice(chunk);
+            self.request(Command::WriteUpdate, &args)?;
+            progress(offset as usize + chunk.len());
+        }
+        self.request(Command::FinishUpdate, &crc32(image).to_le_bytes())
+            .context("the image got corrupted on the way")
+            .map(drop)
+    }
+
+    /// Starts recording the keyboard's bytes, throwing the last capture away
+    pub fn start_capture(&mut self) -> Result<()> {
+        self.request(Command::Capture, &[1]).map(drop)
+    }
+
+    /// Stops recording, returning how many samples were kept and dropped
+    pub fn stop_capture(&mut self) -> Result<(u16, u32)> {
+        let data = self.request(Command::Capture, &[0])?;
+        Ok((
+            u16::from_le_bytes([data[0], data[1]]),
+            u32::from_le_bytes(data[2..6].try_into().unwrap())
+        ))
+    }
+
+    /// Reads a stopped capture, `len` samples of it
+    pub fn capture(&mut self, len: u16) -> Result<Vec<Sample>> {
+        let mut samples = Vec::with_capacity(len as usize);
+        while samples.len() < len as usize {
+            let index = (samples.len() as u16).to_le_bytes();
+            let data = self.request(Command::ReadCapture, &index)?;
+            let count = (data[2] as usize).min(MAX_CAPTURE_SAMPLES);
+            ensure!(count > 0, "the capture is cut off at {}", samples.len());
+            let (chunks, _) = data[3..].as_chunks::<SAMPLE_LEN>();
+            samples.extend(
+                chunks
+                    .iter()
+                    .take(count)
+                    .filter_map(|chunk| Sample::from_bytes(chunk))
+            );
+        }
+        Ok(samples)
+    }
+
+    pub fn macros(&mut self) -> Result<Macros> {
+        let mut macros = Macros::EMPTY;
+        for start in (0..BUFFER_LEN).step_by(VIA_MAX_CHUNK) {
+            let size = VIA_MAX_CHUNK.min(BUFFER_LEN - start);
+            let mut request = [0u8; REPORT_LEN];
+            request[0] = VIA_MACRO_GET_BUFFER;
+            request[1..3].copy_from_slice(&(start as u16).to_be_bytes());
+            request[3] = size as u8;
+            let response = self.via_request(request)?;
+            macros.buffer[start..start + size]
+                .copy_from_slice(&response[4..4 + size]);
+        }
+        Ok(macros)
+    }
+
+    /// Writes the whole macro buffer, which the firmware saves right away
+    pub fn set_macros(&mut self, macros: &Macros) -> Result<()> {
+        for start in (0..BUFFER_LEN).step_by(VIA_MAX_CHUNK) {
+            let size = VIA_MAX_CHUNK.min(BUFFER_LEN - start);
+            let mut request = [0u8; REPORT_LEN];
+            request[0] = VIA_MACRO_SET_BUFFER;
+            request[1..3].copy_from_slice(&(start as u16).to_be_bytes());
+            request[3] = size as u8;
+            request[4..4 + size]
+                .copy_from_slice(&macros.buffer[start..start + size]);
+            self.via_request(request)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! A stand-in for the firmware that runs in-process, so everything above the
+//! transport can be tried out without a device
+//!
+//! It answers requests with the same [`protocol::dispatch`] the firmware uses,
+//! backed by a config in memory instead of flash.
+
+use anyhow::Result;
+use kb_driver_core::{
+    capture::Capture,
+    config::Config,
+    crash_report::CrashReport,
+    firmware_update::UpdateSlot,
+    mem_flash::MemFlash,
+    protocol::{self, Backend, Counter, UpdateError, REPORT_LEN}
+};
+
+use crate::transport::Transport;
+
+/// How many tasks the firmware supervises, for the task restart counters
+const TASK_COUNT: u8 = 6;
+/// Same as the firmware's active slot, the biggest image it takes
+const UPDATE_CAPACITY: usize = 128 * 1024;
+
+pub type UpdateFlash = MemFlash<UPDATE_CAPACITY, UPDATE_CAPACITY, 4>;
+
+pub struct Emulator {
+    /// The config as it is in RAM
+    pub config: Config,
+    /// The config as it was last committed
+    pub saved: Config,
+    pub counters: [u32; (Counter::TASK_RESTARTS + TASK_COUNT) as usize],
+    pub crash: Option<CrashReport>,
+    /// Set once the host asked for the bootloader
+    pub rebooted: bool,
+    pub commits: u32,
+    /// Where firmware updates get written
+    pub update: Box<UpdateSlot<UpdateFlash>>,
+    /// Length of the last image that passed its CRC check
+    pub updated: Option<u32>,
+    /// There's no keyboard, tests record into it themselves
+    pub capture: Box<Capture>
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self {
+            config: Config::DEFAULT,
+            saved: Config::DEFAULT,
+            counters: Default::default(),
+            crash: None,
+            rebooted: false,
+            commits: 0,
+            update: Box::new(UpdateSlot::new(
+                UpdateFlash::new(),
+                0,
+                UPDATE_CAPACITY as u32
+            )),
+            updated: None,
+            capture: Box::default()
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for Emulator {
+    fn with_config<R>(&mut self, f: impl FnOnce(&mut Config) -> R) -> R {
+        f(&mut self.config)
+    }
+
+    fn commit(&mut self) -> bool {
+        self.saved = self.config.clone();
+        self.commits += 1;
+        self.counters[Counter::ConfigCommits as usize] += 1;
+        true
+    }
+
+    fn revert(&mut self) -> bool {
+        self.config = self.saved.clone();
+        true
+    }
+
+    fn counter(&mut self, index: u8) -> Option<u32> {
+        self.counters.get(index as usize).copied()
+    }
+
+    fn counter_count(&self) -> u8 {
+        self.counters.len() as u8
+    }
+
+    fn firmware_version(&self) -> &str {
+        "0.0.0-emulated"
+    }
+
+    fn factory_reset(&mut self) -> bool {
+        self.config = Config::DEFAULT;
+        self.saved = Config::DEFAULT;
+        true
+    }
+
+    fn default_config(&self) -> &Config {
+        &Config::DEFAULT
+    }
+
+    fn vial_definition(&self) -> &[u8] {
+        &[]
+    }
+
+    fn crash_report(&mut self) -> Option<CrashReport> {
+        self.crash
+    }
+
+    fn clear_crash_report(&mut self) {
+        self.crash = None;
+    }
+
+    fn reboot_to_bootloader(&mut self) -> bool {
+        self.rebooted = true;
+        true
+    }
+
+    fn begin_update(&mut self, len: u32) -> Result<(), UpdateError> {
+        self.update.begin(len).map_err(|e| e.map_flash(drop))
+    }
+
+    fn write_update(&mut self, offset: u32, data: &[u8]) -> Result<(), UpdateError> {
+        self.update
+            .write(offset, data)
+            .map_err(|e| e.map_flash(drop))
+    }
+
+    fn finish_update(&mut self, crc: u32) -> Result<(), UpdateError> {
+        let len = self.update.finish(crc).map_err(|e| e.map_flash(drop))?;
+        self.updated = Some(len);
+        Ok(())
+    }
+
+    fn with_capture<R>(&mut self, f: impl FnOnce(&mut Capture) -> R) -> R {
+        f(&mut self.capture)
+    }
+}
+
+impl Transport for Emulator {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN]> {
+        let mut response = [0u8; REPORT_LEN];
+        protocol::dispatch(request, &mut response, self);
+        Ok(response)
+    }
+}
@@ -0,0 +1,12 @@
+//! Host side of the adapter's raw HID interface, behind the `palmkb` command
+//!
+//! Everything talks to the firmware through a [`Transport`], which is either
+//! the real device over hidapi or an in-process [`Emulator`].
+
+pub mod cli;
+pub mod device;
+pub mod emulator;
+pub mod macro_text;
+pub mod transport;
+
+pub use self::{device::Device, emulator::Emulator, transport::Transport};
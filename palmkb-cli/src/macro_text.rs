@@ -0,0 +1,80 @@
+//! Macros written as text, for the command line
+//!
+//! Everything is typed out as is, except for steps in braces: `{tap 0x28}`,
+//! `{down 0xE1}`, `{up 0xE1}` and `{delay 100}`. `{{` types a literal brace.
+
+use anyhow::{bail, Context, Result};
+use kb_driver_core::{
+    key_codes::KeyCode,
+    macros::{Macros, Step, PREFIX}
+};
+
+/// Turns macro text into the bytes the firmware stores
+pub fn parse(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(tail) = rest.strip_prefix("{{") {
+            out.push(b'{');
+            rest = tail;
+            continue;
+        }
+        if c != '{' {
+            if !c.is_ascii() || KeyCode::from_ascii(c as u8).is_none() {
+                bail!("`{c}` can't be typed on a US layout");
+            }
+            out.push(c as u8);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest.find('}').context("a `{` is never closed")?;
+        let (kind, arg) =
+            rest[1..end].trim().split_once(' ').with_context(|| {
+                format!("`{}` is missing an argument", &rest[..=end])
+            })?;
+        let arg = arg.trim();
+        match kind {
+            "delay" => {
+                let ms: u16 = arg.parse().context("delays are in milliseconds")?;
+                out.extend_from_slice(&[PREFIX, 0x04]);
+                out.extend_from_slice(ms.to_string().as_bytes());
+                out.push(b'|');
+            }
+            "tap" | "down" | "up" => {
+                let usage = match arg.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => arg.parse()
+                }
+                .with_context(|| format!("`{arg}` isn't a HID usage"))?;
+                if KeyCode::try_from(usage).is_err() || usage == 0 {
+                    bail!("`{arg}` isn't a key");
+                }
+                let kind = match kind {
+                    "tap" => 0x01,
+                    "down" => 0x02,
+                    _ => 0x03
+                };
+                out.extend_from_slice(&[PREFIX, kind, usage]);
+            }
+            _ => bail!("unknown macro step `{kind}`")
+        }
+        rest = &rest[end + 1..];
+    }
+    Ok(out)
+}
+
+/// Writes a stored macro back out as text
+pub fn format(macros: &Macros, index: u8) -> String {
+    let mut out = String::new();
+    for step in macros.steps(index) {
+        match step {
+            Step::Char(b'{') => out.push_str("{{"),
+            Step::Char(c) => out.push(c as char),
+            Step::Tap(key) => out.push_str(&format!("{{tap {:#04x}}}", key as u8)),
+            Step::Down(key) => out.push_str(&format!("{{down {:#04x}}}", key as u8)),
+            Step::Up(key) => out.push_str(&format!("{{up {:#04x}}}", key as u8)),
+            Step::Delay(ms) => out.push_str(&format!("{{delay {ms}}}"))
+        }
+    }
+    out
+}
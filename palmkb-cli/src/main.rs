@@ -0,0 +1,22 @@
+use anyhow::Result;
+use clap::Parser;
+use palmkb_cli::cli::{self, Cli};
+
+fn main() -> Result<()> {
+    // `RUST_LOG=debug` shows what the shared code logs
+    env_logger::init();
+    let cli = Cli::parse();
+    cli::run(cli, open()?, &mut std::io::stdout())
+}
+
+#[cfg(feature = "hid")]
+fn open() -> Result<impl palmkb_cli::Transport> {
+    palmkb_cli::transport::HidTransport::open()
+}
+
+#[cfg(not(feature = "hid"))]
+fn open() -> Result<palmkb_cli::Emulator> {
+    anyhow::bail!(
+        "built without the `hid` feature, there's no way to reach a device"
+    )
+}
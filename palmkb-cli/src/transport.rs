@@ -0,0 +1,80 @@
+//! How requests get to the firmware and back
+
+use anyhow::Result;
+use kb_driver_core::protocol::REPORT_LEN;
+
+/// Anything that can send a raw HID request and wait for the answer
+pub trait Transport {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN]>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn exchange(&mut self, request: &[u8; REPORT_LEN]) -> Result<[u8; REPORT_LEN]> {
+        (**self).exchange(request)
+    }
+}
+
+#[cfg(feature = "hid")]
+pub use self::hid::HidTransport;
+
+#[cfg(feature = "hid")]
+mod hid {
+    use anyhow::{bail, Context, Result};
+    use hidapi::{HidApi, HidDevice};
+    use kb_driver_core::{
+        protocol::REPORT_LEN,
+        via::{PRODUCT_ID, VENDOR_ID}
+    };
+
+    use super::Transport;
+
+    const USAGE_PAGE: u16 = 0xFF60;
+    const USAGE: u16 = 0x61;
+    const TIMEOUT_MS: i32 = 1000;
+
+    /// The adapter's raw HID interface
+    pub struct HidTransport {
+        device: HidDevice
+    }
+
+    impl HidTransport {
+        /// Opens the first adapter that's plugged in
+        pub fn open() -> Result<Self> {
+            let api = HidApi::new().context("couldn't initialize hidapi")?;
+            let info = api
+                .device_list()
+                .find(|d| {
+                    d.vendor_id() == VENDOR_ID
+                        && d.product_id() == PRODUCT_ID
+                        && d.usage_page() == USAGE_PAGE
+                        && d.usage() == USAGE
+                })
+                .context("no adapter found, is it plugged in?")?;
+            let device = info
+                .open_device(&api)
+                .context("couldn't open the adapter")?;
+            Ok(Self { device })
+        }
+    }
+
+    impl Transport for HidTransport {
+        fn exchange(
+            &mut self,
+            request: &[u8; REPORT_LEN]
+        ) -> Result<[u8; REPORT_LEN]> {
+            // report ID 0 first, the interface doesn't use report IDs
+            let mut out = [0u8; REPORT_LEN + 1];
+            out[1..].copy_from_slice(request);
+            self.device.write(&out).context("couldn't send request")?;
+            let mut response = [0u8; REPORT_LEN];
+            let len = self
+                .device
+                .read_timeout(&mut response, TIMEOUT_MS)
+                .context("couldn't read response")?;
+            if len == 0 {
+                bail!("the adapter didn't answer");
+            }
+            Ok(response)
+        }
+    }
+}
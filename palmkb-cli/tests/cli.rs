@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use kb_driver_core::{
+    config::Config,
+    crash_report::{CrashReport, Event, EventKind},
+    key_codes::KeyCode,
+    keymap::{Action, COLS},
+    power::{IdleAction, Policy}
+};
+use palmkb_cli::{
+    cli::{self, Cli},
+    Emulator
+};
+use palmkb_keymap::{Format, KeymapFile};
+
+fn run(emulator: &mut Emulator, args: &[&str]) -> anyhow::Result<String> {
+    let cli =
+        Cli::try_parse_from(std::iter::once("palmkb").chain(args.iter().copied()))?;
+    let mut out = Vec::new();
+    cli::run(cli, emulator, &mut out)?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("palmkb-test-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn version() {
+    let out = run(&mut Emulator::new(), &["version"]).unwrap();
+    assert!(out.contains("firmware 0.0.0-emulated"));
+    assert!(out.contains("protocol 1"));
+}
+
+#[test]
+fn keymap_dump_matches_config() {
+    let mut emulator = Emulator::new();
+    let out = run(&mut emulator, &["keymap", "dump"]).unwrap();
+    let file = KeymapFile::parse(&out, Format::Json).unwrap();
+    assert_eq!(file, KeymapFile::from_keymap(Config::DEFAULT.keymap()));
+
+    let out = run(&mut emulator, &["keymap", "dump", "--format", "toml"]).unwrap();
+    assert_eq!(KeymapFile::parse(&out, Format::Toml).unwrap(), file);
+}
+
+#[test]
+fn keymap_load_round_trips() {
+    let mut emulator = Emulator::new();
+    let mut keymap = Config::DEFAULT.keymap().clone();
+    keymap.layers[0][2 * COLS + 1] = Action::Key(KeyCode::KeyboardB);
+    keymap.layers[2][0] = Action::LayerTap(3, KeyCode::KeyboardEscape);
+    let path = temp_path("keymap.toml");
+    std::fs::write(
+        &path,
+        KeymapFile::from_keymap(&keymap)
+            .to_string(Format::Toml)
+            .unwrap()
+    )
+    .unwrap();
+
+    run(&mut emulator, &["keymap", "load", path.to_str().unwrap()]).unwrap();
+    assert!(*emulator.saved.keymap() == keymap);
+
+    let dumped = temp_path("dump.json");
+    run(
+        &mut emulator,
+        &["keymap", "dump", "-o", dumped.to_str().unwrap()]
+    )
+    .unwrap();
+    let text = std::fs::read_to_string(&dumped).unwrap();
+    assert_eq!(
+        KeymapFile::parse(&text, Format::Json).unwrap(),
+        KeymapFile::from_keymap(&keymap)
+    );
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(dumped);
+}
+
+#[test]
+fn keymap_load_without_commit_stays_in_ram() {
+    let mut emulator = Emulator::new();
+    let mut keymap = Config::DEFAULT.keymap().clone();
+    keymap.layers[1][5] = Action::Macro(2);
+    let path = temp_path("ram.json");
+    std::fs::write(
+        &path,
+        KeymapFile::from_keymap(&keymap)
+            .to_string(Format::Json)
+            .unwrap()
+    )
+    .unwrap();
+
+    run(
+        &mut emulator,
+        &["keymap", "load", "--no-commit", path.to_str().unwrap()]
+    )
+    .unwrap();
+    assert!(*emulator.config.keymap() == keymap);
+    assert!(emulator.saved.keymap() == Config::DEFAULT.keymap());
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn keymap_load_names_the_bad_position() {
+    let mut emulator = Emulator::new();
+    let mut file = KeymapFile::from_keymap(Config::DEFAULT.keymap());
+    file.layers[1].rows[6][3] = "MO(9)".into();
+    let path = temp_path("bad.json");
+    std::fs::write(&path, file.to_string(Format::Json).unwrap()).unwrap();
+
+    let err =
+        run(&mut emulator, &["keymap", "load", path.to_str().unwrap()]).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("layer 1 row 6 col 3"),
+        "{err:#}"
+    );
+    assert!(emulator.config.keymap() == Config::DEFAULT.keymap());
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn macro_set_and_get() {
+    let mut emulator = Emulator::new();
+    run(
+        &mut emulator,
+        &["macro", "set", "1", "hi{tap 0x28}{delay 50}"]
+    )
+    .unwrap();
+    assert_eq!(emulator.saved.macros.get(1), b"hi\x01\x01\x28\x01\x0450|");
+    let out = run(&mut emulator, &["macro", "get", "1"]).unwrap();
+    assert_eq!(out.trim(), "hi{tap 0x28}{delay 50}");
+    assert!(run(&mut emulator, &["macro", "set", "0", "{nope 1}"]).is_err());
+}
+
+#[test]
+fn profile_switch() {
+    let mut emulator = Emulator::new();
+    run(&mut emulator, &["profile", "switch", "2"]).unwrap();
+    assert_eq!(emulator.saved.profile, 2);
+    assert_eq!(run(&mut emulator, &["profile", "get"]).unwrap().trim(), "2");
+}
+
+#[test]
+fn profile_detect_host() {
+    let mut emulator = Emulator::new();
+    assert!(emulator.saved.detect_host);
+    run(&mut emulator, &["profile", "detect-host", "false"]).unwrap();
+    assert!(!emulator.saved.detect_host);
+    run(&mut emulator, &["profile", "detect-host", "true"]).unwrap();
+    assert!(emulator.saved.detect_host);
+}
+
+#[test]
+fn power() {
+    let mut emulator = Emulator::new();
+    let out = run(&mut emulator, &["power", "get"]).unwrap();
+    assert!(out.contains("idle timeout        never"));
+    run(&mut emulator, &["power", "set", "--preset", "battery"]).unwrap();
+    assert_eq!(emulator.saved.power, Policy::BATTERY);
+    let args = ["--idle-timeout", "10", "--idle-action", "cut-power"];
+    run(&mut emulator, &[&["power", "set"][..], &args].concat()).unwrap();
+    assert!(emulator.saved.power.cut_vcc_on_suspend);
+    assert_eq!(emulator.saved.power.idle_timeout_mins, 10);
+    assert_eq!(emulator.saved.power.idle_action, IdleAction::CutPower);
+    let out = run(&mut emulator, &["power", "get"]).unwrap();
+    assert!(out.contains("idle timeout        10 min"));
+}
+
+#[test]
+fn stats() {
+    let mut emulator = Emulator::new();
+    emulator.counters[2] = 1234;
+    let out = run(&mut emulator, &["stats"]).unwrap();
+    assert!(out.contains("key events"));
+    assert!(out.contains("1234"));
+    assert!(out.contains("task 5 restarts"));
+}
+
+#[test]
+fn logs() {
+    let mut emulator = Emulator::new();
+    let out = run(&mut emulator, &["logs"]).unwrap();
+    assert!(out.contains("no crash"));
+
+    emulator.crash = Some(CrashReport::new(
+        b"index out of bounds",
+        b"src/palm_kb/mod.rs",
+        42,
+        7,
+        [Event {
+            timestamp_ms: 100,
+            kind: EventKind::KbConnected as u8,
+            data: 0
+        }]
+    ));
+    let out = run(&mut emulator, &["logs", "--clear"]).unwrap();
+    assert!(out.contains("src/palm_kb/mod.rs:42:7: index out of bounds"));
+    assert!(out.contains("KbConnected"));
+    assert!(emulator.crash.is_none());
+}
+
+#[test]
+fn reset_to_bootloader() {
+    let mut emulator = Emulator::new();
+    run(&mut emulator, &["reset-to-bootloader"]).unwrap();
+    assert!(emulator.rebooted);
+}
+
+#[test]
+fn update() {
+    let mut emulator = Emulator::new();
+    let image: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    let path = temp_path("firmware.bin");
+    std::fs::write(&path, &image).unwrap();
+    let out = run(&mut emulator, &["update", path.to_str().unwrap()]).unwrap();
+    assert!(out.contains("sent 1000 bytes"));
+    assert_eq!(emulator.updated, Some(1000));
+    assert_eq!(&emulator.update.flash().data[..1000], &image[..]);
+
+    std::fs::write(&path, vec![0; 256 * 1024]).unwrap();
+    let err = run(&mut emulator, &["update", path.to_str().unwrap()]).unwrap_err();
+    assert!(err.to_string().contains("won't take an image that big"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn capture() {
+    let mut emulator = Emulator::new();
+    run(&mut emulator, &["capture", "start"]).unwrap();
+    assert!(emulator.capture.is_recording());
+    // more than one response's worth
+    let bytes = [0xFA, 0xFD, 0x0C, 0x8C, 0x8C, 0x21, 0xA1, 0xA1];
+    for (i, byte) in bytes.into_iter().enumerate() {
+        emulator.capture.record(1000 + i as u32 * 30, byte);
+    }
+
+    let out = run(&mut emulator, &["capture", "save"]).unwrap();
+    assert!(!emulator.capture.is_recording());
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "# palmkb capture, 8 samples");
+    assert_eq!(lines[1], "1000 FA");
+    assert_eq!(lines[3], "1060 0C");
+    assert_eq!(lines[8], "1210 A1");
+    assert_eq!(lines.len(), 9);
+}
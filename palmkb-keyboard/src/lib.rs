@@ -0,0 +1,233 @@
+//! A pretend Palm/Stowaway keyboard, for testing the adapter's key handling
+//! without one, or anything else that reads the keyboard's serial protocol
+//!
+//! The real keyboard talks 9600 baud 8N1 on its TXD line. Once it has power
+//! and sees RTS go high it answers with the [`HANDSHAKE`], then sends a byte
+//! per key event: the position on the [`MATRIX`] for a press, with the top bit
+//! set for a release. Letting go of the last held key sends its release twice.
+//! Left alone for a while it goes to sleep, and a key pressed while it's
+//! asleep raises DCD instead of being sent, until the next handshake wakes it.
+//!
+//! [`Keyboard`] does all that against a clock of its own, handing out what it
+//! sent as timestamped [`Sample`]s, the same as a capture from the adapter.
+//! [`script`] drives it from text, and on Linux [`pty`] feeds its bytes to a
+//! pseudo terminal.
+
+use anyhow::{bail, Context, Result};
+use kb_driver_core::{
+    capture::Sample, key_codes::KeyCode, matrix::MATRIX, model::MODEL
+};
+
+#[cfg(target_os = "linux")]
+pub mod pty;
+pub mod script;
+
+/// What the keyboard sends after being powered up or poked with RTS
+pub const HANDSHAKE: [u8; 2] = MODEL.handshake;
+/// The top bit of a key event, set for releases
+pub const KEY_UP: u8 = 0x80;
+/// How long [`Keyboard::tap`] holds a key, and waits after letting go
+pub const TAP_MS: u32 = 30;
+
+/// Finds a key on the matrix, going by what it does without Fn
+pub fn position(key: KeyCode) -> Option<u8> {
+    MATRIX
+        .iter()
+        .position(|k| matches!(k, Some((k, _)) if *k == key))
+        .map(|pos| pos as u8)
+}
+
+/// Finds a key by its [`KeyCode`] name or its position on the matrix
+pub fn parse_key(name: &str) -> Result<u8> {
+    let name = name.trim();
+    if let Ok(pos) = name.parse::<u8>() {
+        match MATRIX.get(pos as usize) {
+            Some(Some(_)) => return Ok(pos),
+            _ => bail!("there's no key at position {pos}")
+        }
+    }
+    let key =
+        KeyCode::from_name(name).with_context(|| format!("unknown key `{name}`"))?;
+    position(key).with_context(|| format!("the keyboard doesn't have {name}"))
+}
+
+pub struct Keyboard {
+    now_ms: u32,
+    powered: bool,
+    rts: bool,
+    awake: bool,
+    dcd: bool,
+    /// Positions held down, in the order they went down
+    held: Vec<u8>,
+    sent: Vec<Sample>
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keyboard {
+    /// A keyboard that isn't powered yet
+    pub fn new() -> Self {
+        Self {
+            now_ms: 0,
+            powered: false,
+            rts: false,
+            awake: false,
+            dcd: false,
+            held: Vec::new(),
+            sent: Vec::new()
+        }
+    }
+
+    /// Powered up and past the handshake, with the handshake already sent
+    pub fn connected() -> Self {
+        let mut keyboard = Self::new();
+        keyboard.set_power(true);
+        keyboard.set_rts(true);
+        keyboard
+    }
+
+    /// Milliseconds on the keyboard's own clock
+    #[inline]
+    pub fn now_ms(&self) -> u32 {
+        self.now_ms
+    }
+
+    pub fn wait(&mut self, ms: u32) {
+        self.now_ms = self.now_ms.wrapping_add(ms);
+    }
+
+    /// Cutting power lets go of every key without sending anything
+    pub fn set_power(&mut self, on: bool) {
+        self.powered = on;
+        if !on {
+            self.awake = false;
+            self.dcd = false;
+            self.held.clear();
+        }
+    }
+
+    /// RTS going high while powered gets the handshake back and wakes the
+    /// keyboard up
+    pub fn set_rts(&mut self, high: bool) {
+        let rising = high && !self.rts;
+        self.rts = high;
+        if rising && self.powered {
+            self.awake = true;
+            self.dcd = false;
+            self.send_all(&HANDSHAKE);
+        }
+    }
+
+    /// Toggles RTS like the adapter does to get a handshake
+    pub fn handshake(&mut self) {
+        self.set_rts(false);
+        self.set_rts(true);
+    }
+
+    /// What the real keyboard does when nobody pokes it for a while
+    pub fn sleep(&mut self) {
+        self.awake = false;
+    }
+
+    #[inline]
+    pub fn is_awake(&self) -> bool {
+        self.awake
+    }
+
+    /// Raised by a key press while asleep, until the next handshake
+    #[inline]
+    pub fn dcd(&self) -> bool {
+        self.dcd
+    }
+
+    /// Presses the key at a matrix position, does nothing if it's already
+    /// down
+    pub fn press(&mut self, pos: u8) {
+        if !self.powered || self.held.contains(&pos) {
+            return;
+        }
+        if !self.awake {
+            self.dcd = true;
+            return;
+        }
+        self.held.push(pos);
+        self.send(pos);
+    }
+
+    /// Lets go of the key at a matrix position, does nothing if it isn't down
+    pub fn release(&mut self, pos: u8) {
+        let Some(i) = self.held.iter().position(|p| *p == pos) else {
+            return;
+        };
+        self.held.remove(i);
+        self.send(pos | KEY_UP);
+        if self.held.is_empty() {
+            self.send(pos | KEY_UP);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        while let Some(pos) = self.held.first() {
+            self.release(*pos);
+        }
+    }
+
+    /// Presses and lets go of a key, holding it for [`TAP_MS`]
+    pub fn tap(&mut self, pos: u8) {
+        self.press(pos);
+        self.wait(TAP_MS);
+        self.release(pos);
+        self.wait(TAP_MS);
+    }
+
+    /// Types out ASCII text on top of whatever's held down, with shift where a
+    /// US layout needs it
+    pub fn type_str(&mut self, text: &str) -> Result<()> {
+        let shift = position(KeyCode::KeyboardLeftShift).unwrap();
+        for c in text.chars() {
+            let (key, shifted) = u8::try_from(c)
+                .ok()
+                .and_then(KeyCode::from_ascii)
+                .with_context(|| format!("can't type {c:?}"))?;
+            let pos = position(key).with_context(|| {
+                format!("the keyboard doesn't have a key for {c:?}")
+            })?;
+            let shifted = shifted && !self.held.contains(&shift);
+            if shifted {
+                self.press(shift);
+            }
+            self.tap(pos);
+            if shifted {
+                self.release(shift);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends bytes as they are, like a noisy line would
+    pub fn noise(&mut self, bytes: &[u8]) {
+        if self.powered {
+            self.send_all(bytes);
+        }
+    }
+
+    /// Everything sent since the last time this was called
+    pub fn take_sent(&mut self) -> Vec<Sample> {
+        std::mem::take(&mut self.sent)
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.sent.push(Sample {
+            at_ms: self.now_ms,
+            byte
+        });
+    }
+
+    fn send_all(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|b| self.send(*b));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    io::{self, BufRead, Read},
+    path::PathBuf
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use palmkb_keyboard::{script, Keyboard};
+
+/// Pretends to be a Palm keyboard that's already connected, running a script
+/// of key presses. See `palmkb-keyboard/src/script.rs` for what goes in one
+#[derive(Parser, Debug)]
+#[command(name = "palmkb-keyboard", version)]
+struct Args {
+    /// Read from stdin if not given
+    script: Option<PathBuf>,
+    /// Plays the bytes out on a pty as they come instead of printing them as a
+    /// trace for `palmkb-replay`. Linux only
+    #[arg(long)]
+    pty: bool
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut keyboard = Keyboard::connected();
+    if args.pty {
+        return run_pty(&args, keyboard);
+    }
+
+    let text = match &args.script {
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?,
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    script::run(&script::parse(&text)?, &mut keyboard)?;
+    let sent = keyboard.take_sent();
+    println!("# palmkb-keyboard, {} samples", sent.len());
+    for sample in sent {
+        println!("{sample}");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn run_pty(args: &Args, mut keyboard: Keyboard) -> Result<()> {
+    let mut pty = palmkb_keyboard::pty::Pty::open()?;
+    eprintln!("keyboard on {}", pty.path().display());
+    let lines: Box<dyn Iterator<Item = io::Result<String>>> = match &args.script {
+        Some(path) => Box::new(
+            fs::read_to_string(path)
+                .with_context(|| format!("couldn't read {}", path.display()))?
+                .lines()
+                .map(|l| Ok(l.to_string()))
+                .collect::<Vec<_>>()
+                .into_iter()
+        ),
+        None => {
+            eprintln!("type commands, one per line");
+            Box::new(io::stdin().lock().lines())
+        }
+    };
+
+    let mut from_ms = keyboard.now_ms();
+    pty.play(&keyboard.take_sent(), from_ms, from_ms)?;
+    for line in lines {
+        let command = match script::Command::parse(&line?) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{e:#}");
+                continue;
+            }
+        };
+        if let Err(e) = command.run(&mut keyboard) {
+            eprintln!("{e:#}");
+        }
+        pty.play(&keyboard.take_sent(), from_ms, keyboard.now_ms())?;
+        from_ms = keyboard.now_ms();
+    }
+    if args.script.is_some() {
+        // whatever's reading the pty still needs it open
+        eprintln!("script done, Ctrl-C to quit");
+        loop {
+            std::thread::park();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_pty(_: &Args, _: Keyboard) -> Result<()> {
+    anyhow::bail!("--pty only works on Linux")
+}
@@ -0,0 +1,72 @@
+//! A pseudo terminal for the keyboard's bytes to come out of, so anything that
+//! reads a serial port can be pointed at it
+//!
+//! A pty doesn't have RTS or DCD, so whatever reads it has to make do with the
+//! bytes. The terminal is put in raw mode, they come out exactly as sent.
+
+use std::{
+    fs::File,
+    io::Write,
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant}
+};
+
+use anyhow::{Context, Result};
+use kb_driver_core::capture::Sample;
+use nix::{
+    pty::openpty,
+    sys::termios::{self, SetArg},
+    unistd::ttyname
+};
+
+pub struct Pty {
+    master: File,
+    /// Kept open so reads on the other end don't fail while nothing else has
+    /// it open
+    _slave: OwnedFd,
+    path: PathBuf
+}
+
+impl Pty {
+    pub fn open() -> Result<Self> {
+        let pty = openpty(None, None).context("couldn't open a pty")?;
+        let mut attrs = termios::tcgetattr(&pty.slave)?;
+        termios::cfmakeraw(&mut attrs);
+        termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &attrs)?;
+        let path = ttyname(&pty.slave).context("the pty doesn't have a name")?;
+        Ok(Self {
+            master: pty.master.into(),
+            _slave: pty.slave,
+            path
+        })
+    }
+
+    /// Where the other end is, like `/dev/pts/3`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes out samples sent from `from_ms` on, at the same pace the
+    /// keyboard sent them, then waits until `until_ms`
+    pub fn play(
+        &mut self,
+        samples: &[Sample],
+        from_ms: u32,
+        until_ms: u32
+    ) -> Result<()> {
+        let start = Instant::now();
+        let at =
+            |ms: u32| start + Duration::from_millis(ms.wrapping_sub(from_ms) as u64);
+        for sample in samples {
+            thread::sleep(
+                at(sample.at_ms).saturating_duration_since(Instant::now())
+            );
+            self.master.write_all(&[sample.byte])?;
+        }
+        self.master.flush()?;
+        thread::sleep(at(until_ms).saturating_duration_since(Instant::now()));
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Scripts for [`Keyboard`], one command per line
+//!
+//! ```text
+//! # comments start with #
+//! type Hello, world!   # types the rest of the line
+//! tap KeyboardEnter    # keys are KeyCode names or matrix positions
+//! press KeyboardLeftShift
+//! release KeyboardLeftShift
+//! release all
+//! wait 250             # milliseconds
+//! noise 7F FF          # raw bytes, in hex
+//! sleep                # the keyboard goes to sleep
+//! rts                  # toggles RTS, getting the handshake back
+//! power off
+//! ```
+//!
+//! `type` takes everything after the space following it as is, `#` included.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{parse_key, Keyboard};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Type(String),
+    Tap(u8),
+    Press(u8),
+    Release(u8),
+    ReleaseAll,
+    Wait(u32),
+    Noise(Vec<u8>),
+    Sleep,
+    Rts,
+    Power(bool)
+}
+
+impl Command {
+    /// Reads one line of a script, `None` for blank lines and comments
+    pub fn parse(line: &str) -> Result<Option<Self>> {
+        let line = line.trim_start();
+        if let Some(text) = line.strip_prefix("type ") {
+            return Ok(Some(Self::Type(text.to_string())));
+        }
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let (name, arg) = line
+            .split_once(char::is_whitespace)
+            .map(|(name, arg)| (name, arg.trim()))
+            .unwrap_or((line, ""));
+        Ok(Some(match (name, arg) {
+            ("tap", key) => Self::Tap(parse_key(key)?),
+            ("press", key) => Self::Press(parse_key(key)?),
+            ("release", "all") => Self::ReleaseAll,
+            ("release", key) => Self::Release(parse_key(key)?),
+            ("wait", ms) => Self::Wait(
+                ms.parse()
+                    .with_context(|| format!("`{ms}` isn't a delay"))?
+            ),
+            ("noise", bytes) => Self::Noise(
+                bytes
+                    .split_whitespace()
+                    .map(|b| {
+                        u8::from_str_radix(b, 16)
+                            .map_err(|_| anyhow!("`{b}` isn't a hex byte"))
+                    })
+                    .collect::<Result<_>>()?
+            ),
+            ("sleep", "") => Self::Sleep,
+            ("rts", "") => Self::Rts,
+            ("power", "on") => Self::Power(true),
+            ("power", "off") => Self::Power(false),
+            _ => bail!("unknown command `{line}`")
+        }))
+    }
+
+    pub fn run(&self, keyboard: &mut Keyboard) -> Result<()> {
+        match self {
+            Self::Type(text) => keyboard.type_str(text)?,
+            Self::Tap(pos) => keyboard.tap(*pos),
+            Self::Press(pos) => keyboard.press(*pos),
+            Self::Release(pos) => keyboard.release(*pos),
+            Self::ReleaseAll => keyboard.release_all(),
+            Self::Wait(ms) => keyboard.wait(*ms),
+            Self::Noise(bytes) => keyboard.noise(bytes),
+            Self::Sleep => keyboard.sleep(),
+            Self::Rts => keyboard.handshake(),
+            Self::Power(on) => keyboard.set_power(*on)
+        }
+        Ok(())
+    }
+}
+
+/// Reads a whole script
+pub fn parse(text: &str) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(command) =
+            Command::parse(line).with_context(|| format!("line {}", i + 1))?
+        {
+            commands.push(command);
+        }
+    }
+    Ok(commands)
+}
+
+/// Runs a whole script
+pub fn run(commands: &[Command], keyboard: &mut Keyboard) -> Result<()> {
+    commands.iter().try_for_each(|c| c.run(keyboard))
+}
@@ -0,0 +1,166 @@
+use kb_driver_core::{capture::Sample, config::Config, key_codes::KeyCode};
+use palmkb_keyboard::{position, script, Keyboard, HANDSHAKE, KEY_UP, TAP_MS};
+use palmkb_replay::{replay, Event};
+
+fn bytes(samples: &[Sample]) -> Vec<u8> {
+    samples.iter().map(|s| s.byte).collect()
+}
+
+fn pos(key: KeyCode) -> u8 {
+    position(key).unwrap()
+}
+
+#[test]
+fn handshakes_on_rts() {
+    let mut keyboard = Keyboard::new();
+    keyboard.set_rts(true);
+    assert!(keyboard.take_sent().is_empty(), "no power yet");
+
+    keyboard.set_power(true);
+    keyboard.set_rts(true);
+    assert!(keyboard.take_sent().is_empty(), "RTS didn't change");
+
+    keyboard.set_rts(false);
+    keyboard.set_rts(true);
+    assert_eq!(bytes(&keyboard.take_sent()), HANDSHAKE);
+    assert!(keyboard.is_awake());
+}
+
+#[test]
+fn last_release_comes_twice() {
+    let (a, b) = (pos(KeyCode::KeyboardA), pos(KeyCode::KeyboardB));
+    let mut keyboard = Keyboard::connected();
+    keyboard.take_sent();
+    keyboard.press(a);
+    keyboard.press(b);
+    keyboard.press(b);
+    keyboard.release(a);
+    keyboard.wait(10);
+    keyboard.release(b);
+    keyboard.release(b);
+    let sent = keyboard.take_sent();
+    assert_eq!(bytes(&sent), [a, b, a | KEY_UP, b | KEY_UP, b | KEY_UP]);
+    assert_eq!(sent[3].at_ms, 10);
+}
+
+#[test]
+fn wakes_up_with_dcd() {
+    let a = pos(KeyCode::KeyboardA);
+    let mut keyboard = Keyboard::connected();
+    keyboard.take_sent();
+    keyboard.sleep();
+    keyboard.tap(a);
+    assert!(keyboard.take_sent().is_empty());
+    assert!(keyboard.dcd());
+
+    keyboard.handshake();
+    assert!(!keyboard.dcd());
+    keyboard.tap(a);
+    assert_eq!(
+        bytes(&keyboard.take_sent()),
+        [0xFA, 0xFD, a, a | KEY_UP, a | KEY_UP]
+    );
+}
+
+#[test]
+fn typing_through_the_adapters_state() {
+    let mut keyboard = Keyboard::connected();
+    keyboard.type_str("Hi!").unwrap();
+    let (events, held) = replay(&keyboard.take_sent(), &Config::DEFAULT);
+    let reports: Vec<String> = events
+        .iter()
+        .filter(|(_, e)| !matches!(e, Event::Handshake))
+        .map(|(_, e)| e.to_string())
+        .collect();
+    assert_eq!(
+        reports,
+        [
+            "report LEFT_SHIFT",
+            "report LEFT_SHIFT + KeyboardH",
+            "report LEFT_SHIFT",
+            "report (nothing)",
+            "report KeyboardI",
+            "report (nothing)",
+            "report LEFT_SHIFT",
+            "report LEFT_SHIFT + Keyboard1AndExclamation",
+            "report LEFT_SHIFT",
+            "report (nothing)"
+        ]
+    );
+    assert!(held == Default::default());
+
+    assert!(keyboard.type_str("ü").is_err());
+}
+
+#[test]
+fn scripts() {
+    let commands = script::parse(
+        "# warm up\n\
+         type a#\n\
+         \n\
+         press KeyboardLeftShift  # hold it\n\
+         tap 17\n\
+         release all\n\
+         wait 100\n\
+         noise 7f FF\n"
+    )
+    .unwrap();
+    let mut keyboard = Keyboard::connected();
+    keyboard.take_sent();
+    script::run(&commands, &mut keyboard).unwrap();
+
+    let (a, shift, three) = (
+        pos(KeyCode::KeyboardA),
+        pos(KeyCode::KeyboardLeftShift),
+        pos(KeyCode::Keyboard3AndSharp)
+    );
+    let sent = keyboard.take_sent();
+    assert_eq!(
+        bytes(&sent),
+        [
+            // type a#
+            a,
+            a | KEY_UP,
+            a | KEY_UP,
+            shift,
+            three,
+            three | KEY_UP,
+            shift | KEY_UP,
+            shift | KEY_UP,
+            // press, tap, release all
+            shift,
+            17,
+            17 | KEY_UP,
+            shift | KEY_UP,
+            shift | KEY_UP,
+            0x7F,
+            0xFF
+        ]
+    );
+    assert_eq!(sent.last().unwrap().at_ms, 6 * TAP_MS + 100);
+
+    let err = script::parse("tap KeyboardA\ntap KeyboardF13\n").unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "line 2: the keyboard doesn't have KeyboardF13"
+    );
+    assert!(script::parse("tap 27").is_err(), "nothing at position 27");
+    assert!(script::parse("wiggle").is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn plays_out_on_a_pty() {
+    use std::io::Read;
+
+    let mut pty = palmkb_keyboard::pty::Pty::open().unwrap();
+    let mut other_end = std::fs::File::open(pty.path()).unwrap();
+    let mut keyboard = Keyboard::connected();
+    keyboard.tap(pos(KeyCode::KeyboardEnter));
+    let sent = keyboard.take_sent();
+    pty.play(&sent, 0, keyboard.now_ms()).unwrap();
+
+    let mut read = vec![0; sent.len()];
+    other_end.read_exact(&mut read).unwrap();
+    assert_eq!(read, bytes(&sent));
+}
@@ -0,0 +1,331 @@
+//! Keymaps as JSON or TOML files, shared by the firmware's build script,
+//! `palmkb`, `palmkb-replay` and `palmkb-uinput`
+//!
+//! A file has a list of layers, each a grid of [`ROWS`] rows of [`COLS`]
+//! actions laid out like the matrix, in the text form from [`Action`]'s
+//! `Display`. Layers missing from the end of the file are left alone. It can
+//! also have everything else `kb_driver/keymap.toml` sets up, like profiles,
+//! combos and the power policy, which only [`KeymapFile::to_config`] reads.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use kb_driver_core::{
+    combo::{Combo, MAX_COMBOS, MAX_KEYS},
+    config::Config,
+    host_os::{HostOs, HOST_OSES},
+    keymap::{self, Action, Keymap, COLS, LAYERS, ROWS},
+    power::{IdleAction, Policy},
+    profile::{ModifierSwaps, UnicodeMode, NAME_LEN, PROFILES}
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    Json,
+    Toml
+}
+
+impl Format {
+    /// Guesses the format from a file's extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None
+        }
+    }
+}
+
+/// What's in a keymap file, anything left out keeps its default
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeymapFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tapping_term_ms: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect_host: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<PowerEntry>,
+    /// Used by every profile that doesn't have its own
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<ProfileEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub combos: Vec<ComboEntry>
+}
+
+/// [`ROWS`] rows of [`COLS`] actions, laid out like the matrix
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub rows: Vec<Vec<String>>
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileEntry {
+    pub name: Option<String>,
+    /// `CTRL_GUI` and/or `ALT_GUI`
+    pub modifier_swaps: Option<Vec<String>>,
+    /// `macos`, `linux` or `wincompose`
+    pub unicode: Option<String>,
+    /// Host OSes this profile gets picked for when plugged into them
+    pub hosts: Option<Vec<String>>,
+    /// Report Fn as Apple's fn key
+    pub apple_fn: Option<bool>,
+    pub layers: Option<Vec<Layer>>
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowerEntry {
+    /// `always_on` or `battery`, the other fields change it
+    pub preset: Option<String>,
+    pub cut_vcc_on_suspend: Option<bool>,
+    pub idle_timeout_mins: Option<u16>,
+    /// `sleep` or `cut_power`
+    pub idle_action: Option<String>
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComboEntry {
+    /// `[row, col]` of every key in the combo
+    pub keys: Vec<[usize; 2]>,
+    pub action: String
+}
+
+impl KeymapFile {
+    pub fn from_keymap(keymap: &Keymap) -> Self {
+        let layers = keymap
+            .layers
+            .iter()
+            .map(|layer| Layer {
+                rows: layer
+                    .as_chunks::<COLS>()
+                    .0
+                    .iter()
+                    .map(|row| row.iter().map(Action::to_string).collect())
+                    .collect()
+            })
+            .collect();
+        Self {
+            layers,
+            ..Self::default()
+        }
+    }
+
+    /// Copies the layers in the file over `keymap`, nothing is changed if any
+    /// of them are wrong. Files with more than layers in them are turned down,
+    /// as the rest would be quietly left out
+    pub fn apply(&self, keymap: &mut Keymap) -> Result<()> {
+        let layers_only = Self {
+            layers: self.layers.clone(),
+            ..Self::default()
+        };
+        if *self != layers_only {
+            bail!("only the layers can be loaded, the rest of the file can't be");
+        }
+        let mut out = keymap.clone();
+        read_layers(&self.layers, &mut out, "")?;
+        *keymap = out;
+        Ok(())
+    }
+
+    /// Builds a whole config on top of [`Config::DEFAULT`], the way the
+    /// firmware gets its default config from `kb_driver/keymap.toml`
+    pub fn to_config(&self) -> Result<Config> {
+        let mut config = Config::DEFAULT;
+        if let Some(tapping_term_ms) = self.tapping_term_ms {
+            config.tapping_term_ms = tapping_term_ms;
+        }
+        if let Some(detect_host) = self.detect_host {
+            config.detect_host = detect_host;
+        }
+
+        for profile in &mut config.profiles {
+            read_layers(&self.layers, &mut profile.keymap, "")?;
+        }
+        if self.profiles.len() > PROFILES {
+            bail!(
+                "there are {} profiles, there can only be {PROFILES}",
+                self.profiles.len()
+            );
+        }
+        let mut claimed = [false; HOST_OSES];
+        for (p, entry) in self.profiles.iter().enumerate() {
+            for host in entry.hosts.iter().flatten() {
+                let Some(os) = HostOs::from_name(host) else {
+                    bail!(
+                        "profile {p}: `{host}` isn't a host OS, they're `macos`, \
+                         `windows`, `linux` and `ios`"
+                    );
+                };
+                if claimed[os as usize] {
+                    bail!("profile {p}: `{host}` already has a profile");
+                }
+                claimed[os as usize] = true;
+                config.host_profiles[os as usize] = p as u8;
+            }
+            let profile = &mut config.profiles[p];
+            if let Some(name) = &entry.name {
+                if !profile.set_name(name) {
+                    bail!("profile {p}: the name can only be {NAME_LEN} bytes long");
+                }
+            }
+            if let Some(swaps) = &entry.modifier_swaps {
+                profile.swaps = ModifierSwaps::empty();
+                for swap in swaps {
+                    let Some(swap) = ModifierSwaps::from_name(swap) else {
+                        bail!(
+                            "profile {p}: `{swap}` isn't a modifier swap, they're \
+                             `CTRL_GUI` and `ALT_GUI`"
+                        );
+                    };
+                    profile.swaps |= swap;
+                }
+            }
+            if let Some(unicode) = &entry.unicode {
+                profile.unicode = match unicode.as_str() {
+                    "macos" => UnicodeMode::MacOs,
+                    "linux" => UnicodeMode::Linux,
+                    "wincompose" => UnicodeMode::WinCompose,
+                    _ => bail!(
+                        "profile {p}: `{unicode}` isn't a Unicode mode, they're \
+                         `macos`, `linux` and `wincompose`"
+                    )
+                };
+            }
+            if let Some(apple_fn) = entry.apple_fn {
+                profile.apple_fn = apple_fn;
+            }
+            if let Some(layers) = &entry.layers {
+                profile.keymap = Config::DEFAULT.profiles[p].keymap.clone();
+                read_layers(layers, &mut profile.keymap, &format!("profile {p} "))?;
+            }
+        }
+
+        if self.combos.len() > MAX_COMBOS {
+            bail!(
+                "there are {} combos, there can only be {MAX_COMBOS}",
+                self.combos.len()
+            );
+        }
+        for (i, combo) in self.combos.iter().enumerate() {
+            let mut keys = Vec::new();
+            for &[row, col] in &combo.keys {
+                if !is_key(row, col) {
+                    bail!("combo {i}: there's no key at row {row} col {col}");
+                }
+                keys.push((row * COLS + col) as u8);
+            }
+            let action: Action = combo
+                .action
+                .parse()
+                .map_err(|e| anyhow!("combo {i}: `{}`: {e}", combo.action))?;
+            config.combos[i] = Combo::new(&keys, action).with_context(|| {
+                format!("combo {i} needs 2 to {MAX_KEYS} keys, all different")
+            })?;
+        }
+
+        if let Some(power) = &self.power {
+            config.power = power.to_policy()?;
+        }
+        Ok(config)
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Toml => toml::to_string(self)?
+        })
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<Self> {
+        match format {
+            Format::Json => {
+                serde_json::from_str(text).context("invalid JSON keymap")
+            }
+            Format::Toml => toml::from_str(text).context("invalid TOML keymap")
+        }
+    }
+}
+
+impl PowerEntry {
+    fn to_policy(&self) -> Result<Policy> {
+        let mut policy = match self.preset.as_deref() {
+            None | Some("always_on") => Policy::ALWAYS_ON,
+            Some("battery") => Policy::BATTERY,
+            Some(preset) => bail!(
+                "power: `{preset}` isn't a preset, they're `always_on` and \
+                 `battery`"
+            )
+        };
+        if let Some(cut_vcc_on_suspend) = self.cut_vcc_on_suspend {
+            policy.cut_vcc_on_suspend = cut_vcc_on_suspend;
+        }
+        if let Some(mins) = self.idle_timeout_mins {
+            policy.idle_timeout_mins = mins;
+        }
+        if let Some(action) = &self.idle_action {
+            policy.idle_action = match action.as_str() {
+                "sleep" => IdleAction::KeyboardSleep,
+                "cut_power" => IdleAction::CutPower,
+                _ => bail!(
+                    "power: `{action}` isn't an idle action, they're `sleep` \
+                     and `cut_power`"
+                )
+            };
+        }
+        Ok(policy)
+    }
+}
+
+/// Checks if there's a key at a position, so typos in the grid don't go
+/// unnoticed
+fn is_key(row: usize, col: usize) -> bool {
+    row < ROWS && col < COLS && keymap::is_key(row * COLS + col)
+}
+
+/// Reads layers into a keymap, errors start with `prefix`
+fn read_layers(layers: &[Layer], keymap: &mut Keymap, prefix: &str) -> Result<()> {
+    if layers.len() > LAYERS {
+        bail!(
+            "{prefix}there are {} layers, there can only be {LAYERS}",
+            layers.len()
+        );
+    }
+    for (l, layer) in layers.iter().enumerate() {
+        if layer.rows.len() != ROWS {
+            bail!(
+                "{prefix}layer {l} has {} rows instead of {ROWS}",
+                layer.rows.len()
+            );
+        }
+        for (row, actions) in layer.rows.iter().enumerate() {
+            if actions.len() != COLS {
+                bail!(
+                    "{prefix}layer {l} row {row} has {} columns instead of {COLS}",
+                    actions.len()
+                );
+            }
+            for (col, text) in actions.iter().enumerate() {
+                let action: Action = text.parse().map_err(|e| {
+                    anyhow!("{prefix}layer {l} row {row} col {col}: `{text}`: {e}")
+                })?;
+                let empty = matches!(action, Action::None | Action::Transparent);
+                if !empty && !is_key(row, col) {
+                    bail!(
+                        "{prefix}layer {l} row {row} col {col}: there's no key \
+                         there, it has to be `none` or `trans`"
+                    );
+                }
+                keymap.layers[l][row * COLS + col] = action;
+            }
+        }
+    }
+    Ok(())
+}
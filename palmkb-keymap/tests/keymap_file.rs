@@ -0,0 +1,84 @@
+use kb_driver_core::{
+    config::Config,
+    key_codes::KeyCode,
+    keymap::{Action, COLS, LAYERS, ROWS}
+};
+use palmkb_keymap::{Format, KeymapFile};
+
+#[test]
+fn round_trips_both_formats() {
+    let mut keymap = Config::DEFAULT.keymap().clone();
+    keymap.layers[2][2 * COLS + 1] = Action::Key(KeyCode::KeyboardF13);
+    let file = KeymapFile::from_keymap(&keymap);
+    for format in [Format::Json, Format::Toml] {
+        let text = file.to_string(format).unwrap();
+        let parsed = KeymapFile::parse(&text, format).unwrap();
+        assert_eq!(parsed, file);
+        let mut applied = Config::DEFAULT.keymap().clone();
+        parsed.apply(&mut applied).unwrap();
+        assert!(applied == keymap);
+    }
+}
+
+#[test]
+fn missing_layers_are_left_alone() {
+    let mut keymap = Config::DEFAULT.keymap().clone();
+    keymap.layers[LAYERS - 1][0] = Action::Key(KeyCode::KeyboardA);
+    let mut file = KeymapFile::from_keymap(Config::DEFAULT.keymap());
+    file.layers.truncate(1);
+    let expected = keymap.clone();
+    file.apply(&mut keymap).unwrap();
+    assert!(keymap == expected);
+}
+
+#[test]
+fn rejects_wrong_shapes_without_changing_anything() {
+    let keymap = Config::DEFAULT.keymap().clone();
+    let file = KeymapFile::from_keymap(&keymap);
+
+    let mut too_many = file.clone();
+    too_many.layers.push(file.layers[0].clone());
+    let mut short_row = file.clone();
+    short_row.layers[0].rows[ROWS - 1].pop();
+    let mut bad_action = file.clone();
+    bad_action.layers[0].rows[0][0] = "NotAKey".into();
+    let mut no_key = file.clone();
+    no_key.layers[0].rows[5][1] = "KeyboardA".into();
+
+    for bad in [too_many, short_row, bad_action, no_key] {
+        let mut out = keymap.clone();
+        assert!(bad.apply(&mut out).is_err());
+        assert!(out == keymap);
+    }
+}
+
+#[test]
+fn formats_from_extensions() {
+    let format = |path: &str| Format::from_path(path.as_ref());
+    assert_eq!(format("keymap.json"), Some(Format::Json));
+    assert_eq!(format("dir/keymap.toml"), Some(Format::Toml));
+    assert_eq!(format("keymap.txt"), None);
+    assert_eq!(format("keymap"), None);
+}
+
+#[test]
+fn firmware_keymap_is_the_default_config() {
+    let text = include_str!("../../kb_driver/keymap.toml");
+    let file = KeymapFile::parse(text, Format::Toml).unwrap();
+    assert!(file.to_config().unwrap() == Config::DEFAULT);
+    assert!(file.to_config().unwrap().keymap() == Config::DEFAULT.keymap());
+
+    let json = file.to_string(Format::Json).unwrap();
+    let from_json = KeymapFile::parse(&json, Format::Json).unwrap();
+    assert_eq!(from_json, file);
+    assert!(from_json.to_config().unwrap() == Config::DEFAULT);
+}
+
+#[test]
+fn only_loads_files_with_just_layers() {
+    let text = include_str!("../../kb_driver/keymap.toml");
+    let file = KeymapFile::parse(text, Format::Toml).unwrap();
+    let mut keymap = Config::DEFAULT.keymap().clone();
+    assert!(file.apply(&mut keymap).is_err());
+    assert!(KeymapFile::parse("layers = []\nlayer = []", Format::Toml).is_err());
+}
@@ -0,0 +1,168 @@
+//! Replays traces from `palmkb capture save` through the same [`State`] the
+//! firmware runs, to see what the host got sent
+//!
+//! Timing goes by the trace's timestamps, so tap-hold keys and combos come out
+//! the way they did on the adapter as long as the config matches. What the
+//! firmware does with a [`Command`] isn't replayed, it only shows up as an
+//! event.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use kb_driver_core::{
+    capture::Sample,
+    config::Config,
+    key_codes::KeyCode,
+    model::MODEL,
+    report::Report,
+    state::{Command, State}
+};
+
+/// What the keyboard sends after being powered up or poked with RTS
+pub const HANDSHAKE: [u8; 2] = MODEL.handshake;
+
+/// Something that came out of the trace
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The keyboard (re)connected
+    Handshake,
+    /// A byte that isn't a position on the matrix, which the firmware ignores
+    Garbage(u8),
+    Report(Report),
+    Command(Command)
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Handshake => f.write_str("handshake"),
+            Self::Garbage(byte) => write!(f, "ignored {byte:#04x}"),
+            Self::Report(report) => write!(f, "report {}", describe(report)),
+            Self::Command(command) => write!(f, "command {command:?}")
+        }
+    }
+}
+
+/// Names everything held down in a report, `(nothing)` if that's nothing
+pub fn describe(report: &Report) -> String {
+    let mut names: Vec<String> = report
+        .modifiers
+        .iter_names()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    if report.apple_fn {
+        names.push("fn".to_string());
+    }
+    for code in report.keycodes.iter().filter(|k| **k != 0) {
+        names.push(match KeyCode::try_from(*code) {
+            Ok(key) => key.name().to_string(),
+            Err(_) => format!("{code:#04x}")
+        });
+    }
+    match names.is_empty() {
+        true => "(nothing)".to_string(),
+        false => names.join(" + ")
+    }
+}
+
+/// Reads a trace, skipping blank lines and `#` comments
+pub fn parse(text: &str) -> Result<Vec<Sample>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            line.parse()
+                .map_err(|_| anyhow!("line {}: `{line}` isn't a sample", i + 1))
+        })
+        .collect()
+}
+
+/// Feeds samples into a [`State`] in order, keeping track of what came out
+pub struct Replay<'c> {
+    state: State,
+    config: &'c Config,
+    events: Vec<(u32, Event)>,
+    /// The first half of what could be a handshake
+    handshake: Option<Sample>
+}
+
+impl<'c> Replay<'c> {
+    pub fn new(config: &'c Config) -> Self {
+        Self {
+            state: State::new(),
+            config,
+            events: Vec::new(),
+            handshake: None
+        }
+    }
+
+    pub fn feed(&mut self, sample: Sample) {
+        self.tick_until(sample.at_ms);
+        if let Some(first) = self.handshake.take() {
+            if sample.byte == HANDSHAKE[1] {
+                self.events.push((first.at_ms, Event::Handshake));
+                return;
+            }
+            self.input(first);
+        }
+        match sample.byte {
+            byte if byte == HANDSHAKE[0] => self.handshake = Some(sample),
+            _ => self.input(sample)
+        }
+    }
+
+    /// Lets every pending tap-hold key and combo time out, then hands over
+    /// the events in order along with when they happened
+    pub fn finish(mut self) -> (Vec<(u32, Event)>, Report) {
+        if let Some(first) = self.handshake.take() {
+            self.input(first);
+        }
+        while let Some(deadline) = self.state.next_deadline() {
+            self.state.tick(deadline);
+            self.drain(deadline);
+        }
+        (self.events, self.state.report())
+    }
+
+    fn input(&mut self, sample: Sample) {
+        if KeyCode::try_from_matrix_key(sample.byte).is_none() {
+            self.events
+                .push((sample.at_ms, Event::Garbage(sample.byte)));
+            return;
+        }
+        self.state
+            .update_from_kb_input(sample.byte, self.config, sample.at_ms);
+        self.drain(sample.at_ms);
+    }
+
+    /// Does what the firmware does while it waits for the next byte
+    fn tick_until(&mut self, now_ms: u32) {
+        while let Some(deadline) = self.state.next_deadline() {
+            if (now_ms.wrapping_sub(deadline) as i32) < 0 {
+                break;
+            }
+            self.state.tick(deadline);
+            self.drain(deadline);
+        }
+    }
+
+    fn drain(&mut self, at_ms: u32) {
+        while let Some(report) = self.state.pop_report() {
+            self.events.push((at_ms, Event::Report(report)));
+        }
+        while let Some(command) = self.state.pop_command() {
+            self.events.push((at_ms, Event::Command(command)));
+        }
+    }
+}
+
+/// Replays a whole trace, returning the events and what was still held down
+/// at the end of it
+pub fn replay(samples: &[Sample], config: &Config) -> (Vec<(u32, Event)>, Report) {
+    let mut replay = Replay::new(config);
+    for sample in samples {
+        replay.feed(*sample);
+    }
+    replay.finish()
+}
@@ -0,0 +1,56 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use kb_driver_core::{config::Config, profile::PROFILES, report::Report};
+use palmkb_keymap::{Format, KeymapFile};
+
+/// Replays a trace from `palmkb capture save` and prints the HID reports the
+/// adapter would have sent for it
+#[derive(Parser, Debug)]
+#[command(name = "palmkb-replay", version)]
+struct Args {
+    trace: PathBuf,
+    /// The keymap the trace was captured with, from `palmkb keymap dump`.
+    /// The default one if not given
+    #[arg(short, long)]
+    keymap: Option<PathBuf>,
+    /// The profile that was in use
+    #[arg(short, long, default_value_t = 0)]
+    profile: u8
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(
+        (args.profile as usize) < PROFILES,
+        "there's no profile {}",
+        args.profile
+    );
+    let mut config = Config::DEFAULT;
+    config.profile = args.profile;
+    if let Some(path) = &args.keymap {
+        let format = Format::from_path(path).unwrap_or(Format::Json);
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        KeymapFile::parse(&text, format)?
+            .apply(config.keymap_mut())
+            .with_context(|| format!("in {}", path.display()))?;
+    }
+
+    let text = fs::read_to_string(&args.trace)
+        .with_context(|| format!("couldn't read {}", args.trace.display()))?;
+    let samples = palmkb_replay::parse(&text)
+        .with_context(|| format!("in {}", args.trace.display()))?;
+    let (events, held) = palmkb_replay::replay(&samples, &config);
+    for (at_ms, event) in events {
+        println!("{at_ms:>10}ms {event}");
+    }
+    if held != Report::new() {
+        println!(
+            "still held down at the end: {}",
+            palmkb_replay::describe(&held)
+        );
+    }
+    Ok(())
+}
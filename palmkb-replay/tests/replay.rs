@@ -0,0 +1,110 @@
+use kb_driver_core::{
+    capture::Sample, config::Config, key_codes::KeyCode, keymap::Action,
+    matrix::MATRIX, report::Report
+};
+use palmkb_replay::{describe, parse, replay, Event};
+
+/// Where a key is on the matrix
+fn pos(key: KeyCode) -> u8 {
+    MATRIX
+        .iter()
+        .position(|k| matches!(k, Some((k, _)) if *k == key))
+        .unwrap() as u8
+}
+
+fn trace(bytes: &[(u32, u8)]) -> Vec<Sample> {
+    bytes
+        .iter()
+        .map(|&(at_ms, byte)| Sample { at_ms, byte })
+        .collect()
+}
+
+/// The events as text, the reports don't implement `Debug`
+fn lines(events: &[(u32, Event)]) -> Vec<String> {
+    events
+        .iter()
+        .map(|(at_ms, event)| format!("{at_ms} {event}"))
+        .collect()
+}
+
+#[test]
+fn tap_after_handshake() {
+    let a = pos(KeyCode::KeyboardA);
+    let samples = trace(&[
+        (10, 0xFA),
+        (11, 0xFD),
+        (100, a),
+        (150, a | 0x80),
+        (151, a | 0x80)
+    ]);
+    let (events, held) = replay(&samples, &Config::DEFAULT);
+    assert_eq!(
+        lines(&events),
+        [
+            "10 handshake",
+            "100 report KeyboardA",
+            "150 report (nothing)"
+        ]
+    );
+    assert!(held == Report::new());
+}
+
+#[test]
+fn stuck_key_shows_up() {
+    let shift = pos(KeyCode::KeyboardLeftShift);
+    let a = pos(KeyCode::KeyboardA);
+    // shift's release got lost on the way
+    let samples = trace(&[(0, shift), (20, a), (60, a | 0x80)]);
+    let (events, held) = replay(&samples, &Config::DEFAULT);
+    assert_eq!(
+        lines(&events),
+        [
+            "0 report LEFT_SHIFT",
+            "20 report LEFT_SHIFT + KeyboardA",
+            "60 report LEFT_SHIFT"
+        ]
+    );
+    assert_eq!(describe(&held), "LEFT_SHIFT");
+}
+
+#[test]
+fn tap_hold_goes_by_the_timestamps() {
+    let mut config = Config::DEFAULT;
+    let a = pos(KeyCode::KeyboardA);
+    config.keymap_mut().layers[0][a as usize] = Action::ModTap(
+        kb_driver_core::key_codes::Modifiers::LEFT_CTRL,
+        KeyCode::KeyboardA
+    );
+
+    // held past the tapping term
+    let samples = trace(&[(0, a), (500, a | 0x80), (501, a | 0x80)]);
+    let (events, _) = replay(&samples, &config);
+    assert_eq!(
+        lines(&events),
+        ["200 report LEFT_CTRL", "500 report (nothing)"]
+    );
+
+    // nothing after the press, it still gets decided at the end
+    let (events, held) = replay(&trace(&[(0, a)]), &config);
+    assert_eq!(lines(&events), ["200 report LEFT_CTRL"]);
+    assert_eq!(describe(&held), "LEFT_CTRL");
+}
+
+#[test]
+fn garbage_is_ignored() {
+    let samples = trace(&[(0, 0xFA), (5, 0x7F), (6, 0xFD)]);
+    let (events, _) = replay(&samples, &Config::DEFAULT);
+    assert_eq!(
+        lines(&events),
+        ["0 ignored 0xfa", "5 ignored 0x7f", "6 ignored 0xfd"]
+    );
+}
+
+#[test]
+fn parses_traces() {
+    let text = "# palmkb capture, 2 samples\n\n1000 FA\n1001 FD # handshake\n";
+    assert_eq!(parse(text).unwrap(), trace(&[(1000, 0xFA), (1001, 0xFD)]));
+
+    let err = parse("1000 FA\n1001 nope\n").unwrap_err();
+    assert_eq!(err.to_string(), "line 2: `1001 nope` isn't a sample");
+}
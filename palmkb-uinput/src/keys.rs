@@ -0,0 +1,79 @@
+//! Turning HID reports into Linux key events
+
+use kb_driver_core::report::Report;
+
+/// `KEY_FN` from `linux/input-event-codes.h`, for Apple's fn key
+pub const KEY_FN: u16 = 0x1D0;
+
+/// Linux key codes for keyboard page usages, 0 for ones it doesn't have. The
+/// same table the kernel's own HID driver goes by
+#[rustfmt::skip]
+const HID_KEYBOARD: [u8; 256] = [
+      0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+     50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+      4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+     27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+     65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+    105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+     72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+    191, 192, 193, 194, 134, 138, 130, 132, 128, 129, 131, 137, 133, 135, 136, 113,
+    115, 114,   0,   0,   0, 121,   0,  89,  93, 124,  92,  94,  95,   0,   0,   0,
+    122, 123,  90,  91,  85,   0,   0,   0,   0,   0,   0,   0, 111,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0, 179, 180,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0, 111,   0,   0,   0,   0,   0,   0,   0,
+     29,  42,  56, 125,  97,  54, 100, 126, 164, 166, 165, 163, 161, 115, 114, 113,
+    150, 158, 159, 128, 136, 177, 178, 176, 142, 152, 173, 140,   0,   0,   0,   0
+];
+
+/// The Linux key code for a keyboard page usage
+pub fn code(usage: u8) -> Option<u16> {
+    match HID_KEYBOARD[usage as usize] {
+        0 => None,
+        code => Some(code as u16)
+    }
+}
+
+/// Every key code [`changes`] can come up with
+pub fn all_codes() -> impl Iterator<Item = u16> {
+    (0..=u8::MAX).filter_map(code).chain([KEY_FN])
+}
+
+/// The usages held down in a report, modifiers first
+fn usages(report: &Report) -> impl Iterator<Item = u8> + '_ {
+    (0..8)
+        .filter(|bit| report.modifiers.bits() & (1 << bit) != 0)
+        .map(|bit| 0xE0 + bit)
+        .chain(report.keycodes.iter().copied().filter(|k| *k != 0))
+}
+
+/// What has to be let go of and pressed to get from one report to the other,
+/// as `(code, down)`. Keys are let go of before modifiers and modifiers go
+/// down before keys, so a shifted key never comes out unshifted
+pub fn changes(from: &Report, to: &Report) -> Vec<(u16, bool)> {
+    let mut out = Vec::new();
+    let released: Vec<u8> = usages(from)
+        .filter(|u| !usages(to).any(|v| v == *u))
+        .collect();
+    out.extend(
+        released
+            .iter()
+            .rev()
+            .filter_map(|u| code(*u))
+            .map(|c| (c, false))
+    );
+    if from.apple_fn && !to.apple_fn {
+        out.push((KEY_FN, false));
+    }
+    if to.apple_fn && !from.apple_fn {
+        out.push((KEY_FN, true));
+    }
+    out.extend(
+        usages(to)
+            .filter(|u| !usages(from).any(|v| v == *u))
+            .filter_map(code)
+            .map(|c| (c, true))
+    );
+    out
+}
@@ -0,0 +1,274 @@
+//! The adapter's firmware, minus the adapter: talks to a Palm keyboard through
+//! a USB serial adapter and types on Linux through uinput
+//!
+//! The keyboard goes on the adapter's RX, RTS and DCD, and gets its power from
+//! DTR, which is enough for it. [`Driver`] does the same handshake as the
+//! firmware and feeds what comes after through the same [`State`] and keymap,
+//! only the reports end up as key events instead of going out over USB.
+//!
+//! Remaps and profile switches from the keyboard only last until the driver is
+//! restarted, there's nowhere to save them.
+
+use std::{
+    thread,
+    time::{Duration, Instant}
+};
+
+use anyhow::{bail, Result};
+use kb_driver_core::{
+    config::Config,
+    macros::{Output, Player},
+    model::{KeyboardModel, MODEL},
+    profile::{self, PROFILES},
+    program::Typer,
+    report::Report,
+    state::{Command, State}
+};
+use log::{debug, info, warn};
+
+pub mod keys;
+pub mod serial;
+#[cfg(target_os = "linux")]
+pub mod uinput;
+
+/// How many handshakes go unanswered before giving up
+pub const HANDSHAKE_ATTEMPTS: u32 = 5;
+/// Longest a read waits, so DCD gets checked every now and then
+const POLL: Duration = Duration::from_millis(50);
+
+#[inline]
+fn ms(ms: u16) -> Duration {
+    Duration::from_millis(ms as u64)
+}
+
+/// The wires going to the keyboard
+pub trait Line {
+    /// Waits up to `timeout` for a byte from the keyboard
+    fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>>;
+    fn set_rts(&mut self, high: bool) -> Result<()>;
+    fn set_power(&mut self, on: bool) -> Result<()>;
+    /// The keyboard raises DCD when a key is pressed while it's asleep
+    fn dcd(&mut self) -> Result<bool>;
+}
+
+/// Where key events end up
+pub trait Keys {
+    /// Presses or lets go of a Linux key code
+    fn key(&mut self, code: u16, down: bool) -> Result<()>;
+    /// Sends off everything since the last sync as one change
+    fn sync(&mut self) -> Result<()>;
+}
+
+pub struct Driver<L: Line, K: Keys> {
+    line: L,
+    keys: K,
+    config: Config,
+    state: State,
+    /// What the keys were last told is held down
+    sent: Report,
+    started: Instant,
+    last_handshake: Instant
+}
+
+impl<L: Line, K: Keys> Driver<L, K> {
+    pub fn new(line: L, keys: K, config: Config) -> Self {
+        Self {
+            line,
+            keys,
+            config,
+            state: State::new(),
+            sent: Report::new(),
+            started: Instant::now(),
+            last_handshake: Instant::now()
+        }
+    }
+
+    #[inline]
+    pub fn line_mut(&mut self) -> &mut L {
+        &mut self.line
+    }
+
+    #[inline]
+    pub fn keys(&self) -> &K {
+        &self.keys
+    }
+
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Connects and then runs until something goes wrong
+    pub fn run(&mut self) -> Result<()> {
+        self.connect()?;
+        loop {
+            self.poll()?;
+        }
+    }
+
+    /// Powers the keyboard up and handshakes with it
+    pub fn connect(&mut self) -> Result<()> {
+        self.line.set_power(false)?;
+        self.line.set_rts(false)?;
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            // toggle RTS to trigger the handshake frames
+            self.line.set_rts(false)?;
+            thread::sleep(ms(MODEL.power.rts_low_ms));
+            self.line.set_power(true)?;
+            self.line.set_rts(true)?;
+            if self.read_handshake(ms(MODEL.power.power_up_timeout_ms))? {
+                info!("keyboard handshake successful");
+                self.last_handshake = Instant::now();
+                return Ok(());
+            }
+            warn!("keyboard handshake unsuccessful");
+        }
+        bail!("the keyboard never answered, is it plugged in?")
+    }
+
+    /// Waits for one byte from the keyboard and does what it says, or does
+    /// whatever's due if nothing comes
+    pub fn poll(&mut self) -> Result<()> {
+        let timeout = match self.state.next_deadline() {
+            Some(deadline) => {
+                let left = deadline.wrapping_sub(self.now_ms()) as i32;
+                POLL.min(Duration::from_millis(left.max(0) as u64))
+            }
+            None => POLL
+        };
+        match self.line.read_byte(timeout)? {
+            Some(byte) => {
+                debug!("received {byte:08b}");
+                let now_ms = self.now_ms();
+                self.state.update_from_kb_input(byte, &self.config, now_ms);
+            }
+            None => {
+                self.state.tick(self.now_ms());
+                if self.line.dcd()? {
+                    info!("keyboard woke up");
+                    self.reconnect()?;
+                } else if self.last_handshake.elapsed()
+                    >= Duration::from_secs(MODEL.power.keep_awake_secs as u64)
+                {
+                    self.reconnect()?;
+                }
+            }
+        }
+        self.flush()
+    }
+
+    /// Handshakes again with a keyboard that's already powered
+    fn reconnect(&mut self) -> Result<()> {
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            self.line.set_rts(false)?;
+            // gotta have this here or kb just will not notice the toggle
+            thread::sleep(ms(MODEL.power.rts_low_ms));
+            self.line.set_rts(true)?;
+            if self.read_handshake(ms(MODEL.power.handshake_timeout_ms))? {
+                debug!("keyboard handshake successful");
+                self.last_handshake = Instant::now();
+                return Ok(());
+            }
+            warn!("keyboard handshake unsuccessful");
+        }
+        // don't leave anything stuck down on the way out
+        self.state.reset();
+        self.flush()?;
+        bail!("lost the keyboard")
+    }
+
+    fn read_handshake(&mut self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 2];
+        for byte in &mut buf {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.line.read_byte(left)? {
+                Some(b) => *byte = b,
+                None => return Ok(false)
+            }
+        }
+        debug!("received initial buf: {buf:02X?}");
+        if buf == MODEL.handshake {
+            return Ok(true);
+        }
+        match KeyboardModel::detect(buf) {
+            Some(model) => warn!("that's a {}, not a {}", model.name, MODEL.name),
+            None => warn!("unknown handshake {buf:02X?}")
+        }
+        Ok(false)
+    }
+
+    /// Milliseconds since the driver started, as used by [`State`]
+    fn now_ms(&self) -> u32 {
+        self.started.elapsed().as_millis() as u32
+    }
+
+    /// Sends out everything the state queued up and does what it asked for
+    fn flush(&mut self) -> Result<()> {
+        while let Some(report) = self.state.pop_report() {
+            self.send(report)?;
+        }
+        while let Some(command) = self.state.pop_command() {
+            match command {
+                Command::PlayMacro(index) => {
+                    debug!("playing macro {index}");
+                    let held = self.state.report();
+                    let mut player = Player::new(index, held);
+                    while let Some(output) = player.next(&self.config.macros) {
+                        match output {
+                            Output::Report(report) => self.send(report)?,
+                            Output::Delay(ms) => {
+                                thread::sleep(Duration::from_millis(ms as u64))
+                            }
+                        }
+                    }
+                    self.send(held)?;
+                }
+                Command::FactoryReset => {
+                    info!("back to the default config until restarted");
+                    self.config = Config::DEFAULT;
+                }
+                Command::Bootloader => {
+                    warn!("there's no bootloader to reboot into")
+                }
+                Command::Type(message) => {
+                    for report in Typer::new(message) {
+                        self.send(report)?;
+                    }
+                }
+                Command::Remap { layer, pos, action } => {
+                    info!("remapping {pos} on layer {layer}");
+                    self.config.keymap_mut().set(
+                        layer as usize,
+                        pos as usize,
+                        action
+                    );
+                }
+                Command::SwitchProfile(index) if (index as usize) < PROFILES => {
+                    info!("switching to profile {index}");
+                    self.config.profile = index;
+                }
+                Command::SwitchProfile(index) => warn!("there's no profile {index}"),
+                Command::Unicode(code_point) => {
+                    let mode = self.config.active_profile().unicode;
+                    for report in profile::unicode_reports(mode, code_point) {
+                        self.send(report)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, report: Report) -> Result<()> {
+        let changes = keys::changes(&self.sent, &report);
+        self.sent = report;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        for (code, down) in changes {
+            self.keys.key(code, down)?;
+        }
+        self.keys.sync()
+    }
+}
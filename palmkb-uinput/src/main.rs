@@ -0,0 +1,58 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use kb_driver_core::{config::Config, profile::PROFILES};
+use palmkb_keymap::{Format, KeymapFile};
+
+/// Runs a Palm keyboard hooked up to a USB serial adapter as a Linux keyboard,
+/// no adapter board needed
+#[derive(Parser, Debug)]
+#[command(name = "palmkb-uinput", version)]
+struct Args {
+    /// Like `/dev/ttyUSB0`
+    port: String,
+    /// A keymap from `palmkb keymap dump`, the default one if not given
+    #[arg(short, long)]
+    keymap: Option<PathBuf>,
+    /// The profile to use
+    #[arg(short, long, default_value_t = 0)]
+    profile: u8
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info")
+    )
+    .init();
+    let args = Args::parse();
+    anyhow::ensure!(
+        (args.profile as usize) < PROFILES,
+        "there's no profile {}",
+        args.profile
+    );
+    let mut config = Config::DEFAULT;
+    config.profile = args.profile;
+    if let Some(path) = &args.keymap {
+        let format = Format::from_path(path).unwrap_or(Format::Json);
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        KeymapFile::parse(&text, format)?
+            .apply(config.keymap_mut())
+            .with_context(|| format!("in {}", path.display()))?;
+    }
+    run(&args, config)
+}
+
+#[cfg(target_os = "linux")]
+fn run(args: &Args, config: Config) -> Result<()> {
+    use palmkb_uinput::{serial::Serial, uinput::Uinput, Driver};
+
+    let line = Serial::open(&args.port)?;
+    Driver::new(line, Uinput::new()?, config).run()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run(_: &Args, _: Config) -> Result<()> {
+    anyhow::bail!("uinput is Linux only")
+}
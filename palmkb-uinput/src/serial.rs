@@ -0,0 +1,73 @@
+//! A [`Line`] over a serial port, set up the way the keyboard wants
+
+use std::{
+    io::{ErrorKind, Read},
+    time::Duration
+};
+
+use anyhow::{bail, Context, Result};
+use kb_driver_core::model::{self, MODEL};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::Line;
+
+pub struct Serial {
+    port: Box<dyn SerialPort>
+}
+
+impl Serial {
+    /// Opens a port like `/dev/ttyUSB0`
+    pub fn open(path: &str) -> Result<Self> {
+        let line = MODEL.line;
+        let data_bits = match line.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            bits => bail!("serial ports can't do {bits} data bits")
+        };
+        let parity = match line.parity {
+            model::Parity::None => Parity::None,
+            model::Parity::Even => Parity::Even,
+            model::Parity::Odd => Parity::Odd
+        };
+        let stop_bits = match line.stop_bits {
+            2 => StopBits::Two,
+            _ => StopBits::One
+        };
+        let port = serialport::new(path, line.baud)
+            .data_bits(data_bits)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .flow_control(FlowControl::None)
+            .open()
+            .with_context(|| format!("couldn't open {path}"))?;
+        Ok(Self { port })
+    }
+}
+
+impl Line for Serial {
+    fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>> {
+        self.port.set_timeout(timeout)?;
+        let mut buf = [0u8; 1];
+        match self.port.read(&mut buf) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(buf[0])),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn set_rts(&mut self, high: bool) -> Result<()> {
+        Ok(self.port.write_request_to_send(high)?)
+    }
+
+    /// The keyboard runs off DTR
+    fn set_power(&mut self, on: bool) -> Result<()> {
+        Ok(self.port.write_data_terminal_ready(on)?)
+    }
+
+    fn dcd(&mut self) -> Result<bool> {
+        Ok(self.port.read_carrier_detect()?)
+    }
+}
@@ -0,0 +1,39 @@
+//! [`Keys`] as a virtual keyboard, needs write access to `/dev/uinput`
+
+use anyhow::{Context, Result};
+use evdev::{uinput::VirtualDevice, AttributeSet, InputEvent, KeyCode, KeyEvent};
+
+use crate::{keys, Keys};
+
+pub struct Uinput {
+    device: VirtualDevice,
+    pending: Vec<InputEvent>
+}
+
+impl Uinput {
+    pub fn new() -> Result<Self> {
+        let codes: AttributeSet<KeyCode> = keys::all_codes().map(KeyCode).collect();
+        let device = VirtualDevice::builder()
+            .and_then(|b| b.name("Palm keyboard").with_keys(&codes))
+            .and_then(|b| b.build())
+            .context("couldn't make a uinput device, is /dev/uinput writable?")?;
+        Ok(Self {
+            device,
+            pending: Vec::new()
+        })
+    }
+}
+
+impl Keys for Uinput {
+    fn key(&mut self, code: u16, down: bool) -> Result<()> {
+        self.pending
+            .push(*KeyEvent::new(KeyCode(code), down as i32));
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.device.emit(&self.pending)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
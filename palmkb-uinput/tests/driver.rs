@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use kb_driver_core::{
+    config::Config,
+    key_codes::{KeyCode, Modifiers},
+    report::Report
+};
+use palmkb_uinput::{keys, Driver, Keys, Line};
+
+// from linux/input-event-codes.h
+const KEY_H: u16 = 35;
+const KEY_I: u16 = 23;
+const KEY_A: u16 = 30;
+const KEY_LEFTSHIFT: u16 = 42;
+
+/// Stands in for uinput
+#[derive(Default)]
+struct Recorded {
+    events: Vec<(u16, bool)>,
+    syncs: usize
+}
+
+impl Keys for Recorded {
+    fn key(&mut self, code: u16, down: bool) -> Result<()> {
+        self.events.push((code, down));
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.syncs += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn reports_to_key_events() {
+    let mut shifted = Report::new();
+    shifted.press(KeyCode::KeyboardLeftShift);
+    shifted.press(KeyCode::KeyboardH);
+    assert_eq!(
+        keys::changes(&Report::new(), &shifted),
+        [(KEY_LEFTSHIFT, true), (KEY_H, true)]
+    );
+    assert_eq!(
+        keys::changes(&shifted, &Report::new()),
+        [(KEY_H, false), (KEY_LEFTSHIFT, false)]
+    );
+
+    let mut next = shifted;
+    next.release(KeyCode::KeyboardH);
+    next.press(KeyCode::KeyboardI);
+    next.apple_fn = true;
+    assert_eq!(
+        keys::changes(&shifted, &next),
+        [(KEY_H, false), (keys::KEY_FN, true), (KEY_I, true)]
+    );
+    assert!(keys::changes(&next, &next).is_empty());
+
+    let all = Report {
+        modifiers: Modifiers::all(),
+        ..Report::new()
+    };
+    let codes: Vec<u16> = keys::changes(&Report::new(), &all)
+        .into_iter()
+        .map(|(code, _)| code)
+        .collect();
+    assert_eq!(codes, [29, 42, 56, 125, 97, 54, 100, 126]);
+}
+
+/// Nothing on the other end
+struct Unplugged;
+
+impl Line for Unplugged {
+    fn read_byte(&mut self, _: Duration) -> Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn set_rts(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_power(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn dcd(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[test]
+fn gives_up_without_a_keyboard() {
+    let mut driver = Driver::new(Unplugged, Recorded::default(), Config::DEFAULT);
+    assert!(driver.connect().is_err());
+}
+
+#[cfg(target_os = "linux")]
+mod pty {
+    use palmkb_keyboard::{position, pty::Pty, Keyboard};
+    use palmkb_uinput::serial::Serial;
+
+    use super::*;
+
+    /// The pretend keyboard, with its bytes going through a pty and the
+    /// serial code. A pty doesn't have the control lines, so those go to the
+    /// keyboard directly
+    struct Wired {
+        serial: Serial,
+        pty: Pty,
+        keyboard: Keyboard
+    }
+
+    impl Wired {
+        fn new() -> Self {
+            let pty = Pty::open().unwrap();
+            let serial = Serial::open(pty.path().to_str().unwrap()).unwrap();
+            Self {
+                serial,
+                pty,
+                keyboard: Keyboard::new()
+            }
+        }
+
+        /// Plays out what the keyboard sent since last time
+        fn flush(&mut self) {
+            let sent = self.keyboard.take_sent();
+            if let (Some(first), Some(last)) = (sent.first(), sent.last()) {
+                self.pty.play(&sent, first.at_ms, last.at_ms).unwrap();
+            }
+        }
+    }
+
+    impl Line for Wired {
+        fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>> {
+            self.serial.read_byte(timeout)
+        }
+
+        fn set_rts(&mut self, high: bool) -> Result<()> {
+            self.keyboard.set_rts(high);
+            self.flush();
+            Ok(())
+        }
+
+        fn set_power(&mut self, on: bool) -> Result<()> {
+            self.keyboard.set_power(on);
+            Ok(())
+        }
+
+        fn dcd(&mut self) -> Result<bool> {
+            Ok(self.keyboard.dcd())
+        }
+    }
+
+    fn poll_until_quiet(driver: &mut Driver<Wired, Recorded>) {
+        let mut quiet = 0;
+        while quiet < 3 {
+            let before = driver.keys().events.len();
+            driver.poll().unwrap();
+            match driver.keys().events.len() == before {
+                true => quiet += 1,
+                false => quiet = 0
+            }
+        }
+    }
+
+    #[test]
+    fn types_through_a_pty() {
+        let mut driver =
+            Driver::new(Wired::new(), Recorded::default(), Config::DEFAULT);
+        driver.connect().unwrap();
+        assert!(driver.line_mut().keyboard.is_awake());
+
+        driver.line_mut().keyboard.type_str("hI").unwrap();
+        driver.line_mut().flush();
+        poll_until_quiet(&mut driver);
+        assert_eq!(
+            driver.keys().events,
+            [
+                (KEY_H, true),
+                (KEY_H, false),
+                (KEY_LEFTSHIFT, true),
+                (KEY_I, true),
+                (KEY_I, false),
+                (KEY_LEFTSHIFT, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn handshakes_again_when_woken_up() {
+        let a = position(KeyCode::KeyboardA).unwrap();
+        let mut driver =
+            Driver::new(Wired::new(), Recorded::default(), Config::DEFAULT);
+        driver.connect().unwrap();
+
+        let keyboard = &mut driver.line_mut().keyboard;
+        keyboard.sleep();
+        keyboard.tap(a);
+        assert!(keyboard.dcd());
+        driver.poll().unwrap();
+        let keyboard = &mut driver.line_mut().keyboard;
+        assert!(keyboard.is_awake());
+        assert!(!keyboard.dcd());
+        assert!(
+            driver.keys().events.is_empty(),
+            "the press that woke it up is gone"
+        );
+
+        driver.line_mut().keyboard.tap(a);
+        driver.line_mut().flush();
+        poll_until_quiet(&mut driver);
+        assert_eq!(driver.keys().events, [(KEY_A, true), (KEY_A, false)]);
+        assert_eq!(driver.keys().syncs, 2);
+    }
+}